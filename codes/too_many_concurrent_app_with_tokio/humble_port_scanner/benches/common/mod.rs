@@ -0,0 +1,75 @@
+use std::net::SocketAddr;
+
+use ipnet::Ipv4Net;
+use tokio::{net::TcpListener, runtime::Runtime};
+
+use humble_port_scanner::models::SubnetScanConfiguration;
+
+/// Keeps one accepting listener per host of a loopback subnet alive for the
+/// whole benchmark, every listener on the same port so a scan of the subnet
+/// hits an open port on each probe.
+pub struct ListenerFarm {
+    _runtime: Runtime,
+    subnet: Ipv4Net,
+    pub port: u16,
+    pub hosts: usize,
+}
+
+impl ListenerFarm {
+    pub fn start(subnet: &str) -> Self {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .thread_name("listener_farm")
+            .enable_io()
+            .build()
+            .expect("Failed to build the listener farm runtime.");
+
+        let subnet = subnet.parse::<Ipv4Net>().unwrap();
+
+        let (port, hosts) = runtime.block_on(async {
+            let mut hosts = subnet.hosts();
+            let first_listener = TcpListener::bind((hosts.next().unwrap(), 0))
+                .await
+                .expect("Unable to bind the first listener of the farm");
+            let port = first_listener
+                .local_addr()
+                .unwrap()
+                .port();
+
+            let mut listeners = vec![first_listener];
+            for host in hosts {
+                let listener = TcpListener::bind(SocketAddr::from((host, port)))
+                    .await
+                    .unwrap_or_else(|_| panic!("Unable to bind {}:{} for the farm", host, port));
+                listeners.push(listener);
+            }
+
+            let hosts = listeners.len();
+            for listener in listeners {
+                tokio::spawn(async move {
+                    while let Ok((socket, _)) = listener.accept().await {
+                        drop(socket);
+                    }
+                });
+            }
+
+            (port, hosts)
+        });
+
+        Self {
+            _runtime: runtime,
+            subnet,
+            port,
+            hosts,
+        }
+    }
+
+    /// The farm subnet split into `split_prefix` subnets, each scanned by its own task.
+    pub fn scan_configurations(&self, split_prefix: u8) -> Vec<SubnetScanConfiguration> {
+        self.subnet
+            .subnets(split_prefix)
+            .unwrap()
+            .map(|subnet| SubnetScanConfiguration::new(subnet, self.port, self.port + 1))
+            .collect()
+    }
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use humble_port_scanner::{
+    app::SubnetScannerApp,
+    models::{RuntimeConfiguration, RuntimeFlavor},
+};
+
+use common::ListenerFarm;
+
+// every host of 127.77.0.0/24 listens on the same port so a scan of the
+// whole /24 hits an open port on each probe.
+const FARM_SUBNET: &str = "127.77.0.0/24";
+const FARM_SPLIT_PREFIX: u8 = 26;
+
+fn runtime_configurations() -> Vec<(String, RuntimeConfiguration)> {
+    let mut configurations = vec![(
+        String::from("current_thread"),
+        RuntimeConfiguration {
+            flavor: RuntimeFlavor::CurrentThread,
+            ..RuntimeConfiguration::default()
+        },
+    )];
+
+    for worker_threads in [1, 2, 4, 8] {
+        configurations.push((
+            format!("multi_thread_{}_workers", worker_threads),
+            RuntimeConfiguration {
+                worker_threads,
+                ..RuntimeConfiguration::default()
+            },
+        ));
+    }
+
+    configurations.push((
+        String::from("multi_thread_4_workers_tight_intervals"),
+        RuntimeConfiguration {
+            event_interval: Some(8),
+            global_queue_interval: Some(8),
+            ..RuntimeConfiguration::default()
+        },
+    ));
+
+    configurations
+}
+
+fn scan_throughput_per_runtime(c: &mut Criterion) {
+    let farm = ListenerFarm::start(FARM_SUBNET);
+    let scan_configurations = farm.scan_configurations(FARM_SPLIT_PREFIX);
+
+    let mut group = c.benchmark_group("scan_throughput_per_runtime");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(farm.hosts as u64));
+
+    for (name, runtime_config) in runtime_configurations() {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(name),
+            &runtime_config,
+            |b, runtime_config| {
+                b.iter_custom(|iters| {
+                    let mut elapsed = Duration::ZERO;
+                    for _ in 0..iters {
+                        let mut app = SubnetScannerApp::builder()
+                            .set_configs(scan_configurations.clone())
+                            .set_scan_timeout(Duration::from_secs(1))
+                            .set_runtime_config(runtime_config.clone())
+                            .build()
+                            .unwrap();
+
+                        let start = Instant::now();
+                        app.start_subnet_scans();
+                        app.run();
+                        elapsed += start.elapsed();
+                    }
+                    elapsed
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, scan_throughput_per_runtime);
+criterion_main!(benches);
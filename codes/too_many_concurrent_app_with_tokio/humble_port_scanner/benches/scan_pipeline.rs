@@ -0,0 +1,509 @@
+mod common;
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    collections::HashMap,
+    net::Ipv4Addr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use criterion::{
+    criterion_group, criterion_main,
+    measurement::{Measurement, ValueFormatter},
+    BenchmarkId, Criterion, Throughput,
+};
+use futures::future::BoxFuture;
+use ipnet::Ipv4Net;
+use tokio::{runtime::Runtime, sync::mpsc};
+
+use humble_port_scanner::{
+    app::SubnetScannerApp,
+    connector::{Connector, NetworkConnector},
+    models::{
+        IpPortScanResult, PortState, ProbeRoute, RuntimeConfiguration, RuntimeFlavor, ScanTarget,
+        SourceBinding, SubnetScanConfiguration,
+    },
+    simulated_network::{SimulatedLink, SimulatedNetwork},
+    tokio_helpers,
+};
+
+use common::ListenerFarm;
+
+// 4094 simulated hosts * 4 ports, one of which answers open on every host.
+const SIMULATED_SUBNET: &str = "10.96.0.0/20";
+const SIMULATED_BEGIN_PORT: u16 = 8000;
+const SIMULATED_END_PORT: u16 = 8004;
+const SIMULATED_OPEN_PORT: u16 = 8001;
+// splits of the simulated subnet, one scan task per split.
+const SIMULATED_SPLIT_PREFIXES: [u8; 4] = [20, 22, 24, 26];
+const CHANNEL_CAPACITIES: [usize; 3] = [1, 64, 1024];
+
+// a thousand loopback listeners, the probes go through the kernel for real.
+const FARM_SUBNET: &str = "127.78.0.0/22";
+const FARM_SPLIT_PREFIXES: [u8; 2] = [24, 26];
+
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// The system allocator, keeping count of the bytes in use and their peak.
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            track_allocation(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            ALLOCATED_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+            track_allocation(new_size);
+        }
+        new_ptr
+    }
+}
+
+fn track_allocation(size: usize) {
+    let allocated = ALLOCATED_BYTES.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK_ALLOCATED_BYTES.fetch_max(allocated, Ordering::Relaxed);
+}
+
+#[global_allocator]
+static GLOBAL_ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Heap growth of the measured routine at its peak, in bytes.
+struct PeakAllocatedBytes;
+
+impl Measurement for PeakAllocatedBytes {
+    type Intermediate = usize;
+    type Value = usize;
+
+    fn start(&self) -> Self::Intermediate {
+        let allocated = ALLOCATED_BYTES.load(Ordering::Relaxed);
+        PEAK_ALLOCATED_BYTES.store(allocated, Ordering::Relaxed);
+        allocated
+    }
+
+    fn end(&self, baseline: Self::Intermediate) -> Self::Value {
+        PEAK_ALLOCATED_BYTES
+            .load(Ordering::Relaxed)
+            .saturating_sub(baseline)
+    }
+
+    fn add(&self, v1: &Self::Value, v2: &Self::Value) -> Self::Value {
+        v1 + v2
+    }
+
+    fn zero(&self) -> Self::Value {
+        0
+    }
+
+    fn to_f64(&self, value: &Self::Value) -> f64 {
+        *value as f64
+    }
+
+    fn formatter(&self) -> &dyn ValueFormatter {
+        &BytesFormatter
+    }
+}
+
+struct BytesFormatter;
+
+impl ValueFormatter for BytesFormatter {
+    fn scale_values(&self, typical_value: f64, values: &mut [f64]) -> &'static str {
+        let (divisor, unit) = match typical_value {
+            bytes if bytes < 1024.0 => (1.0, "B"),
+            bytes if bytes < 1024.0 * 1024.0 => (1024.0, "KiB"),
+            _ => (1024.0 * 1024.0, "MiB"),
+        };
+        for value in values {
+            *value /= divisor;
+        }
+        unit
+    }
+
+    // bytes per probe rather than a rate, memory does not flow over time.
+    fn scale_throughputs(
+        &self,
+        _typical_value: f64,
+        throughput: &Throughput,
+        values: &mut [f64],
+    ) -> &'static str {
+        if let Throughput::Elements(probes) = throughput {
+            for value in values {
+                *value /= *probes as f64;
+            }
+        }
+        "B/probe"
+    }
+
+    fn scale_for_machines(&self, _values: &mut [f64]) -> &'static str {
+        "B"
+    }
+}
+
+/// Stamps the moment every probe of the wrapped network completes, to time
+/// how long its result takes to go through the scanner.
+struct StampingConnector {
+    network: SimulatedNetwork,
+    completed: Mutex<HashMap<(Ipv4Addr, u16), Instant>>,
+}
+
+impl Connector for StampingConnector {
+    fn connect<'a>(
+        &'a self,
+        route: ProbeRoute,
+        source: &'a SourceBinding,
+        ip: Ipv4Addr,
+        port: u16,
+    ) -> BoxFuture<'a, anyhow::Result<PortState>> {
+        Box::pin(async move {
+            let state = self
+                .network
+                .connect(route, source, ip, port)
+                .await;
+            self.completed
+                .lock()
+                .unwrap()
+                .insert((ip, port), Instant::now());
+            state
+        })
+    }
+}
+
+fn simulated_network() -> SimulatedNetwork {
+    SimulatedNetwork::default().with_port(
+        SIMULATED_OPEN_PORT,
+        SimulatedLink::accepting(Duration::ZERO),
+    )
+}
+
+fn simulated_scan_configurations(split_prefix: u8) -> Vec<SubnetScanConfiguration> {
+    SIMULATED_SUBNET
+        .parse::<Ipv4Net>()
+        .unwrap()
+        .subnets(split_prefix)
+        .unwrap()
+        .map(|subnet| {
+            SubnetScanConfiguration::new(subnet, SIMULATED_BEGIN_PORT, SIMULATED_END_PORT)
+        })
+        .collect()
+}
+
+fn probe_count(scan_configurations: &[SubnetScanConfiguration]) -> u64 {
+    scan_configurations
+        .iter()
+        .map(SubnetScanConfiguration::probe_count)
+        .sum()
+}
+
+fn runtimes() -> Vec<(&'static str, Arc<Runtime>)> {
+    [
+        (
+            "current_thread",
+            RuntimeConfiguration {
+                flavor: RuntimeFlavor::CurrentThread,
+                ..RuntimeConfiguration::default()
+            },
+        ),
+        (
+            "multi_thread_4_workers",
+            RuntimeConfiguration {
+                worker_threads: 4,
+                ..RuntimeConfiguration::default()
+            },
+        ),
+    ]
+    .into_iter()
+    .map(|(name, runtime_config)| {
+        let runtime = tokio_helpers::setup_tokio_runtime(&runtime_config)
+            .expect("Failed to build the benchmark runtime.");
+        (name, Arc::new(runtime))
+    })
+    .collect()
+}
+
+fn scan_app(
+    runtime: &Arc<Runtime>,
+    scan_configurations: &[SubnetScanConfiguration],
+    channel_capacity: usize,
+    connector: Arc<dyn Connector>,
+) -> SubnetScannerApp {
+    let mut app = SubnetScannerApp::builder()
+        .set_configs(scan_configurations.to_vec())
+        .set_scan_timeout(Duration::from_secs(1))
+        .set_channel_capacity(channel_capacity)
+        .set_runtime(runtime)
+        .set_show_progress(false)
+        .set_connector(connector)
+        .build()
+        .unwrap();
+    app.start_subnet_scans();
+    app
+}
+
+// the scanner's own overhead: probes answer at once, so every probe spends
+// its time in the scan tasks, the result channels, the streamer and the
+// progress tracker.
+fn simulated_scan_throughput(c: &mut Criterion) {
+    let network: Arc<dyn Connector> = Arc::new(simulated_network());
+
+    let mut group = c.benchmark_group("simulated_scan_throughput");
+    group.sample_size(10);
+
+    for (runtime_name, runtime) in runtimes() {
+        for split_prefix in SIMULATED_SPLIT_PREFIXES {
+            let scan_configurations = simulated_scan_configurations(split_prefix);
+            group.throughput(Throughput::Elements(probe_count(&scan_configurations)));
+
+            for channel_capacity in CHANNEL_CAPACITIES {
+                group.bench_function(
+                    BenchmarkId::new(
+                        runtime_name,
+                        format!(
+                            "{}_tasks_capacity_{}",
+                            scan_configurations.len(),
+                            channel_capacity
+                        ),
+                    ),
+                    |b| {
+                        b.iter_custom(|iters| {
+                            let mut elapsed = Duration::ZERO;
+                            for _ in 0..iters {
+                                let app = scan_app(
+                                    &runtime,
+                                    &scan_configurations,
+                                    channel_capacity,
+                                    network.clone(),
+                                );
+
+                                let start = Instant::now();
+                                app.run();
+                                elapsed += start.elapsed();
+                            }
+                            elapsed
+                        })
+                    },
+                );
+            }
+        }
+    }
+
+    group.finish();
+}
+
+// mean time from a probe completing to its result reaching the result
+// observer, the last stop of the pipeline.
+fn result_latency(c: &mut Criterion) {
+    let mut group = c.benchmark_group("result_latency");
+    group.sample_size(10);
+
+    for (runtime_name, runtime) in runtimes() {
+        for split_prefix in [22, 26] {
+            let scan_configurations = simulated_scan_configurations(split_prefix);
+
+            for channel_capacity in CHANNEL_CAPACITIES {
+                group.bench_function(
+                    BenchmarkId::new(
+                        runtime_name,
+                        format!(
+                            "{}_tasks_capacity_{}",
+                            scan_configurations.len(),
+                            channel_capacity
+                        ),
+                    ),
+                    |b| {
+                        b.iter_custom(|iters| {
+                            let mut latency = Duration::ZERO;
+                            for _ in 0..iters {
+                                latency += mean_result_latency(
+                                    &runtime,
+                                    &scan_configurations,
+                                    channel_capacity,
+                                );
+                            }
+                            latency
+                        })
+                    },
+                );
+            }
+        }
+    }
+
+    group.finish();
+}
+
+fn mean_result_latency(
+    runtime: &Arc<Runtime>,
+    scan_configurations: &[SubnetScanConfiguration],
+    channel_capacity: usize,
+) -> Duration {
+    let connector = Arc::new(StampingConnector {
+        network: simulated_network(),
+        completed: Mutex::new(HashMap::new()),
+    });
+    let (observer_tx, mut observer_rx) =
+        mpsc::unbounded_channel::<(ScanTarget, IpPortScanResult)>();
+    // receives on its own thread so the results are stamped as they arrive.
+    let observer = std::thread::spawn(move || {
+        let mut received = Vec::new();
+        while let Some((_, scan_result)) = observer_rx.blocking_recv() {
+            received.push(((scan_result.ip, scan_result.port), Instant::now()));
+        }
+        received
+    });
+
+    let mut app = SubnetScannerApp::builder()
+        .set_configs(scan_configurations.to_vec())
+        .set_channel_capacity(channel_capacity)
+        .set_runtime(runtime)
+        .set_show_progress(false)
+        .set_connector(connector.clone())
+        .set_result_observer(observer_tx)
+        .build()
+        .unwrap();
+    app.start_subnet_scans();
+    app.run();
+
+    let received = observer
+        .join()
+        .expect("The result observer panicked");
+    let completed = connector.completed.lock().unwrap();
+    // a result the connector never stamped, e.g. of a probe that timed out
+    // before its connection completed, has no latency to measure.
+    let latencies: Vec<Duration> = received
+        .iter()
+        .filter_map(|(probe, received_at)| {
+            completed
+                .get(probe)
+                .map(|completed_at| received_at.saturating_duration_since(*completed_at))
+        })
+        .collect();
+    if latencies.is_empty() {
+        return Duration::ZERO;
+    }
+    latencies.iter().sum::<Duration>() / latencies.len() as u32
+}
+
+// the same pipeline fed by real connections to a thousand loopback listeners.
+fn listener_farm_throughput(c: &mut Criterion) {
+    let farm = ListenerFarm::start(FARM_SUBNET);
+    let network: Arc<dyn Connector> = Arc::new(NetworkConnector);
+
+    let mut group = c.benchmark_group("listener_farm_throughput");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(farm.hosts as u64));
+
+    for (runtime_name, runtime) in runtimes() {
+        for split_prefix in FARM_SPLIT_PREFIXES {
+            let scan_configurations = farm.scan_configurations(split_prefix);
+
+            for channel_capacity in [64, 1024] {
+                group.bench_function(
+                    BenchmarkId::new(
+                        runtime_name,
+                        format!(
+                            "{}_tasks_capacity_{}",
+                            scan_configurations.len(),
+                            channel_capacity
+                        ),
+                    ),
+                    |b| {
+                        b.iter_custom(|iters| {
+                            let mut elapsed = Duration::ZERO;
+                            for _ in 0..iters {
+                                let app = scan_app(
+                                    &runtime,
+                                    &scan_configurations,
+                                    channel_capacity,
+                                    network.clone(),
+                                );
+
+                                let start = Instant::now();
+                                app.run();
+                                elapsed += start.elapsed();
+                            }
+                            elapsed
+                        })
+                    },
+                );
+            }
+        }
+    }
+
+    group.finish();
+}
+
+// heap growth of a whole simulated scan, from building the app to its report,
+// the queued results grow with the channel capacity.
+fn scan_peak_memory(c: &mut Criterion<PeakAllocatedBytes>) {
+    let network: Arc<dyn Connector> = Arc::new(simulated_network());
+
+    let mut group = c.benchmark_group("scan_peak_memory");
+    group.sample_size(10);
+
+    for (runtime_name, runtime) in runtimes() {
+        for split_prefix in SIMULATED_SPLIT_PREFIXES {
+            let scan_configurations = simulated_scan_configurations(split_prefix);
+            group.throughput(Throughput::Elements(probe_count(&scan_configurations)));
+
+            for channel_capacity in [1, 1024, 16384] {
+                group.bench_function(
+                    BenchmarkId::new(
+                        runtime_name,
+                        format!(
+                            "{}_tasks_capacity_{}",
+                            scan_configurations.len(),
+                            channel_capacity
+                        ),
+                    ),
+                    |b| {
+                        b.iter_custom(|iters| {
+                            let mut peak_bytes = 0;
+                            for _ in 0..iters {
+                                let baseline = PeakAllocatedBytes.start();
+                                scan_app(
+                                    &runtime,
+                                    &scan_configurations,
+                                    channel_capacity,
+                                    network.clone(),
+                                )
+                                .run();
+                                peak_bytes += PeakAllocatedBytes.end(baseline);
+                            }
+                            peak_bytes
+                        })
+                    },
+                );
+            }
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    throughput,
+    simulated_scan_throughput,
+    result_latency,
+    listener_farm_throughput
+);
+criterion_group! {
+    name = memory;
+    config = Criterion::default().with_measurement(PeakAllocatedBytes);
+    targets = scan_peak_memory
+}
+criterion_main!(throughput, memory);
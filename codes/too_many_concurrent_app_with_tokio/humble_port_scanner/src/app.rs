@@ -1,32 +1,85 @@
-use std::{future::Future, pin::Pin, sync::Arc, time::Duration};
+use std::{
+    future::Future,
+    net::{Ipv4Addr, SocketAddr, TcpListener},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    time::{Duration, Instant, SystemTime},
+};
 
 use tokio::{
     runtime::Runtime,
-    sync::mpsc::{self},
+    sync::mpsc::{
+        self,
+        error::{SendError, TrySendError},
+    },
 };
 
 use tokio_stream::StreamExt;
+use tracing::Instrument;
 
 use crate::{
+    connector::{Connector, NetworkConnector},
     errors::{self, AppErrors},
-    models::{IpPortScanResult, SubnetScanConfiguration},
+    metrics_helpers::{self, ScanMetrics},
+    models::{
+        IpPortScanResult, RuntimeConfiguration, ScanOrder, ScanTarget, SubnetScanConfiguration,
+    },
+    permutation_helpers::FeistelPermutation,
     port_helpers,
     progress_helper::ScanProgressTracker,
+    scan_report::{ScanReport, ScanReportCollector},
     scan_stream::ScanResultStreamer,
-    tokio_helpers,
+    target_helpers::IndexedRangeSet,
+    task_supervisor::{self, TaskOutcome, TaskReport},
+    tokio_helpers::{self, ScopedTask},
 };
 
-use anyhow::bail;
+use anyhow::{bail, Context};
 
 const PROGRESS_BAR_SIZE: u64 = 100;
+const DEFAULT_CHANNEL_CAPACITY: usize = 1024;
 
 pub struct SubnetScannerApp {
     subnet_scan_configurations: Vec<SubnetScanConfiguration>,
     scan_timeout: Duration,
+    channel_capacity: usize,
     runtime: Arc<Runtime>,
     scan_results: ScanResultStreamer,
     scan_progress: ScanProgressTracker,
-    scan_futures: Vec<Pin<Box<dyn Future<Output = ()>>>>,
+    scan_futures: Vec<Pin<Box<dyn Future<Output = TaskReport> + Send>>>,
+    max_restarts: u32,
+    metrics: Arc<ScanMetrics>,
+    metrics_listener: Option<TcpListener>,
+    result_observer: Option<mpsc::UnboundedSender<(ScanTarget, IpPortScanResult)>>,
+    connector: Arc<dyn Connector>,
+    time_budget: Option<Duration>,
+    // set when the run starts, shared by every subnet scan.
+    deadline: Arc<OnceLock<tokio::time::Instant>>,
+}
+
+/// Everything a subnet scan task needs, cloned for every (re)start of the task.
+#[derive(Clone)]
+struct SubnetScanContext {
+    config: SubnetScanConfiguration,
+    scan_timeout: Duration,
+    connector: Arc<dyn Connector>,
+    tx: mpsc::Sender<IpPortScanResult>,
+    metrics: Arc<ScanMetrics>,
+    // results handed to the channel so far, a restarted scan resumes after them.
+    delivered: Arc<AtomicU64>,
+    deadline: Arc<OnceLock<tokio::time::Instant>>,
+}
+
+impl SubnetScanContext {
+    // once past the deadline no probe is issued, the ones in flight still finish.
+    fn out_of_time(&self) -> bool {
+        self.deadline
+            .get()
+            .is_some_and(|deadline| tokio::time::Instant::now() >= *deadline)
+    }
 }
 
 impl SubnetScannerApp {
@@ -36,24 +89,37 @@ impl SubnetScannerApp {
 
     pub fn start_subnet_scans(&mut self) {
         for config in &self.subnet_scan_configurations {
-            let (tx, rx) = mpsc::unbounded_channel::<IpPortScanResult>();
+            let (tx, rx) = mpsc::channel::<IpPortScanResult>(self.channel_capacity);
 
             // let config = config.clone();
             let runtime = self.runtime.clone();
-            let scan_name = format!("scan_{}", config.subnet);
+            let scan_name = format!("scan_{}", config.target);
 
             self.scan_results
-                .add_stream_from_rx(config.subnet, rx);
+                .add_stream_from_rx(config.target.clone(), rx);
 
             self.scan_progress
-                .initate_subnet_progress(
-                    config.subnet,
-                    (config.end_port - config.begin_port) as u64,
-                );
-            let scan_fut = tokio_helpers::run_named_task(
+                .initate_target_progress(&config.target, config.probe_count());
+            let scan_span = tracing::info_span!(
+                "subnet_scan",
+                target = %config.target,
+                ports = config.ports().len()
+            );
+            let scan_context = SubnetScanContext {
+                config: config.clone(),
+                scan_timeout: self.scan_timeout,
+                connector: self.connector.clone(),
+                tx,
+                metrics: self.metrics.clone(),
+                delivered: Arc::new(AtomicU64::new(0)),
+                deadline: self.deadline.clone(),
+            };
+            let scan_fut = task_supervisor::supervise(
                 scan_name,
+                Some(config.target.clone()),
                 runtime,
-                Self::scan_ipv4_subnet(*config, self.scan_timeout, tx),
+                self.max_restarts,
+                move || Self::scan_ipv4_subnet(scan_context.clone()).instrument(scan_span.clone()),
             );
 
             self.scan_futures
@@ -64,58 +130,376 @@ impl SubnetScannerApp {
     async fn stream_progress(
         mut scan_stream: ScanResultStreamer,
         mut scan_progress: ScanProgressTracker,
-    ) {
-        while let Some((subnet, scan_result)) = scan_stream.next().await {
+        metrics: Arc<ScanMetrics>,
+        mut report_collector: ScanReportCollector,
+        result_observer: Option<mpsc::UnboundedSender<(ScanTarget, IpPortScanResult)>>,
+    ) -> ScanReportCollector {
+        while let Some((target, scan_result)) = scan_stream.next().await {
             match scan_result {
-                Some(_port_scan_result) => scan_progress.update_progress(subnet),
-                None => scan_progress.complete_progress(subnet),
+                Some(port_scan_result) => {
+                    metrics.result_dequeued(&target);
+                    report_collector.record(&target, &port_scan_result);
+                    scan_progress.update_progress(&target);
+                    if let Some(result_observer) = &result_observer {
+                        // the observer going away does not stop the scan.
+                        let _ = result_observer.send((target.clone(), port_scan_result));
+                    }
+                }
+                None => {
+                    tracing::info!(%target, "target scan completed");
+                    scan_progress.complete_progress(&target)
+                }
             }
         }
+
+        report_collector
     }
 
-    pub fn run(self) {
+    /// The `/metrics` endpoint address, when one was requested.
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics_listener
+            .as_ref()
+            .and_then(|listener| listener.local_addr().ok())
+    }
+
+    /// Runs every scan to completion and summarizes the results and how each task ended.
+    pub fn run(self) -> ScanReport {
+        let runtime = self.runtime.clone();
+        runtime.block_on(self.run_async())
+    }
+
+    /// Same as [`SubnetScannerApp::run`], awaited from a task of the app's runtime.
+    /// Dropping the future aborts the scan tasks and the metrics endpoint.
+    pub async fn run_async(self) -> ScanReport {
+        let started_at = SystemTime::now();
+        let run_start = Instant::now();
         let runtime = self.runtime;
         let scan_stream = self.scan_results;
         let scan_progress = self.scan_progress;
-        let mut tasks = self.scan_futures;
+        let tasks = self.scan_futures;
+        let targets: Vec<ScanTarget> = self
+            .subnet_scan_configurations
+            .iter()
+            .map(|config| config.target.clone())
+            .collect();
+        let configs = self
+            .subnet_scan_configurations
+            .clone();
+        let new_report_collector = || {
+            let mut report_collector = ScanReportCollector::new(targets.clone());
+            for config in &configs {
+                report_collector.plan(config);
+            }
+            report_collector
+        };
+        let report_collector = new_report_collector();
+
+        if let Some(time_budget) = self.time_budget {
+            let _ = self
+                .deadline
+                .set(tokio::time::Instant::now() + time_budget);
+        }
+
+        let metrics_endpoint = self
+            .metrics_listener
+            .map(|metrics_listener| {
+                ScopedTask::from(metrics_helpers::spawn_metrics_endpoint(
+                    &runtime,
+                    metrics_listener,
+                    self.metrics.clone(),
+                ))
+            });
+
+        let progress_task_name = String::from("stream_progress");
         let progerss_fut = tokio_helpers::run_named_task(
-            String::from("stream_progress"),
+            progress_task_name.clone(),
             runtime.clone(),
-            Self::stream_progress(scan_stream, scan_progress),
+            Self::stream_progress(
+                scan_stream,
+                scan_progress,
+                self.metrics,
+                report_collector,
+                self.result_observer,
+            ),
         );
 
-        tasks.push(Box::pin(progerss_fut));
-        runtime.block_on(futures::future::join_all(tasks));
+        let (mut task_reports, progress_result) =
+            futures::future::join(futures::future::join_all(tasks), progerss_fut).await;
+
+        let report_collector = match progress_result {
+            Ok(report_collector) => report_collector,
+            Err(join_error) => {
+                task_reports.push(TaskReport {
+                    task_name: progress_task_name,
+                    target: None,
+                    outcome: TaskOutcome::from_join_result(Err(join_error)),
+                    restarts: 0,
+                });
+                new_report_collector()
+            }
+        };
+
+        // the endpoint only stops on its own when it failed, which is reported
+        // like a failed scan task. Otherwise dropping it stops it.
+        if let Some(metrics_endpoint) = metrics_endpoint {
+            if metrics_endpoint.is_finished() {
+                let outcome = TaskOutcome::from_join_result(metrics_endpoint.await);
+                tracing::error!(%outcome, "metrics endpoint stopped");
+                task_reports.push(TaskReport {
+                    task_name: String::from(metrics_helpers::METRICS_ENDPOINT_TASK),
+                    target: None,
+                    outcome,
+                    restarts: 0,
+                });
+            }
+        }
+
+        report_collector.finish(task_reports, started_at, run_start.elapsed())
     }
 
-    async fn scan_ipv4_subnet(
+    /// Scans `config` with `connector` on the current runtime and sends every result
+    /// to `tx`, skipping the first `delivered` probes like a restarted scan does.
+    pub async fn scan_configuration(
         config: SubnetScanConfiguration,
         scan_timeout: Duration,
-        tx: mpsc::UnboundedSender<IpPortScanResult>,
+        delivered: u64,
+        connector: Arc<dyn Connector>,
+        tx: mpsc::Sender<IpPortScanResult>,
+        metrics: Arc<ScanMetrics>,
     ) -> anyhow::Result<()> {
-        for ip in config.subnet.hosts() {
-            for port in config.begin_port..config.end_port {
-                let scan_result =
-                    port_helpers::check_port_status_with_timeout(ip, port, scan_timeout).await;
-
-                if let Err(send_error) = tx.send(scan_result) {
-                    bail!(AppErrors::IpScanResultChannelSendError {
-                        channel: format!("subnet: {}", config.subnet),
-                        result: scan_result,
-                        source: send_error
-                    })
-                }
+        Self::scan_ipv4_subnet(SubnetScanContext {
+            config,
+            scan_timeout,
+            connector,
+            tx,
+            metrics,
+            delivered: Arc::new(AtomicU64::new(delivered)),
+            deadline: Arc::default(),
+        })
+        .await
+    }
+
+    // probe positions number the (host, port) pairs of the target, the shard
+    // takes every `count`-th of them and `delivered` tells how many it already
+    // handed over, so a restarted scan resumes at the next one.
+    async fn scan_ipv4_subnet(scan_context: SubnetScanContext) -> anyhow::Result<()> {
+        let hosts = IndexedRangeSet::new(scan_context.config.hosts());
+        let ports = IndexedRangeSet::new(scan_context.config.ports());
+        if ports.is_empty() {
+            return Ok(());
+        }
+
+        let shard = scan_context.config.shard;
+        let delivered = scan_context
+            .delivered
+            .load(Ordering::SeqCst);
+        let first_position = shard.offset() + delivered * shard.count;
+
+        match scan_context.config.order {
+            ScanOrder::Sequential => {
+                Self::scan_in_sequence(&scan_context, hosts, ports, first_position, shard.count)
+                    .await?
+            }
+            ScanOrder::Random { seed } => {
+                let permutation = FeistelPermutation::new(hosts.len() * ports.len(), seed);
+                Self::scan_in_random_order(
+                    &scan_context,
+                    hosts,
+                    ports,
+                    permutation,
+                    first_position,
+                    shard.count,
+                )
+                .await?
+            }
+        }
+
+        if scan_context.out_of_time() {
+            tracing::info!(
+                target = %scan_context.config.target,
+                "time budget spent, leaving the rest of the target unscanned"
+            );
+        }
+        Ok(())
+    }
+
+    async fn scan_in_sequence(
+        scan_context: &SubnetScanContext,
+        hosts: IndexedRangeSet<Ipv4Addr>,
+        ports: IndexedRangeSet<u16>,
+        first_position: u64,
+        step: u64,
+    ) -> anyhow::Result<()> {
+        let ports_per_host = ports.len();
+        let probe_count = hosts.len() * ports_per_host;
+
+        let mut position = first_position;
+        while position < probe_count && !scan_context.out_of_time() {
+            let host_index = position / ports_per_host;
+            let ip = hosts
+                .get(host_index)
+                .context("probe host out of range")?;
+            Self::scan_ipv4_host(
+                scan_context,
+                ip,
+                (position % ports_per_host..ports_per_host)
+                    .step_by(step as usize)
+                    .filter_map(|port_index| ports.get(port_index)),
+            )
+            .await?;
+
+            // first position of the shard on one of the following hosts.
+            let next_host_position = (host_index + 1) * ports_per_host;
+            position += (next_host_position - position).div_ceil(step) * step;
+        }
+
+        Ok(())
+    }
+
+    // walks a seeded permutation of every (host, port) pair, the shard and
+    // resume logic apply to the positions in the permutation.
+    async fn scan_in_random_order(
+        scan_context: &SubnetScanContext,
+        hosts: IndexedRangeSet<Ipv4Addr>,
+        ports: IndexedRangeSet<u16>,
+        permutation: FeistelPermutation,
+        first_position: u64,
+        step: u64,
+    ) -> anyhow::Result<()> {
+        for position in (first_position..permutation.size()).step_by(step as usize) {
+            if scan_context.out_of_time() {
+                break;
+            }
+            let probe = permutation.permute(position);
+            // consecutive probes land on different hosts rather than different ports.
+            let ip = hosts
+                .get(probe % hosts.len())
+                .context("probe host out of range")?;
+            let port = ports
+                .get(probe / hosts.len())
+                .context("probe port out of range")?;
+
+            Self::probe(scan_context, ip, port).await?;
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "host_scan", level = "debug", skip_all, fields(%ip))]
+    async fn scan_ipv4_host(
+        scan_context: &SubnetScanContext,
+        ip: Ipv4Addr,
+        ports: impl Iterator<Item = u16>,
+    ) -> anyhow::Result<()> {
+        for port in ports {
+            if scan_context.out_of_time() {
+                break;
             }
+            Self::probe(scan_context, ip, port).await?;
         }
 
         Ok(())
     }
+
+    async fn probe(
+        scan_context: &SubnetScanContext,
+        ip: Ipv4Addr,
+        port: u16,
+    ) -> anyhow::Result<()> {
+        let metrics = &scan_context.metrics;
+
+        metrics.probe_started();
+        let probe_start = Instant::now();
+        let mut scan_result = port_helpers::check_port_status_with_timeout(
+            scan_context.connector.as_ref(),
+            scan_context.config.route,
+            &scan_context.config.source,
+            ip,
+            port,
+            scan_context.scan_timeout,
+        )
+        .await
+        .inspect_err(|_| metrics.probe_failed())?;
+        metrics.probe_finished(&scan_result, probe_start.elapsed());
+        scan_result.hostname = scan_context
+            .config
+            .hostnames
+            .get(&ip)
+            .cloned();
+        scan_result.service = scan_context
+            .config
+            .service_names
+            .get(&port)
+            .cloned();
+
+        Self::send_scan_result(scan_context, scan_result).await
+    }
+
+    // waits for room in the channel when the consumer falls behind, which in
+    // turn holds back the next probe of this subnet.
+    async fn send_scan_result(
+        scan_context: &SubnetScanContext,
+        scan_result: IpPortScanResult,
+    ) -> anyhow::Result<()> {
+        let SubnetScanContext {
+            config,
+            tx,
+            metrics,
+            delivered,
+            ..
+        } = scan_context;
+        metrics.result_queued(&config.target);
+
+        let send_result = match tx.try_send(scan_result) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(scan_result)) => {
+                tracing::debug!(
+                    target = %config.target,
+                    capacity = tx.max_capacity(),
+                    "scan result channel is full, holding back probes"
+                );
+                let blocked_start = Instant::now();
+                let send_result = tx.send(scan_result).await;
+                metrics.producer_blocked(&config.target, blocked_start.elapsed());
+                send_result
+            }
+            Err(TrySendError::Closed(scan_result)) => Err(SendError(scan_result)),
+        };
+
+        if let Err(send_error) = send_result {
+            metrics.result_dequeued(&config.target);
+            tracing::error!(target = %config.target, "scan result channel closed");
+            bail!(AppErrors::IpScanResultChannelSendError {
+                channel: format!("target: {}", config.target),
+                result: send_error.0.clone(),
+                source: send_error
+            })
+        }
+
+        delivered.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
 }
 
 pub struct SubnetScannerAppBuilder {
     subnet_scan_configurations: Vec<SubnetScanConfiguration>,
     scan_timeout: Duration,
+    channel_capacity: usize,
+    max_restarts: u32,
     runtime: Option<Arc<Runtime>>,
+    runtime_config: Option<RuntimeConfiguration>,
+    metrics_addr: Option<SocketAddr>,
+    metrics: Option<Arc<ScanMetrics>>,
+    show_progress: bool,
+    result_observer: Option<mpsc::UnboundedSender<(ScanTarget, IpPortScanResult)>>,
+    connector: Arc<dyn Connector>,
+    time_budget: Option<Duration>,
+}
+
+impl Default for SubnetScannerAppBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SubnetScannerAppBuilder {
@@ -123,7 +507,16 @@ impl SubnetScannerAppBuilder {
         SubnetScannerAppBuilder {
             subnet_scan_configurations: Vec::new(),
             scan_timeout: Duration::from_secs(1),
+            channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+            max_restarts: 0,
             runtime: None,
+            runtime_config: None,
+            metrics_addr: None,
+            metrics: None,
+            show_progress: true,
+            result_observer: None,
+            connector: Arc::new(NetworkConnector),
+            time_budget: None,
         }
     }
 
@@ -138,14 +531,95 @@ impl SubnetScannerAppBuilder {
         self
     }
 
+    /// Number of scan results a subnet may queue before its probes are held back.
+    pub fn set_channel_capacity(mut self, channel_capacity: usize) -> Self {
+        self.channel_capacity = channel_capacity;
+        self
+    }
+
+    /// Number of times a failed or panicked subnet scan is resumed before giving up.
+    pub fn set_max_restarts(mut self, max_restarts: u32) -> Self {
+        self.max_restarts = max_restarts;
+        self
+    }
+
     pub fn set_runtime(mut self, runtime: &Arc<Runtime>) -> Self {
         self.runtime = Some(runtime.clone());
         self
     }
 
+    /// Builds a dedicated runtime from `runtime_config` when no runtime is set explicitly.
+    pub fn set_runtime_config(mut self, runtime_config: RuntimeConfiguration) -> Self {
+        self.runtime_config = Some(runtime_config);
+        self
+    }
+
+    /// Serves the scan and runtime metrics in prometheus format on `metrics_addr`.
+    pub fn set_metrics_addr(mut self, metrics_addr: SocketAddr) -> Self {
+        self.metrics_addr = Some(metrics_addr);
+        self
+    }
+
+    /// Records the scan metrics in `metrics`, e.g. ones served across several runs,
+    /// instead of a fresh set.
+    pub fn set_metrics(mut self, metrics: Arc<ScanMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Draws a progress bar per target on stderr, on by default.
+    pub fn set_show_progress(mut self, show_progress: bool) -> Self {
+        self.show_progress = show_progress;
+        self
+    }
+
+    /// Receives every scan result along with its target, as it is reported.
+    pub fn set_result_observer(
+        mut self,
+        result_observer: mpsc::UnboundedSender<(ScanTarget, IpPortScanResult)>,
+    ) -> Self {
+        self.result_observer = Some(result_observer);
+        self
+    }
+
+    /// Opens the probe connections with `connector` instead of the host's network,
+    /// e.g. a [`crate::simulated_network::SimulatedNetwork`].
+    pub fn set_connector(mut self, connector: Arc<dyn Connector>) -> Self {
+        self.connector = connector;
+        self
+    }
+
+    /// Stops issuing probes once `time_budget` has passed since the run started,
+    /// what is left of every target is reported as not scanned.
+    pub fn set_time_budget(mut self, time_budget: Duration) -> Self {
+        self.time_budget = Some(time_budget);
+        self
+    }
+
     pub fn build(self) -> anyhow::Result<SubnetScannerApp> {
-        if self.runtime.is_none() {
-            bail!(errors::AppErrors::NoRuntimeProvidedError)
+        if self.channel_capacity == 0 {
+            bail!(errors::AppErrors::InvalidChannelCapacityError)
+        }
+
+        let runtime = match (self.runtime, self.runtime_config) {
+            (Some(runtime), _) => runtime,
+            (None, Some(runtime_config)) => {
+                Arc::new(tokio_helpers::setup_tokio_runtime(&runtime_config)?)
+            }
+            (None, None) => bail!(errors::AppErrors::NoRuntimeProvidedError),
+        };
+
+        let metrics = self.metrics.unwrap_or_default();
+        let metrics_listener = match self.metrics_addr {
+            Some(metrics_addr) => {
+                metrics.register_runtime(runtime.handle().clone())?;
+                Some(metrics_helpers::bind_metrics_endpoint(metrics_addr)?)
+            }
+            None => None,
+        };
+
+        for config in &self.subnet_scan_configurations {
+            port_helpers::validate_source(&config.source)?;
         }
 
         let subnet_config_size = self
@@ -155,10 +629,475 @@ impl SubnetScannerAppBuilder {
         Ok(SubnetScannerApp {
             subnet_scan_configurations: self.subnet_scan_configurations,
             scan_timeout: self.scan_timeout,
-            runtime: self.runtime.unwrap(),
+            channel_capacity: self.channel_capacity,
+            runtime,
             scan_results: ScanResultStreamer::new(),
-            scan_progress: ScanProgressTracker::new(PROGRESS_BAR_SIZE),
+            scan_progress: if self.show_progress {
+                ScanProgressTracker::new(PROGRESS_BAR_SIZE)
+            } else {
+                ScanProgressTracker::hidden(PROGRESS_BAR_SIZE)
+            },
             scan_futures: Vec::with_capacity(subnet_config_size),
+            max_restarts: self.max_restarts,
+            metrics,
+            metrics_listener,
+            result_observer: self.result_observer,
+            connector: self.connector,
+            time_budget: self.time_budget,
+            deadline: Arc::default(),
         })
     }
 }
+
+#[cfg(test)]
+mod subnet_scan_tests {
+    use ipnet::Ipv4Net;
+    use std::{
+        collections::HashMap,
+        net::Ipv4Addr,
+        sync::{atomic::AtomicU64, Arc},
+        time::Duration,
+    };
+
+    use tokio::sync::mpsc;
+
+    use crate::{
+        app::{SubnetScanContext, SubnetScannerApp},
+        metrics_helpers::ScanMetrics,
+        models::{
+            Exclusions, HostAddressMode, IpPortScanResult, ScanOrder, ScanTarget, Shard,
+            SubnetScanConfiguration,
+        },
+        simulated_network::{SimulatedLink, SimulatedNetwork},
+    };
+
+    fn scan_context(
+        config: SubnetScanConfiguration,
+        tx: mpsc::Sender<IpPortScanResult>,
+        metrics: Arc<ScanMetrics>,
+    ) -> SubnetScanContext {
+        SubnetScanContext {
+            config,
+            scan_timeout: Duration::from_millis(500),
+            connector: Arc::new(SimulatedNetwork::default()),
+            tx,
+            metrics,
+            delivered: Arc::new(AtomicU64::new(0)),
+            deadline: Arc::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_hold_back_probes_while_the_consumer_is_slow() {
+        const CHANNEL_CAPACITY: usize = 4;
+        const PORTS: u16 = 128;
+
+        let config = SubnetScanConfiguration::new(
+            "127.0.0.1/32"
+                .parse::<Ipv4Net>()
+                .unwrap(),
+            41000,
+            41000 + PORTS,
+        );
+        let metrics = Arc::new(ScanMetrics::new());
+        let (tx, mut rx) = mpsc::channel(CHANNEL_CAPACITY);
+
+        let scan = tokio::spawn(SubnetScannerApp::scan_ipv4_subnet(scan_context(
+            config.clone(),
+            tx,
+            metrics.clone(),
+        )));
+
+        let mut consumed = 0_u64;
+        while rx.recv().await.is_some() {
+            consumed += 1;
+            // everything probed but not consumed yet is either queued in the
+            // channel or the single result waiting for room in it.
+            assert!(metrics.probes_sent() - consumed <= CHANNEL_CAPACITY as u64 + 1);
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        scan.await.unwrap().unwrap();
+        assert_eq!(consumed, PORTS as u64);
+        assert!(metrics.producer_blocked_count(&config.target) > 0);
+    }
+
+    #[tokio::test]
+    async fn should_resume_a_restarted_scan_after_the_delivered_results() {
+        let config = SubnetScanConfiguration::new(
+            "127.0.0.0/30"
+                .parse::<Ipv4Net>()
+                .unwrap(),
+            41000,
+            41003,
+        );
+        let (tx, mut rx) = mpsc::channel(16);
+        let context = scan_context(config, tx, Arc::new(ScanMetrics::new()));
+        // 2 hosts * 3 ports, the first host and one port of the second were delivered.
+        context
+            .delivered
+            .store(4, std::sync::atomic::Ordering::SeqCst);
+
+        SubnetScannerApp::scan_ipv4_subnet(context)
+            .await
+            .unwrap();
+
+        let mut resumed = Vec::new();
+        while let Ok(scan_result) = rx.try_recv() {
+            resumed.push((scan_result.ip.to_string(), scan_result.port));
+        }
+        assert_eq!(
+            resumed,
+            vec![
+                (String::from("127.0.0.2"), 41001),
+                (String::from("127.0.0.2"), 41002)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_skip_excluded_hosts_and_ports() {
+        let exclusions = Exclusions {
+            hosts: std::iter::once(Ipv4Addr::new(127, 0, 0, 2)..=Ipv4Addr::new(127, 0, 0, 3))
+                .collect(),
+            ports: std::iter::once(41001..=41001).collect(),
+        };
+        let config = SubnetScanConfiguration::new(
+            "127.0.0.0/29"
+                .parse::<Ipv4Net>()
+                .unwrap(),
+            41000,
+            41003,
+        )
+        .with_exclusions(Arc::new(exclusions));
+        let (tx, mut rx) = mpsc::channel(64);
+
+        assert_eq!(config.probe_count(), 8);
+        SubnetScannerApp::scan_ipv4_subnet(scan_context(config, tx, Arc::new(ScanMetrics::new())))
+            .await
+            .unwrap();
+
+        let mut scanned = Vec::new();
+        while let Ok(scan_result) = rx.try_recv() {
+            scanned.push((scan_result.ip.to_string(), scan_result.port));
+        }
+        assert_eq!(scanned.len(), 8);
+        assert_eq!(
+            scanned[..4],
+            [
+                (String::from("127.0.0.1"), 41000),
+                (String::from("127.0.0.1"), 41002),
+                (String::from("127.0.0.4"), 41000),
+                (String::from("127.0.0.4"), 41002)
+            ]
+        );
+    }
+
+    // (host, port) pairs probed by a scan of `config` resumed after `delivered` results.
+    async fn scanned_pairs(
+        config: &SubnetScanConfiguration,
+        delivered: u64,
+    ) -> Vec<(Ipv4Addr, u16)> {
+        let (tx, mut rx) = mpsc::channel(64);
+        let context = scan_context(config.clone(), tx, Arc::new(ScanMetrics::new()));
+        context
+            .delivered
+            .store(delivered, std::sync::atomic::Ordering::SeqCst);
+        SubnetScannerApp::scan_ipv4_subnet(context)
+            .await
+            .unwrap();
+
+        let mut scanned = Vec::new();
+        while let Ok(scan_result) = rx.try_recv() {
+            scanned.push((scan_result.ip, scan_result.port));
+        }
+        scanned
+    }
+
+    #[tokio::test]
+    async fn should_probe_every_pair_once_in_a_seeded_random_order() {
+        let config = SubnetScanConfiguration::new(
+            "127.0.0.0/29"
+                .parse::<Ipv4Net>()
+                .unwrap(),
+            41000,
+            41004,
+        )
+        .with_order(ScanOrder::Random { seed: 7 });
+
+        let scanned = scanned_pairs(&config, 0).await;
+        let sequential: Vec<(Ipv4Addr, u16)> = (1..=6)
+            .flat_map(|last_octet| {
+                (41000..41004).map(move |port| (Ipv4Addr::new(127, 0, 0, last_octet), port))
+            })
+            .collect();
+
+        let mut sorted = scanned.clone();
+        sorted.sort();
+        assert_eq!(sorted, sequential);
+        assert_ne!(scanned, sequential);
+        assert_eq!(scanned_pairs(&config, 0).await, scanned);
+        // a restarted scan picks up the permutation where it was left.
+        assert_eq!(scanned_pairs(&config, 20).await, scanned[20..]);
+    }
+
+    #[tokio::test]
+    async fn should_cover_every_pair_exactly_once_across_shards() {
+        let config = SubnetScanConfiguration::new(
+            "127.0.0.0/29"
+                .parse::<Ipv4Net>()
+                .unwrap(),
+            41000,
+            41005,
+        );
+
+        for order in [ScanOrder::Sequential, ScanOrder::Random { seed: 3 }] {
+            let whole = scanned_pairs(&config.clone().with_order(order), 0).await;
+            let mut sharded = Vec::new();
+            for index in 1..=4 {
+                let shard_config = config
+                    .clone()
+                    .with_order(order)
+                    .with_shard(Shard { index, count: 4 });
+                let shard_pairs = scanned_pairs(&shard_config, 0).await;
+
+                assert_eq!(shard_pairs.len() as u64, shard_config.probe_count());
+                // a restarted shard resumes within its own share.
+                assert_eq!(scanned_pairs(&shard_config, 2).await, shard_pairs[2..]);
+                sharded.extend(shard_pairs);
+            }
+
+            let mut whole_sorted = whole.clone();
+            whole_sorted.sort();
+            sharded.sort();
+            assert_eq!(whole.len(), 30);
+            assert_eq!(sharded, whole_sorted);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_keep_the_hostname_and_service_on_scan_results() {
+        let config = SubnetScanConfiguration::new(
+            ScanTarget::Named(String::from("localhost")),
+            41000,
+            41002,
+        )
+        .with_target_hosts(std::iter::once(Ipv4Addr::LOCALHOST..=Ipv4Addr::LOCALHOST).collect())
+        .with_hostnames(HashMap::from([(
+            Ipv4Addr::LOCALHOST,
+            Arc::from("localhost"),
+        )]))
+        .with_service_names(HashMap::from([(41001, Arc::from("lab-api"))]));
+        let (tx, mut rx) = mpsc::channel(16);
+
+        SubnetScannerApp::scan_ipv4_subnet(scan_context(config, tx, Arc::new(ScanMetrics::new())))
+            .await
+            .unwrap();
+
+        let mut hostnames = Vec::new();
+        let mut services = Vec::new();
+        while let Ok(scan_result) = rx.try_recv() {
+            hostnames.push(scan_result.hostname);
+            services.push(scan_result.service);
+        }
+        assert_eq!(hostnames, vec![Some(Arc::from("localhost")); 2]);
+        assert_eq!(services, vec![None, Some(Arc::from("lab-api"))]);
+    }
+
+    fn paused_runtime() -> Arc<tokio::runtime::Runtime> {
+        Arc::new(
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .start_paused(true)
+                .build()
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn should_scan_a_simulated_network_end_to_end() {
+        let runtime = paused_runtime();
+        let network = Arc::new(
+            SimulatedNetwork::new(SimulatedLink::refusing(Duration::from_millis(1)))
+                .with_port(443, SimulatedLink::accepting(Duration::from_millis(50)))
+                .with_host(Ipv4Addr::new(10, 0, 0, 3), SimulatedLink::dropped()),
+        );
+        // 6 hosts * 4 ports, every port of 10.0.0.3 is dropped.
+        let config = SubnetScanConfiguration::new(
+            "10.0.0.0/29"
+                .parse::<Ipv4Net>()
+                .unwrap(),
+            440,
+            444,
+        );
+        let mut app = SubnetScannerApp::builder()
+            .set_runtime(&runtime)
+            .set_configs(vec![config])
+            .set_scan_timeout(Duration::from_secs(2))
+            .set_show_progress(false)
+            .set_connector(network.clone())
+            .build()
+            .unwrap();
+        let scan_start = runtime.block_on(async { tokio::time::Instant::now() });
+
+        app.start_subnet_scans();
+        let report = app.run();
+
+        let state_counts = report.state_counts();
+        assert_eq!(
+            (state_counts.open, state_counts.closed, state_counts.timeout),
+            (5, 15, 4)
+        );
+        assert_eq!(network.connection_attempts(), 24);
+        assert_eq!(
+            report
+                .host(Ipv4Addr::new(10, 0, 0, 1))
+                .unwrap()
+                .open_ports
+                .iter()
+                .map(|open_port| open_port.port)
+                .collect::<Vec<_>>(),
+            vec![443]
+        );
+        // 4 timeouts, 5 open and 15 closed ports went by without any real waiting.
+        assert_eq!(
+            runtime.block_on(async move { scan_start.elapsed() }),
+            Duration::from_millis(8000 + 5 * 50 + 15)
+        );
+        assert!(report.duration < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn should_stop_issuing_probes_once_the_time_budget_is_spent() {
+        let runtime = paused_runtime();
+        let network = Arc::new(SimulatedNetwork::new(SimulatedLink::refusing(
+            Duration::from_millis(100),
+        )));
+        // 2 hosts * 5 ports per target, the targets are scanned side by side.
+        let configs = ["10.0.0.0/30", "10.0.1.0/30"]
+            .iter()
+            .map(|subnet| {
+                SubnetScanConfiguration::new(subnet.parse::<Ipv4Net>().unwrap(), 440, 445)
+            })
+            .collect();
+        let mut app = SubnetScannerApp::builder()
+            .set_runtime(&runtime)
+            .set_configs(configs)
+            .set_show_progress(false)
+            .set_connector(network.clone())
+            .set_time_budget(Duration::from_millis(550))
+            .build()
+            .unwrap();
+
+        app.start_subnet_scans();
+        let report = app.run();
+
+        // probes start every 100ms, the one started at 500ms is the last.
+        assert_eq!(network.connection_attempts(), 12);
+        assert_eq!(report.not_scanned(), 8);
+        for subnet_report in &report.subnets {
+            assert_eq!(subnet_report.state_counts.closed, 6);
+            assert_eq!(
+                (subnet_report.planned_probes, subnet_report.not_scanned),
+                (10, 4)
+            );
+            assert_eq!(subnet_report.coverage(), Some(60.0));
+        }
+        assert_eq!(report.completed_hosts().count(), 0);
+    }
+
+    #[test]
+    fn should_scan_edge_prefixes_and_complete_zero_port_targets() {
+        let runtime = paused_runtime();
+        let network = Arc::new(SimulatedNetwork::new(SimulatedLink::accepting(
+            Duration::from_millis(1),
+        )));
+        let subnet = |subnet: &str| subnet.parse::<Ipv4Net>().unwrap();
+        let every_address = ScanTarget::from(subnet("10.0.2.0/30"));
+        let configs = vec![
+            SubnetScanConfiguration::new(subnet("10.0.0.0/31"), 22, 23),
+            SubnetScanConfiguration::new(subnet("10.0.1.7/32"), 22, 23),
+            SubnetScanConfiguration::new(every_address.clone(), 22, 23)
+                .with_target_hosts(every_address.hosts_with(HostAddressMode::All)),
+            SubnetScanConfiguration::new(subnet("10.0.3.0/24"), 22, 22),
+        ];
+        let mut app = SubnetScannerApp::builder()
+            .set_runtime(&runtime)
+            .set_configs(configs)
+            .set_show_progress(false)
+            .set_connector(network.clone())
+            .build()
+            .unwrap();
+
+        app.start_subnet_scans();
+        let report = app.run();
+
+        assert_eq!(network.connection_attempts(), 7);
+        assert_eq!(
+            report
+                .subnets
+                .iter()
+                .map(|subnet_report| (
+                    subnet_report.state_counts.open,
+                    subnet_report.planned_probes,
+                    subnet_report.coverage()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (2, 2, Some(100.0)),
+                (1, 1, Some(100.0)),
+                (4, 4, Some(100.0)),
+                (0, 0, None)
+            ]
+        );
+        for host in ["10.0.0.0", "10.0.0.1", "10.0.1.7", "10.0.2.0", "10.0.2.3"] {
+            assert!(report
+                .host(host.parse().unwrap())
+                .is_some());
+        }
+    }
+
+    #[test]
+    fn should_keep_counting_in_shared_metrics_across_runs() {
+        let runtime = paused_runtime();
+        let network = Arc::new(SimulatedNetwork::new(SimulatedLink::refusing(
+            Duration::from_millis(1),
+        )));
+        let metrics = Arc::new(ScanMetrics::new());
+
+        for _ in 0..2 {
+            let mut app = SubnetScannerApp::builder()
+                .set_runtime(&runtime)
+                .set_configs(vec![SubnetScanConfiguration::new(
+                    "10.0.0.0/30"
+                        .parse::<Ipv4Net>()
+                        .unwrap(),
+                    22,
+                    24,
+                )])
+                .set_show_progress(false)
+                .set_connector(network.clone())
+                .set_metrics(metrics.clone())
+                .build()
+                .unwrap();
+            app.start_subnet_scans();
+            app.run();
+        }
+
+        // 2 hosts * 2 ports per run.
+        assert_eq!(metrics.probes_sent(), 8);
+    }
+
+    #[test]
+    fn should_reject_zero_channel_capacity() {
+        assert_eq!(
+            "Scan result channel capacity must be greater than zero",
+            SubnetScannerApp::builder()
+                .set_channel_capacity(0)
+                .build()
+                .err()
+                .unwrap()
+                .to_string()
+        );
+    }
+}
@@ -1,28 +1,138 @@
-use crate::{models::SubnetScanConfiguration, subnet_helpers::parse_subnet};
+use std::{
+    collections::HashMap,
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    errors::AppErrors,
+    guardrails::{self, Guardrails},
+    models::{Exclusions, GuardrailArgs, HostAddressMode, ScanTarget, SubnetScanConfiguration},
+    resolve_helpers::{self, HostResolver},
+    services::{self, ServiceRegistry},
+    target_helpers::{self, RangeSet, TargetList},
+};
 use anyhow::anyhow;
-use anyhow::Context;
+use anyhow::{bail, Context};
+
+/// A target with the addresses it stands for, and the names of the resolved ones.
+struct ResolvedTarget {
+    target: ScanTarget,
+    hosts: RangeSet<Ipv4Addr>,
+    hostnames: HashMap<Ipv4Addr, Arc<str>>,
+}
 
-pub fn prepare_subnets_and_port_ranges(
-    subnets: Vec<String>,
+impl ResolvedTarget {
+    fn resolve(
+        spec: &str,
+        host_addresses: HostAddressMode,
+        resolver: &HostResolver,
+    ) -> anyhow::Result<Self> {
+        let spec = spec.trim();
+        let target = match target_helpers::parse_scan_target(spec) {
+            Ok(target) => target,
+            Err(_) if resolve_helpers::is_hostname(spec) => {
+                let hostname: Arc<str> = Arc::from(spec);
+                let addresses = resolver.resolve(spec)?;
+                return Ok(Self {
+                    target: ScanTarget::Named(spec.to_string()),
+                    hosts: addresses
+                        .iter()
+                        .map(|ip| *ip..=*ip)
+                        .collect(),
+                    hostnames: addresses
+                        .into_iter()
+                        .map(|ip| (ip, hostname.clone()))
+                        .collect(),
+                });
+            }
+            Err(parse_error) => return Err(parse_error),
+        };
+
+        Ok(Self {
+            hosts: target.hosts_with(host_addresses),
+            target,
+            hostnames: HashMap::new(),
+        })
+    }
+}
+
+/// Pairs the targets, then the target lists, with their port specs. A single
+/// port spec applies to every target, hostnames are resolved with `resolver`
+/// and subnets keep the network and broadcast addresses of `host_addresses`.
+/// Port specs name services of `services`, which also names the scanned ports.
+pub fn prepare_scan_configurations(
+    targets: Vec<String>,
+    target_lists: &[TargetList],
     port_ranges: Vec<String>,
+    host_addresses: HostAddressMode,
+    services: &ServiceRegistry,
+    resolver: &HostResolver,
 ) -> anyhow::Result<Vec<SubnetScanConfiguration>> {
-    subnets
+    let target_count = targets.len() + target_lists.len();
+    let port_ranges = match port_ranges.as_slice() {
+        [port_range] => vec![port_range.clone(); target_count],
+        _ if port_ranges.len() == target_count => port_ranges,
+        _ => bail!(
+            "Number of targets and port ranges must be equal, or a single port range given. targets count was {}, port ranges count was: {}",
+            target_count,
+            port_ranges.len()
+        ),
+    };
+
+    let mut resolved_targets = targets
+        .iter()
+        .map(|target| ResolvedTarget::resolve(target, host_addresses, resolver))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    for target_list in target_lists {
+        resolved_targets.push(resolve_target_list(target_list, host_addresses, resolver)?);
+    }
+
+    resolved_targets
         .into_iter()
         .zip(port_ranges)
-        .map(|(subnet_str, port_range_str)| {
-            parse_subnet(subnet_str).and_then(|subnet| {
-                parse_port_ranges(port_range_str).map(|(begin_port, end_port)| {
-                    SubnetScanConfiguration {
-                        subnet,
-                        begin_port,
-                        end_port,
-                    }
-                })
+        .map(|(resolved_target, port_range)| {
+            parse_port_spec(&port_range, services).map(|target_ports| {
+                let service_names = services.names_of(&target_ports);
+                SubnetScanConfiguration::for_ports(resolved_target.target, target_ports)
+                    .with_target_hosts(resolved_target.hosts)
+                    .with_hostnames(resolved_target.hostnames)
+                    .with_service_names(service_names)
             })
         })
         .collect()
 }
 
+/// Resolves a target list as a single target covering every line.
+fn resolve_target_list(
+    target_list: &TargetList,
+    host_addresses: HostAddressMode,
+    resolver: &HostResolver,
+) -> anyhow::Result<ResolvedTarget> {
+    let mut target_hosts = Vec::new();
+    let mut hostnames = HashMap::new();
+    for target in &target_list.targets {
+        let resolved_target = ResolvedTarget::resolve(target, host_addresses, resolver)?;
+        target_hosts.extend(resolved_target.hosts.ranges());
+        hostnames.extend(resolved_target.hostnames);
+    }
+
+    Ok(ResolvedTarget {
+        target: ScanTarget::Named(target_list.name.clone()),
+        hosts: target_hosts.into_iter().collect(),
+        hostnames,
+    })
+}
+
+/// Reads every target file once, stdin included, so repeated scans reuse the lists.
+pub fn read_target_lists(target_files: &[PathBuf]) -> anyhow::Result<Vec<TargetList>> {
+    target_files
+        .iter()
+        .map(|target_file| TargetList::read(target_file))
+        .collect()
+}
+
 pub fn parse_port_ranges(port_range: String) -> anyhow::Result<(u16, u16)> {
     let (begin_port_str, end_port_str) = port_range
         .split_once(':')
@@ -60,9 +170,138 @@ pub fn parse_port_ranges(port_range: String) -> anyhow::Result<(u16, u16)> {
     Ok((begin_port, end_port))
 }
 
+/// Parses comma separated ports, [begin_port]:[end_port] ranges with an exclusive
+/// end and service names, e.g. `ssh,https,8000:8100`.
+pub fn parse_port_spec(
+    port_spec: &str,
+    services: &ServiceRegistry,
+) -> anyhow::Result<RangeSet<u16>> {
+    let mut ports = RangeSet::new();
+    for port in port_spec.split(',').map(str::trim) {
+        if port.contains(':') {
+            let (begin_port, end_port) = parse_port_ranges(port.to_string())?;
+            if begin_port < end_port {
+                ports.insert(begin_port..=end_port - 1);
+            }
+            continue;
+        }
+
+        let port = match port.parse::<u16>() {
+            Ok(port) => port,
+            Err(_) => match services.port(port) {
+                Some(port) => port,
+                None => bail!(AppErrors::UnknownServiceError {
+                    service: port.to_string()
+                }),
+            },
+        };
+        ports.insert(port..=port);
+    }
+
+    Ok(ports)
+}
+
+/// Hosts and ports left out of the scans, the ports given as in `--ports`
+/// and their names looked up in `services`.
+pub fn prepare_exclusions(
+    excluded_hosts: Vec<String>,
+    exclude_file: Option<PathBuf>,
+    excluded_ports: Vec<String>,
+    services: &ServiceRegistry,
+) -> anyhow::Result<Exclusions> {
+    let excluded_hosts = match exclude_file {
+        Some(exclude_file) => {
+            let mut excluded_hosts = excluded_hosts;
+            excluded_hosts.extend(target_helpers::read_target_file(&exclude_file)?);
+            excluded_hosts
+        }
+        None => excluded_hosts,
+    };
+
+    Ok(Exclusions {
+        hosts: excluded_hosts
+            .iter()
+            .map(|excluded_host| target_helpers::parse_ip_range(excluded_host))
+            .collect::<anyhow::Result<_>>()?,
+        ports: excluded_ports
+            .iter()
+            .map(|excluded_port| parse_port_spec(excluded_port, services))
+            .collect::<anyhow::Result<Vec<_>>>()?
+            .iter()
+            .flat_map(RangeSet::ranges)
+            .collect(),
+    })
+}
+
+/// Service names of the embedded registry, overridden by the system services
+/// file when present, minus its malformed entries, and then by `services_file`.
+pub fn prepare_services(services_file: Option<PathBuf>) -> anyhow::Result<ServiceRegistry> {
+    let mut services = ServiceRegistry::embedded();
+    let system_services_path = Path::new(services::SYSTEM_SERVICES_PATH);
+    if system_services_path.exists() {
+        services = services.with_system_services_file(system_services_path)?;
+    }
+    if let Some(services_file) = services_file {
+        services = services.with_services_file(&services_file)?;
+    }
+
+    Ok(services)
+}
+
+/// Guardrails of the scans, the organization allowlist at its usual place is
+/// enforced whenever it exists, along with the allowlist file given.
+pub fn prepare_guardrails(guardrail_args: GuardrailArgs) -> anyhow::Result<Guardrails> {
+    guardrails_within(
+        guardrail_args,
+        Path::new(guardrails::ORGANIZATION_ALLOWLIST_PATH),
+    )
+}
+
+fn guardrails_within(
+    guardrail_args: GuardrailArgs,
+    organization_allowlist_path: &Path,
+) -> anyhow::Result<Guardrails> {
+    let GuardrailArgs {
+        confirm_above,
+        allow,
+        allowlist_file,
+    } = guardrail_args;
+
+    let mut guardrails = Guardrails::new(confirm_above).with_allowlist(
+        allow
+            .iter()
+            .map(|allowed| target_helpers::parse_ip_range(allowed))
+            .collect::<anyhow::Result<_>>()?,
+    );
+
+    // a scan must stay within both files.
+    let allowlist_files = organization_allowlist_path
+        .exists()
+        .then(|| organization_allowlist_path.to_path_buf())
+        .into_iter()
+        .chain(allowlist_file);
+    for allowlist_file in allowlist_files {
+        guardrails = guardrails.with_organization_allowlist(
+            target_helpers::read_target_file(&allowlist_file)?
+                .iter()
+                .map(|allowed| target_helpers::parse_ip_range(allowed))
+                .collect::<anyhow::Result<_>>()
+                .context(format!(
+                    "Invalid organization allowlist {}",
+                    allowlist_file.display()
+                ))?,
+        );
+    }
+
+    Ok(guardrails)
+}
+
 #[cfg(test)]
 mod port_tests {
-    use crate::arg_helpers::parse_port_ranges;
+    use crate::{
+        arg_helpers::{parse_port_ranges, parse_port_spec, prepare_exclusions},
+        services::ServiceRegistry,
+    };
 
     #[test]
     fn parse_port_ranges_test() {
@@ -103,14 +342,397 @@ mod port_tests {
                 .to_string()
         );
     }
+
+    #[test]
+    fn prepare_exclusions_test() {
+        let services = ServiceRegistry::embedded();
+        let excluded_ports = |excluded_ports: &[&str]| {
+            prepare_exclusions(
+                Vec::new(),
+                None,
+                excluded_ports
+                    .iter()
+                    .map(|excluded_port| excluded_port.to_string())
+                    .collect(),
+                &services,
+            )
+            .map(|exclusions| {
+                exclusions
+                    .ports
+                    .iter()
+                    .collect::<Vec<u16>>()
+            })
+        };
+
+        assert_eq!(excluded_ports(&["25"]).unwrap(), vec![25]);
+        assert_eq!(
+            excluded_ports(&["135:140", "smtp", "SSH"]).unwrap(),
+            vec![22, 25, 135, 136, 137, 138, 139]
+        );
+        assert_eq!(
+            excluded_ports(&["gibberish"])
+                .err()
+                .unwrap()
+                .to_string(),
+            "Unknown service `gibberish`, expected a port number or a known service name"
+        );
+    }
+
+    #[test]
+    fn parse_port_spec_test() {
+        let services = ServiceRegistry::embedded();
+        let ports = |port_spec| {
+            parse_port_spec(port_spec, &services)
+                .unwrap()
+                .iter()
+                .collect::<Vec<u16>>()
+        };
+
+        assert_eq!(ports("ssh,https,postgresql"), vec![22, 443, 5432]);
+        assert_eq!(ports("8000:8003, HTTP ,8001"), vec![80, 8000, 8001, 8002]);
+        assert_eq!(ports("22:24"), vec![22, 23]);
+        assert_eq!(ports("22:22"), Vec::<u16>::new());
+        assert_eq!(
+            "Unknown service `gibberish`, expected a port number or a known service name",
+            parse_port_spec("ssh,gibberish", &services)
+                .err()
+                .unwrap()
+                .to_string()
+        );
+        assert_eq!(
+            "Begin port 8000 is bigger than the end port 5000",
+            parse_port_spec("ssh,8000:5000", &services)
+                .err()
+                .unwrap()
+                .to_string()
+        );
+    }
 }
 
 #[cfg(test)]
 mod parsing_input_arg_tests {
+    use std::{net::Ipv4Addr, path::PathBuf};
+
+    use crate::{
+        arg_helpers::{prepare_scan_configurations, read_target_lists},
+        models::{HostAddressMode, ScanTarget},
+        resolve_helpers::{dns_stand_in, HostResolver},
+        services::ServiceRegistry,
+    };
 
     #[test]
-    fn prepare_subnets_and_port_ranges_test() {
-        // TODO: implement this test
-        //        assert_eq!(vec![Ok(SubnetScanConfiguration {})])
+    fn prepare_scan_configurations_test() {
+        let configs = prepare_scan_configurations(
+            vec![
+                String::from("10.0.0.0/30"),
+                String::from("10.0.1.5-10.0.1.7"),
+            ],
+            &[],
+            vec![String::from("22:24")],
+            HostAddressMode::Usable,
+            &ServiceRegistry::embedded(),
+            &HostResolver::System,
+        )
+        .unwrap();
+
+        assert_eq!(
+            configs
+                .iter()
+                .map(|config| (config.target.to_string(), config.probe_count()))
+                .collect::<Vec<_>>(),
+            vec![
+                (String::from("10.0.0.0/30"), 4),
+                (String::from("10.0.1.5-10.0.1.7"), 6)
+            ]
+        );
+
+        assert_eq!(
+            "Number of targets and port ranges must be equal, or a single port range given. targets count was 1, port ranges count was: 2",
+            prepare_scan_configurations(
+                vec![String::from("10.0.0.1")],
+                &[],
+                vec![String::from("22:23"), String::from("80:81")],
+                HostAddressMode::Usable,
+                &ServiceRegistry::embedded(),
+
+                &HostResolver::System,
+            )
+            .err()
+            .unwrap()
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn prepare_scan_configurations_with_service_names_test() {
+        let configs = prepare_scan_configurations(
+            vec![String::from("10.0.0.0/30")],
+            &[],
+            vec![String::from("ssh,postgresql,41000:41002")],
+            HostAddressMode::Usable,
+            &ServiceRegistry::embedded(),
+            &HostResolver::System,
+        )
+        .unwrap();
+
+        assert_eq!(
+            configs[0]
+                .ports()
+                .iter()
+                .collect::<Vec<_>>(),
+            vec![22, 5432, 41000, 41001]
+        );
+        let mut service_names: Vec<(u16, &str)> = configs[0]
+            .service_names
+            .iter()
+            .map(|(port, name)| (*port, name.as_ref()))
+            .collect();
+        service_names.sort();
+        assert_eq!(service_names, vec![(22, "ssh"), (5432, "postgresql")]);
+        assert_eq!(configs[0].probe_count(), 8);
+    }
+
+    #[test]
+    fn prepare_scan_configurations_with_host_addresses_test() {
+        let configs = prepare_scan_configurations(
+            vec![
+                String::from("10.0.0.0/30"),
+                String::from("10.0.1.0/31"),
+                String::from("10.0.2.0-10.0.2.3"),
+            ],
+            &[],
+            vec![String::from("22:23")],
+            HostAddressMode::IncludeNetwork,
+            &ServiceRegistry::embedded(),
+            &HostResolver::System,
+        )
+        .unwrap();
+
+        assert_eq!(
+            configs
+                .iter()
+                .map(|config| {
+                    config
+                        .hosts()
+                        .iter()
+                        .map(|ip| ip.octets()[3])
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>(),
+            vec![vec![0, 1, 2], vec![0, 1], vec![0, 1, 2, 3]]
+        );
+    }
+
+    #[test]
+    fn prepare_scan_configurations_from_a_target_file_test() {
+        let target_file = std::env::temp_dir().join(format!(
+            "humble_port_scanner_targets_{}.txt",
+            std::process::id()
+        ));
+        std::fs::write(
+            &target_file,
+            "# lab\n10.0.0.0/30\n10.0.0.2-10.0.0.6 # overlaps the subnet\n\n10.0.0.9\n",
+        )
+        .unwrap();
+
+        let target_lists = read_target_lists(std::slice::from_ref(&target_file)).unwrap();
+        std::fs::remove_file(&target_file).unwrap();
+        // the lists are read once, every later scan reuses them.
+        let prepare = || {
+            prepare_scan_configurations(
+                Vec::new(),
+                &target_lists,
+                vec![String::from("80:81")],
+                HostAddressMode::Usable,
+                &ServiceRegistry::embedded(),
+                &HostResolver::System,
+            )
+            .unwrap()
+        };
+        let configs = prepare();
+        assert_eq!(prepare()[0].probe_count(), 7);
+
+        assert_eq!(configs.len(), 1);
+        assert_eq!(
+            configs[0].target,
+            ScanTarget::Named(target_file.display().to_string())
+        );
+        assert_eq!(
+            configs[0]
+                .hosts()
+                .iter()
+                .collect::<Vec<_>>(),
+            vec![1, 2, 3, 4, 5, 6, 9]
+                .into_iter()
+                .map(|last_octet| Ipv4Addr::new(10, 0, 0, last_octet))
+                .collect::<Vec<_>>()
+        );
+
+        assert!(read_target_lists(&[PathBuf::from("/nonexistent/targets.txt")]).is_err());
+    }
+
+    #[test]
+    fn prepare_scan_configurations_with_hostnames_test() {
+        let resolver = HostResolver::Dns(dns_stand_in::spawn(&[
+            (
+                "web.scan.test",
+                &[Ipv4Addr::new(10, 0, 0, 7), Ipv4Addr::new(10, 0, 0, 3)],
+            ),
+            ("db.scan.test", &[Ipv4Addr::new(10, 0, 1, 9)]),
+        ]));
+
+        let configs = prepare_scan_configurations(
+            vec![String::from("web.scan.test"), String::from("10.0.1.0/30")],
+            &[],
+            vec![String::from("80:81")],
+            HostAddressMode::Usable,
+            &ServiceRegistry::embedded(),
+            &resolver,
+        )
+        .unwrap();
+
+        assert_eq!(
+            configs[0].target,
+            ScanTarget::Named(String::from("web.scan.test"))
+        );
+        assert_eq!(
+            configs[0]
+                .hosts()
+                .iter()
+                .collect::<Vec<_>>(),
+            vec![Ipv4Addr::new(10, 0, 0, 3), Ipv4Addr::new(10, 0, 0, 7)]
+        );
+        assert_eq!(
+            configs[0]
+                .hostnames
+                .get(&Ipv4Addr::new(10, 0, 0, 7))
+                .map(|hostname| hostname.as_ref()),
+            Some("web.scan.test")
+        );
+        assert!(configs[1].hostnames.is_empty());
+
+        assert_eq!(
+            "Unable to parse IP address: 10.0.0.300",
+            prepare_scan_configurations(
+                vec![String::from("10.0.0.300")],
+                &[],
+                vec![String::from("80:81")],
+                HostAddressMode::Usable,
+                &ServiceRegistry::embedded(),
+                &resolver,
+            )
+            .err()
+            .unwrap()
+            .to_string()
+        );
+    }
+}
+
+#[cfg(test)]
+mod guardrail_arg_tests {
+    use std::{fs, path::PathBuf};
+
+    use ipnet::Ipv4Net;
+
+    use crate::{
+        arg_helpers::{guardrails_within, prepare_guardrails},
+        models::{GuardrailArgs, SubnetScanConfiguration},
+    };
+
+    fn scan_of(subnet: &str) -> Vec<SubnetScanConfiguration> {
+        vec![SubnetScanConfiguration::new(
+            subnet.parse::<Ipv4Net>().unwrap(),
+            80,
+            81,
+        )]
+    }
+
+    #[test]
+    fn prepare_guardrails_test() {
+        let allowlist_file = std::env::temp_dir().join(format!(
+            "humble_port_scanner_allowlist_{}.txt",
+            std::process::id()
+        ));
+        fs::write(
+            &allowlist_file,
+            "# lab networks\n10.20.0.0/16\n192.0.2.0/24\n",
+        )
+        .unwrap();
+
+        let guardrails = prepare_guardrails(GuardrailArgs {
+            confirm_above: 1000,
+            allow: vec![String::from("192.0.2.0/24")],
+            allowlist_file: Some(allowlist_file.clone()),
+        })
+        .unwrap();
+        fs::remove_file(&allowlist_file).unwrap();
+
+        assert_eq!(
+            guardrails
+                .check(&scan_of("192.0.2.0/24"))
+                .unwrap(),
+            254
+        );
+        assert!(guardrails
+            .check(&scan_of("10.20.0.0/16"))
+            .is_err());
+        assert!(guardrails
+            .check(&scan_of("10.30.0.0/24"))
+            .is_err());
+
+        assert!(prepare_guardrails(GuardrailArgs {
+            confirm_above: 1000,
+            allow: Vec::new(),
+            allowlist_file: Some(PathBuf::from("/nonexistent/allowlist")),
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn should_keep_the_organization_allowlist_along_with_the_allowlist_file() {
+        let organization_allowlist = std::env::temp_dir().join(format!(
+            "humble_port_scanner_organization_allowlist_{}.txt",
+            std::process::id()
+        ));
+        let allowlist_file = std::env::temp_dir().join(format!(
+            "humble_port_scanner_user_allowlist_{}.txt",
+            std::process::id()
+        ));
+        fs::write(
+            &organization_allowlist,
+            "10.20.0.0/16
+",
+        )
+        .unwrap();
+        fs::write(
+            &allowlist_file,
+            "10.0.0.0/8
+",
+        )
+        .unwrap();
+
+        let guardrails = guardrails_within(
+            GuardrailArgs {
+                confirm_above: 1000,
+                allow: Vec::new(),
+                allowlist_file: Some(allowlist_file.clone()),
+            },
+            &organization_allowlist,
+        )
+        .unwrap();
+        fs::remove_file(&organization_allowlist).unwrap();
+        fs::remove_file(&allowlist_file).unwrap();
+
+        assert!(guardrails
+            .check(&scan_of("10.20.1.0/24"))
+            .is_ok());
+        assert_eq!(
+            guardrails
+                .check(&scan_of("10.30.0.0/24"))
+                .err()
+                .unwrap()
+                .to_string(),
+            "Target 10.30.0.0/24 reaches 10.30.0.1, outside the organization allowlist"
+        );
     }
 }
@@ -0,0 +1,260 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::Context;
+
+use crate::{errors::AppErrors, watch_helpers::ChangeEvent};
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Destination of the change events noticed while watching.
+pub trait ChangeSink {
+    fn publish(&mut self, change_events: &[ChangeEvent]) -> anyhow::Result<()>;
+}
+
+pub struct StdoutSink;
+
+impl ChangeSink for StdoutSink {
+    fn publish(&mut self, change_events: &[ChangeEvent]) -> anyhow::Result<()> {
+        for change_event in change_events {
+            println!("{}", change_event);
+        }
+        Ok(())
+    }
+}
+
+/// Appends one JSON object per change event to a file.
+pub struct JsonlFileSink {
+    path: PathBuf,
+    file: File,
+}
+
+impl JsonlFileSink {
+    pub fn open(path: PathBuf) -> anyhow::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .context(format!("Unable to open change log {}", path.display()))?;
+
+        Ok(Self { path, file })
+    }
+}
+
+impl ChangeSink for JsonlFileSink {
+    fn publish(&mut self, change_events: &[ChangeEvent]) -> anyhow::Result<()> {
+        let mut lines = String::new();
+        for change_event in change_events {
+            lines.push_str(&serde_json::to_string(change_event)?);
+            lines.push('\n');
+        }
+
+        self.file
+            .write_all(lines.as_bytes())
+            .context(format!(
+                "Unable to append to change log {}",
+                self.path.display()
+            ))
+    }
+}
+
+/// POSTs the change events of each scan as one JSON array.
+pub struct WebhookSink {
+    url: String,
+    client: reqwest::blocking::Client,
+}
+
+impl WebhookSink {
+    pub fn new(url: String) -> anyhow::Result<Self> {
+        let client = reqwest::blocking::Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()
+            .context("Unable to setup the webhook client")?;
+
+        Ok(Self { url, client })
+    }
+}
+
+impl ChangeSink for WebhookSink {
+    fn publish(&mut self, change_events: &[ChangeEvent]) -> anyhow::Result<()> {
+        self.client
+            .post(&self.url)
+            .json(change_events)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .context(format!("Unable to deliver change events to {}", self.url))?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SinkSpec {
+    Stdout,
+    Jsonl(PathBuf),
+    Webhook(String),
+}
+
+impl SinkSpec {
+    pub fn open(&self) -> anyhow::Result<Box<dyn ChangeSink>> {
+        Ok(match self {
+            SinkSpec::Stdout => Box::new(StdoutSink),
+            SinkSpec::Jsonl(path) => Box::new(JsonlFileSink::open(path.clone())?),
+            SinkSpec::Webhook(url) => Box::new(WebhookSink::new(url.clone())?),
+        })
+    }
+}
+
+impl FromStr for SinkSpec {
+    type Err = AppErrors;
+
+    fn from_str(sink: &str) -> Result<Self, Self::Err> {
+        let invalid_sink = || AppErrors::InvalidSinkError {
+            sink: sink.to_string(),
+        };
+
+        match sink.split_once(':') {
+            None if sink == "stdout" => Ok(SinkSpec::Stdout),
+            Some(("jsonl", path)) if !path.is_empty() => Ok(SinkSpec::Jsonl(PathBuf::from(path))),
+            Some(("webhook", url)) if url.starts_with("http://") || url.starts_with("https://") => {
+                Ok(SinkSpec::Webhook(url.to_string()))
+            }
+            _ => Err(invalid_sink()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod change_sink_tests {
+    use std::{
+        net::Ipv4Addr,
+        path::PathBuf,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{extract::State, routing::post, Json, Router};
+
+    use crate::{
+        change_sinks::{ChangeSink, JsonlFileSink, SinkSpec, WebhookSink},
+        watch_helpers::{Change, ChangeEvent},
+    };
+
+    fn change_events() -> Vec<ChangeEvent> {
+        vec![
+            ChangeEvent {
+                detected_at: String::from("2024-01-01T00:00:00Z"),
+                change: Change::HostAppeared {
+                    ip: Ipv4Addr::new(10, 0, 0, 7),
+                },
+            },
+            ChangeEvent {
+                detected_at: String::from("2024-01-01T00:00:00Z"),
+                change: Change::PortOpened {
+                    ip: Ipv4Addr::new(10, 0, 0, 7),
+                    port: 22,
+                    service: Some(String::from("ssh")),
+                },
+            },
+        ]
+    }
+
+    #[test]
+    fn should_parse_sink_specs() {
+        assert_eq!(
+            "stdout"
+                .parse::<SinkSpec>()
+                .unwrap(),
+            SinkSpec::Stdout
+        );
+        assert_eq!(
+            "jsonl:/var/log/changes.jsonl"
+                .parse::<SinkSpec>()
+                .unwrap(),
+            SinkSpec::Jsonl(PathBuf::from("/var/log/changes.jsonl"))
+        );
+        assert_eq!(
+            "webhook:http://127.0.0.1:8080/hook"
+                .parse::<SinkSpec>()
+                .unwrap(),
+            SinkSpec::Webhook(String::from("http://127.0.0.1:8080/hook"))
+        );
+        assert_eq!(
+            "webhook:127.0.0.1"
+                .parse::<SinkSpec>()
+                .err()
+                .unwrap()
+                .to_string(),
+            "Invalid change sink `webhook:127.0.0.1`, expected stdout, jsonl:<path> or webhook:<url>"
+        );
+    }
+
+    #[test]
+    fn should_append_change_events_as_json_lines() {
+        let path = std::env::temp_dir().join(format!(
+            "humble_port_scanner_changes_{}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let mut sink = JsonlFileSink::open(path.clone()).unwrap();
+        sink.publish(&change_events())
+            .unwrap();
+        sink.publish(&change_events()[..1])
+            .unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[1],
+            r#"{"detected_at":"2024-01-01T00:00:00Z","event":"port_opened","ip":"10.0.0.7","port":22,"service":"ssh"}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<ChangeEvent>(lines[2]).unwrap(),
+            change_events()[0]
+        );
+    }
+
+    #[test]
+    fn should_post_change_events_to_the_webhook() {
+        let received: Arc<Mutex<Vec<ChangeEvent>>> = Arc::default();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = runtime
+            .block_on(tokio::net::TcpListener::bind("127.0.0.1:0"))
+            .unwrap();
+        let webhook_addr = listener.local_addr().unwrap();
+
+        let router = Router::new()
+            .route(
+                "/hook",
+                post(
+                    |State(received): State<Arc<Mutex<Vec<ChangeEvent>>>>,
+                     Json(change_events): Json<Vec<ChangeEvent>>| async move {
+                        received
+                            .lock()
+                            .unwrap()
+                            .extend(change_events);
+                    },
+                ),
+            )
+            .with_state(received.clone());
+        runtime.spawn(async move { axum::serve(listener, router).await });
+
+        let mut sink = WebhookSink::new(format!("http://{}/hook", webhook_addr)).unwrap();
+        sink.publish(&change_events())
+            .unwrap();
+
+        assert_eq!(*received.lock().unwrap(), change_events());
+        assert!(WebhookSink::new(format!("http://{}/missing", webhook_addr))
+            .unwrap()
+            .publish(&change_events())
+            .is_err());
+    }
+}
@@ -0,0 +1,38 @@
+use std::net::Ipv4Addr;
+
+use futures::future::BoxFuture;
+
+use crate::{
+    models::{PortState, ProbeRoute, SourceBinding},
+    port_helpers,
+};
+
+/// Opens the connections the probes are made of, the scanner reaches the
+/// network through nothing else.
+pub trait Connector: Send + Sync {
+    /// Connects to `port` on `ip` along `route` and from `source`. A port that
+    /// never answers leaves the future pending, the caller times it out.
+    fn connect<'a>(
+        &'a self,
+        route: ProbeRoute,
+        source: &'a SourceBinding,
+        ip: Ipv4Addr,
+        port: u16,
+    ) -> BoxFuture<'a, anyhow::Result<PortState>>;
+}
+
+/// Probes over the network of this host, the connector scans use unless told otherwise.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NetworkConnector;
+
+impl Connector for NetworkConnector {
+    fn connect<'a>(
+        &'a self,
+        route: ProbeRoute,
+        source: &'a SourceBinding,
+        ip: Ipv4Addr,
+        port: u16,
+    ) -> BoxFuture<'a, anyhow::Result<PortState>> {
+        Box::pin(port_helpers::connect(route, source, ip, port))
+    }
+}
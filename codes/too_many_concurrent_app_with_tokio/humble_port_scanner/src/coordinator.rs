@@ -0,0 +1,848 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    net::{Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{bail, Context};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, BufReader, Lines},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, Notify},
+    task::JoinSet,
+};
+
+use crate::{
+    errors::AppErrors,
+    metrics_helpers::ScanMetrics,
+    models::{Exclusions, IpPortScanResult, ScanTarget, SubnetScanConfiguration},
+    port_helpers,
+    progress_helper::ScanProgressTracker,
+    scan_report::{ScanReport, ScanReportCollector},
+    target_helpers::RangeChunks,
+    task_supervisor::{TaskOutcome, TaskReport},
+    work_protocol::{self, CoordinatorMessage, WorkChunk, WorkerMessage, PROTOCOL_VERSION},
+};
+
+const PROGRESS_BAR_SIZE: u64 = 100;
+
+/// Splits the scan targets into chunks of hosts and hands them out to the
+/// workers connecting to its listener, one chunk per worker at a time.
+///
+/// The chunk of a worker that disconnects or goes silent for longer than the
+/// worker timeout goes back to the front of the queue and resumes after the
+/// results already received, so every probe is reported exactly once.
+pub struct Coordinator {
+    listener: TcpListener,
+    targets: Vec<ScanTarget>,
+    state: CoordinatorState,
+    scan_timeout: Duration,
+    worker_timeout: Duration,
+    metrics: Arc<ScanMetrics>,
+    result_observer: Option<mpsc::UnboundedSender<(ScanTarget, IpPortScanResult)>>,
+}
+
+struct SharedState {
+    state: Mutex<CoordinatorState>,
+    changed: Notify,
+    scan_timeout: Duration,
+    worker_timeout: Duration,
+    metrics: Arc<ScanMetrics>,
+    result_observer: Option<mpsc::UnboundedSender<(ScanTarget, IpPortScanResult)>>,
+}
+
+/// The chunks of a target not handed out yet, cut from its hosts on demand.
+struct TargetChunks {
+    config: SubnetScanConfiguration,
+    hosts: RangeChunks<Ipv4Addr>,
+    unassigned: u64,
+}
+
+struct CoordinatorState {
+    unassigned: VecDeque<TargetChunks>,
+    reassigned: VecDeque<WorkChunk>,
+    next_chunk_id: u64,
+    in_flight: HashMap<u64, WorkChunk>,
+    remaining_chunks: HashMap<ScanTarget, u64>,
+    reassignments: HashMap<ScanTarget, u32>,
+    failures: HashMap<ScanTarget, String>,
+    report_collector: ScanReportCollector,
+    scan_progress: ScanProgressTracker,
+}
+
+impl Coordinator {
+    /// `worker_timeout` must exceed `scan_timeout`, a worker waiting on a
+    /// filtered port would be taken for dead otherwise.
+    pub fn new(
+        listener: TcpListener,
+        configs: Vec<SubnetScanConfiguration>,
+        chunk_hosts: u64,
+        scan_timeout: Duration,
+        worker_timeout: Duration,
+    ) -> anyhow::Result<Self> {
+        if chunk_hosts == 0 {
+            bail!(AppErrors::InvalidCoordinatorConfigurationError {
+                reason: String::from("chunks need at least one host"),
+            })
+        }
+        if worker_timeout <= scan_timeout {
+            bail!(AppErrors::InvalidCoordinatorConfigurationError {
+                reason: format!(
+                    "worker timeout {:?} must be longer than the scan timeout {:?}",
+                    worker_timeout, scan_timeout
+                ),
+            })
+        }
+
+        // the source address and device are checked by the workers, where
+        // the probes are sent from.
+        for config in &configs {
+            port_helpers::validate_source_ports(&config.source)?;
+        }
+
+        let targets: Vec<ScanTarget> = configs
+            .iter()
+            .map(|config| config.target.clone())
+            .collect();
+        let mut unassigned = VecDeque::new();
+        let mut remaining_chunks = HashMap::new();
+        let mut scan_progress = ScanProgressTracker::new(PROGRESS_BAR_SIZE);
+        let mut report_collector = ScanReportCollector::new(targets.clone());
+
+        for config in configs {
+            let probes = config.probe_count();
+            scan_progress.initate_target_progress(&config.target, probes);
+            report_collector.plan(&config);
+
+            let hosts = config.hosts();
+            let chunks = hosts.len().div_ceil(chunk_hosts);
+            remaining_chunks.insert(config.target.clone(), chunks);
+            if chunks == 0 {
+                scan_progress.complete_progress(&config.target);
+                continue;
+            }
+
+            // chunks cover their hosts only, the excluded hosts are already left out.
+            let exclusions = Arc::new(Exclusions {
+                hosts: Default::default(),
+                ports: config.exclusions.ports.clone(),
+            });
+            unassigned.push_back(TargetChunks {
+                config: config.with_exclusions(exclusions),
+                hosts: hosts.into_chunks(chunk_hosts),
+                unassigned: chunks,
+            });
+        }
+
+        let state = CoordinatorState {
+            unassigned,
+            reassigned: VecDeque::new(),
+            next_chunk_id: 0,
+            in_flight: HashMap::new(),
+            remaining_chunks,
+            reassignments: HashMap::new(),
+            failures: HashMap::new(),
+            report_collector,
+            scan_progress,
+        };
+
+        Ok(Self {
+            listener,
+            targets,
+            state,
+            scan_timeout,
+            worker_timeout,
+            metrics: Arc::new(ScanMetrics::new()),
+            result_observer: None,
+        })
+    }
+
+    /// Counts the results of the workers in `metrics`, e.g. ones served on `/metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<ScanMetrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Receives every result of the workers along with its target, as it arrives.
+    pub fn with_result_observer(
+        mut self,
+        result_observer: mpsc::UnboundedSender<(ScanTarget, IpPortScanResult)>,
+    ) -> Self {
+        self.result_observer = Some(result_observer);
+        self
+    }
+
+    pub fn local_addr(&self) -> anyhow::Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Serves workers until every chunk is scanned and summarizes their results,
+    /// the chunks handed to another worker count as restarts of their target.
+    /// A target with a failed or unscanned chunk is reported as failed.
+    pub async fn run(self) -> ScanReport {
+        let started_at = SystemTime::now();
+        let run_start = Instant::now();
+        let shared = Arc::new(SharedState {
+            state: Mutex::new(self.state),
+            changed: Notify::new(),
+            scan_timeout: self.scan_timeout,
+            worker_timeout: self.worker_timeout,
+            metrics: self.metrics,
+            result_observer: self.result_observer,
+        });
+        let mut workers = JoinSet::new();
+
+        loop {
+            tokio::select! {
+                _ = shared.all_done() => break,
+                accepted = self.listener.accept() => match accepted {
+                    Ok((stream, peer)) => {
+                        let shared = shared.clone();
+                        workers.spawn(async move {
+                            if let Err(worker_error) = serve_worker(stream, shared).await {
+                                tracing::warn!(%peer, error = %format!("{:#}", worker_error), "worker lost");
+                            }
+                        });
+                    }
+                    Err(accept_error) => {
+                        tracing::warn!(error = %accept_error, "unable to accept a worker")
+                    }
+                },
+            }
+        }
+
+        // give the connected workers a chance to hear that the scan is done.
+        let _ = tokio::time::timeout(shared.worker_timeout, async {
+            while workers.join_next().await.is_some() {}
+        })
+        .await;
+        workers.abort_all();
+
+        let mut state = shared.state();
+        let task_reports = self
+            .targets
+            .iter()
+            .map(|target| TaskReport {
+                task_name: format!("scan_{}", target),
+                target: Some(target.clone()),
+                outcome: state.outcome(target),
+                restarts: state
+                    .reassignments
+                    .get(target)
+                    .copied()
+                    .unwrap_or_default(),
+            })
+            .collect();
+        let report_collector = std::mem::replace(
+            &mut state.report_collector,
+            ScanReportCollector::new(Vec::new()),
+        );
+
+        report_collector.finish(task_reports, started_at, run_start.elapsed())
+    }
+}
+
+impl CoordinatorState {
+    // chunks lost with their worker go first, they resume where the worker stopped.
+    fn take_chunk(&mut self, scan_timeout: Duration) -> Option<WorkChunk> {
+        if let Some(chunk) = self.reassigned.pop_front() {
+            return Some(chunk);
+        }
+
+        let target_chunks = self.unassigned.front_mut()?;
+        let hosts = target_chunks.hosts.next()?;
+        let chunk = WorkChunk {
+            id: self.next_chunk_id,
+            config: target_chunks
+                .config
+                .clone()
+                .with_target_hosts(hosts),
+            delivered: 0,
+            scan_timeout,
+        };
+        target_chunks.unassigned -= 1;
+        if target_chunks.unassigned == 0 {
+            self.unassigned.pop_front();
+        }
+        self.next_chunk_id += 1;
+
+        Some(chunk)
+    }
+
+    fn is_done(&self) -> bool {
+        self.unassigned.is_empty() && self.reassigned.is_empty() && self.in_flight.is_empty()
+    }
+
+    // counts a chunk out of its target, whether it was scanned or gave up.
+    fn finish_chunk(&mut self, chunk_id: u64) -> Option<ScanTarget> {
+        let target = self
+            .in_flight
+            .remove(&chunk_id)?
+            .config
+            .target;
+        let remaining = self
+            .remaining_chunks
+            .entry(target.clone())
+            .or_default();
+        *remaining = remaining.saturating_sub(1);
+        if *remaining == 0 {
+            tracing::info!(%target, "target scan finished");
+            self.scan_progress
+                .complete_progress(&target);
+        }
+
+        Some(target)
+    }
+
+    fn outcome(&self, target: &ScanTarget) -> TaskOutcome {
+        let remaining = self
+            .remaining_chunks
+            .get(target)
+            .copied()
+            .unwrap_or_default();
+
+        match self.failures.get(target) {
+            Some(reason) => TaskOutcome::Failed(reason.clone()),
+            None if remaining > 0 => {
+                TaskOutcome::Failed(format!("{} chunks were not scanned", remaining))
+            }
+            None => TaskOutcome::Completed,
+        }
+    }
+}
+
+impl SharedState {
+    fn state(&self) -> MutexGuard<'_, CoordinatorState> {
+        self.state
+            .lock()
+            .expect("coordinator state lock poisoned")
+    }
+
+    /// The next chunk to scan, waiting for a chunk of a lost worker while other
+    /// chunks are in flight. `None` once every chunk is done.
+    async fn next_chunk(&self) -> Option<WorkChunk> {
+        loop {
+            let changed = self.changed.notified();
+            tokio::pin!(changed);
+            // registered before looking at the state, so no change is missed.
+            changed.as_mut().enable();
+
+            {
+                let mut state = self.state();
+                if let Some(chunk) = state.take_chunk(self.scan_timeout) {
+                    state
+                        .in_flight
+                        .insert(chunk.id, chunk.clone());
+                    return Some(chunk);
+                }
+                if state.in_flight.is_empty() {
+                    return None;
+                }
+            }
+
+            changed.await;
+        }
+    }
+
+    async fn all_done(&self) {
+        loop {
+            let changed = self.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+
+            if self.state().is_done() {
+                return;
+            }
+
+            changed.await;
+        }
+    }
+
+    fn record(&self, chunk_id: u64, scan_result: IpPortScanResult) {
+        let state = &mut *self.state();
+        if let Some(chunk) = state.in_flight.get_mut(&chunk_id) {
+            chunk.delivered += 1;
+            let target = &chunk.config.target;
+            state
+                .report_collector
+                .record(target, &scan_result);
+            state
+                .scan_progress
+                .update_progress(target);
+            self.metrics
+                .result_received(&scan_result);
+            if let Some(result_observer) = &self.result_observer {
+                let _ = result_observer.send((target.clone(), scan_result));
+            }
+        }
+    }
+
+    fn complete(&self, chunk_id: u64) {
+        self.state().finish_chunk(chunk_id);
+        self.changed.notify_waiters();
+    }
+
+    // a chunk the worker could not scan is not handed out again, the same
+    // failure would most likely happen on the next worker.
+    fn fail(&self, chunk_id: u64, reason: String) {
+        let mut state = self.state();
+        if let Some(target) = state.finish_chunk(chunk_id) {
+            tracing::error!(%target, chunk = chunk_id, %reason, "chunk scan failed");
+            state
+                .failures
+                .entry(target)
+                .or_insert(format!("chunk {}: {}", chunk_id, reason));
+        }
+        drop(state);
+        self.changed.notify_waiters();
+    }
+
+    fn requeue(&self, chunk_id: u64) {
+        let mut state = self.state();
+        if let Some(chunk) = state.in_flight.remove(&chunk_id) {
+            *state
+                .reassignments
+                .entry(chunk.config.target.clone())
+                .or_default() += 1;
+            state.reassigned.push_front(chunk);
+        }
+        drop(state);
+        self.changed.notify_waiters();
+    }
+}
+
+async fn serve_worker(stream: TcpStream, shared: Arc<SharedState>) -> anyhow::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    let worker_name = match read_from_worker(&mut lines, shared.worker_timeout).await? {
+        WorkerMessage::Hello {
+            version,
+            worker_name,
+        } if version == PROTOCOL_VERSION => worker_name,
+        WorkerMessage::Hello { version, .. } => {
+            let reason = format!(
+                "protocol version {} is not supported, expected {}",
+                version, PROTOCOL_VERSION
+            );
+            work_protocol::send_message(
+                &mut writer,
+                &CoordinatorMessage::Rejected {
+                    reason: reason.clone(),
+                },
+            )
+            .await?;
+            bail!(reason)
+        }
+        message => bail!("Expected a hello from the worker, got {:?}", message),
+    };
+    tracing::info!(worker = %worker_name, "worker joined");
+
+    while let Some(chunk) = shared.next_chunk().await {
+        let chunk_id = chunk.id;
+        if let Err(worker_error) = scan_on_worker(chunk, &shared, &mut writer, &mut lines).await {
+            tracing::warn!(worker = %worker_name, chunk = chunk_id, "reassigning the chunk of a lost worker");
+            shared.requeue(chunk_id);
+            return Err(worker_error);
+        }
+    }
+
+    work_protocol::send_message(&mut writer, &CoordinatorMessage::Done).await
+}
+
+async fn scan_on_worker<R, W>(
+    chunk: WorkChunk,
+    shared: &SharedState,
+    writer: &mut W,
+    lines: &mut Lines<R>,
+) -> anyhow::Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let chunk_id = chunk.id;
+    work_protocol::send_message(
+        writer,
+        &CoordinatorMessage::Assign {
+            chunk: Box::new(chunk),
+        },
+    )
+    .await?;
+
+    loop {
+        match read_from_worker(lines, shared.worker_timeout).await? {
+            WorkerMessage::Result {
+                chunk_id: result_chunk_id,
+                result,
+            } if result_chunk_id == chunk_id => shared.record(chunk_id, result),
+            WorkerMessage::ChunkDone {
+                chunk_id: done_chunk_id,
+            } if done_chunk_id == chunk_id => {
+                shared.complete(chunk_id);
+                return Ok(());
+            }
+            WorkerMessage::ChunkFailed {
+                chunk_id: failed_chunk_id,
+                reason,
+            } if failed_chunk_id == chunk_id => {
+                shared.fail(chunk_id, reason);
+                return Ok(());
+            }
+            message => bail!("Unexpected message from the worker: {:?}", message),
+        }
+    }
+}
+
+async fn read_from_worker<R>(
+    lines: &mut Lines<R>,
+    worker_timeout: Duration,
+) -> anyhow::Result<WorkerMessage>
+where
+    R: AsyncBufRead + Unpin,
+{
+    match tokio::time::timeout(worker_timeout, work_protocol::read_message(lines))
+        .await
+        .context(format!("Worker silent for more than {:?}", worker_timeout))??
+    {
+        Some(message) => Ok(message),
+        None => bail!("Worker closed the connection"),
+    }
+}
+
+#[cfg(test)]
+mod coordinator_tests {
+    use std::{net::Ipv4Addr, sync::Arc, time::Duration};
+
+    use ipnet::Ipv4Net;
+    use tokio::{
+        io::{AsyncBufReadExt, BufReader},
+        net::{TcpListener, TcpStream},
+        sync::mpsc,
+    };
+
+    use crate::{
+        coordinator::Coordinator,
+        metrics_helpers::ScanMetrics,
+        models::{IpPortScanResult, PortState, SourceBinding, SubnetScanConfiguration},
+        simulated_network::{SimulatedLink, SimulatedNetwork},
+        task_supervisor::TaskOutcome,
+        work_protocol::{self, CoordinatorMessage, WorkerMessage, PROTOCOL_VERSION},
+        worker,
+    };
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn should_report_every_probe_once_despite_a_lost_worker() {
+        let config = SubnetScanConfiguration::new(
+            "127.0.0.0/28"
+                .parse::<Ipv4Net>()
+                .unwrap(),
+            41000,
+            41004,
+        );
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let coordinator = Coordinator::new(
+            listener,
+            vec![config],
+            2,
+            Duration::from_millis(500),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        let coordinator_addr = coordinator.local_addr().unwrap();
+        let coordinator_task = tokio::spawn(coordinator.run());
+
+        // takes a chunk, reports one probe of it and dies.
+        let stream = TcpStream::connect(coordinator_addr)
+            .await
+            .unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        work_protocol::send_message(
+            &mut writer,
+            &WorkerMessage::Hello {
+                version: PROTOCOL_VERSION,
+                worker_name: String::from("doomed"),
+            },
+        )
+        .await
+        .unwrap();
+        let Some(CoordinatorMessage::Assign { chunk }) = work_protocol::read_message(&mut lines)
+            .await
+            .unwrap()
+        else {
+            panic!("expected a chunk for the first worker");
+        };
+        work_protocol::send_message(
+            &mut writer,
+            &WorkerMessage::Result {
+                chunk_id: chunk.id,
+                result: IpPortScanResult {
+                    ip: chunk
+                        .config
+                        .hosts()
+                        .iter()
+                        .next()
+                        .unwrap(),
+                    port: 41000,
+                    state: PortState::Closed,
+                    hostname: None,
+                    service: None,
+                },
+            },
+        )
+        .await
+        .unwrap();
+        drop(writer);
+        drop(lines);
+
+        let network = Arc::new(
+            SimulatedNetwork::default()
+                .with_port(41002, SimulatedLink::accepting(Duration::from_millis(1))),
+        );
+        let workers: Vec<_> = (0..3)
+            .map(|index| {
+                tokio::spawn(worker::run_worker(
+                    coordinator_addr,
+                    format!("worker-{}", index),
+                    network.clone(),
+                ))
+            })
+            .collect();
+        let report = coordinator_task.await.unwrap();
+        let mut chunks_scanned = 0;
+        for worker in workers {
+            chunks_scanned += worker.await.unwrap().unwrap();
+        }
+
+        assert_eq!(report.total_probes(), 14 * 4);
+        assert_eq!(network.connection_attempts(), 14 * 4 - 1);
+        assert_eq!(report.hosts().count(), 14);
+        assert!(report
+            .hosts()
+            .all(|host| host.state_counts.total() == 4 && host.state_counts.open == 1));
+        assert_eq!(report.total_restarts(), 1);
+        assert!(!report.has_failures());
+        assert_eq!(chunks_scanned, 7);
+    }
+
+    #[tokio::test]
+    async fn should_fail_the_target_of_a_chunk_no_worker_could_scan() {
+        let config = SubnetScanConfiguration::new(
+            "127.0.0.0/30"
+                .parse::<Ipv4Net>()
+                .unwrap(),
+            41000,
+            41001,
+        );
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let metrics = Arc::new(ScanMetrics::new());
+        let (observer_tx, mut observer_rx) = mpsc::unbounded_channel();
+        let coordinator = Coordinator::new(
+            listener,
+            vec![config],
+            1,
+            Duration::from_millis(500),
+            Duration::from_secs(5),
+        )
+        .unwrap()
+        .with_metrics(metrics.clone())
+        .with_result_observer(observer_tx);
+        let coordinator_addr = coordinator.local_addr().unwrap();
+        let coordinator_task = tokio::spawn(coordinator.run());
+
+        // scans the first chunk and fails the second one.
+        let stream = TcpStream::connect(coordinator_addr)
+            .await
+            .unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        work_protocol::send_message(
+            &mut writer,
+            &WorkerMessage::Hello {
+                version: PROTOCOL_VERSION,
+                worker_name: String::from("flaky"),
+            },
+        )
+        .await
+        .unwrap();
+        for reply in ["done", "failed"] {
+            let Some(CoordinatorMessage::Assign { chunk }) =
+                work_protocol::read_message(&mut lines)
+                    .await
+                    .unwrap()
+            else {
+                panic!("expected a chunk");
+            };
+            let done = if reply == "done" {
+                work_protocol::send_message(
+                    &mut writer,
+                    &WorkerMessage::Result {
+                        chunk_id: chunk.id,
+                        result: IpPortScanResult {
+                            ip: chunk
+                                .config
+                                .hosts()
+                                .iter()
+                                .next()
+                                .unwrap(),
+                            port: 41000,
+                            state: PortState::Closed,
+                            hostname: None,
+                            service: None,
+                        },
+                    },
+                )
+                .await
+                .unwrap();
+                WorkerMessage::ChunkDone { chunk_id: chunk.id }
+            } else {
+                WorkerMessage::ChunkFailed {
+                    chunk_id: chunk.id,
+                    reason: String::from("boom"),
+                }
+            };
+            work_protocol::send_message(&mut writer, &done)
+                .await
+                .unwrap();
+        }
+        let done: Option<CoordinatorMessage> = work_protocol::read_message(&mut lines)
+            .await
+            .unwrap();
+        let report = coordinator_task.await.unwrap();
+
+        assert!(matches!(done, Some(CoordinatorMessage::Done)));
+        assert_eq!(
+            report.subnets[0].outcome,
+            Some(TaskOutcome::Failed(String::from("chunk 1: boom")))
+        );
+        assert!(report.has_failures());
+        assert_eq!(report.total_probes(), 1);
+        assert!(metrics
+            .encode()
+            .unwrap()
+            .contains("humble_port_scanner_scan_results_total{state=\"closed\"} 1"));
+        assert_eq!(
+            observer_rx
+                .try_recv()
+                .unwrap()
+                .1
+                .port,
+            41000
+        );
+        assert!(observer_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn should_fail_the_chunks_of_a_source_the_worker_does_not_have() {
+        let config = SubnetScanConfiguration::new(
+            "127.0.0.0/30"
+                .parse::<Ipv4Net>()
+                .unwrap(),
+            41000,
+            41001,
+        )
+        .with_source(Arc::new(SourceBinding {
+            address: Some(Ipv4Addr::new(192, 0, 2, 1)),
+            ..SourceBinding::default()
+        }));
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let coordinator = Coordinator::new(
+            listener,
+            vec![config],
+            4,
+            Duration::from_millis(500),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        let coordinator_addr = coordinator.local_addr().unwrap();
+        let coordinator_task = tokio::spawn(coordinator.run());
+
+        let network = Arc::new(SimulatedNetwork::default());
+        let chunks_scanned =
+            worker::run_worker(coordinator_addr, String::from("elsewhere"), network.clone())
+                .await
+                .unwrap();
+        let report = coordinator_task.await.unwrap();
+
+        assert_eq!(chunks_scanned, 1);
+        assert_eq!(
+            report.subnets[0].outcome,
+            Some(TaskOutcome::Failed(String::from(
+                "chunk 0: Source address 192.0.2.1 is not assigned to a local interface"
+            )))
+        );
+        assert_eq!(report.total_probes(), 0);
+        assert_eq!(network.connection_attempts(), 0);
+    }
+
+    #[tokio::test]
+    async fn should_refuse_an_empty_source_port_range() {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let config = SubnetScanConfiguration::new(
+            "127.0.0.0/30"
+                .parse::<Ipv4Net>()
+                .unwrap(),
+            1,
+            2,
+        )
+        .with_source(Arc::new(SourceBinding {
+            ports: Some((47100, 47100)),
+            ..SourceBinding::default()
+        }));
+
+        let coordinator = Coordinator::new(
+            listener,
+            vec![config],
+            2,
+            Duration::from_secs(1),
+            Duration::from_secs(2),
+        );
+
+        assert_eq!(
+            coordinator
+                .err()
+                .unwrap()
+                .to_string(),
+            "Source port range 47100:47100 is empty"
+        );
+    }
+
+    #[tokio::test]
+    async fn should_refuse_a_worker_timeout_below_the_scan_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let config = SubnetScanConfiguration::new(
+            "127.0.0.0/30"
+                .parse::<Ipv4Net>()
+                .unwrap(),
+            1,
+            2,
+        );
+
+        let coordinator = Coordinator::new(
+            listener,
+            vec![config],
+            2,
+            Duration::from_secs(2),
+            Duration::from_secs(1),
+        );
+
+        assert_eq!(
+            coordinator
+                .err()
+                .unwrap()
+                .to_string(),
+            "Invalid coordinator configuration: worker timeout 1s must be longer than the scan timeout 2s"
+        );
+    }
+}
@@ -1,12 +1,53 @@
 use thiserror::Error;
 use tokio::sync::mpsc::error::SendError;
 
-use crate::models::IpPortScanResult;
+use crate::{guardrails::AddressClass, models::IpPortScanResult};
 
 #[derive(Error, Debug)]
 pub enum AppErrors {
-    #[error("A Tokio Runtime must be provided to setup the App")]
+    #[error("A Tokio Runtime or a runtime configuration must be provided to setup the App")]
     NoRuntimeProvidedError,
+    #[error("Scan result channel capacity must be greater than zero")]
+    InvalidChannelCapacityError,
+    #[error("Invalid runtime configuration: {reason}")]
+    InvalidRuntimeConfigurationError { reason: String },
+    #[error("Invalid range set: {reason}")]
+    InvalidRangeSetError { reason: String },
+    #[error("Invalid coordinator configuration: {reason}")]
+    InvalidCoordinatorConfigurationError { reason: String },
+    #[error("Invalid change sink `{sink}`, expected stdout, jsonl:<path> or webhook:<url>")]
+    InvalidSinkError { sink: String },
+    #[error("Invalid policy rule `{rule}`: {reason}")]
+    InvalidPolicyRuleError { rule: String, reason: String },
+    #[error("Invalid shard `{shard}`, expected <index>/<count> with 1 <= index <= count")]
+    InvalidShardError { shard: String },
+    #[error("Invalid proxy `{proxy}`, expected socks5://<ip>:<port> or http://<ip>:<port>")]
+    InvalidProxyError { proxy: String },
+    #[error("Invalid service entry `{entry}`, expected <name> <port>/<protocol> [aliases]")]
+    InvalidServiceEntryError { entry: String },
+    #[error("Unknown service `{service}`, expected a port number or a known service name")]
+    UnknownServiceError { service: String },
+    #[error("Scan of {probes} probes is above the confirmation threshold of {threshold}, confirm it with --i-know")]
+    UnconfirmedScanError { probes: u64, threshold: u64 },
+    #[error("Target {target} reaches {class} address {address}, allowlist it to scan it")]
+    GuardedAddressError {
+        target: String,
+        address: std::net::Ipv4Addr,
+        class: AddressClass,
+    },
+    #[error("Target {target} reaches {address}, outside the organization allowlist")]
+    OutsideOrganizationAllowlistError {
+        target: String,
+        address: std::net::Ipv4Addr,
+    },
+    #[error("Source address {address} is not assigned to a local interface")]
+    SourceAddressNotLocalError { address: std::net::Ipv4Addr },
+    #[error("Scan history schema version {found} is newer than the supported version {supported}")]
+    UnsupportedHistorySchemaError { found: usize, supported: usize },
+    #[error("Scan history schema version {found} is older than {supported}, record a scan to migrate it")]
+    OutdatedHistorySchemaError { found: usize, supported: usize },
+    #[error("No IPv4 address found for host {hostname}")]
+    NoIpv4AddressError { hostname: String },
     #[error("Unable to send scan result {result:?} over tokio channel {channel}")]
     IpScanResultChannelSendError {
         channel: String,
@@ -0,0 +1,411 @@
+use std::{fmt, net::Ipv4Addr};
+
+use anyhow::bail;
+use ipnet::Ipv4Net;
+
+use crate::{
+    errors::AppErrors,
+    models::{ScanTarget, SubnetScanConfiguration},
+    target_helpers::RangeSet,
+};
+
+/// Probes a scan may make before it has to be confirmed.
+pub const DEFAULT_CONFIRMATION_THRESHOLD: u64 = 65_536;
+/// Organization allowlist enforced when present, along with any other allowlist file.
+pub const ORGANIZATION_ALLOWLIST_PATH: &str = "/etc/humble_port_scanner/allowlist";
+
+// ranges scanned without being allowlisted, none of them is routed on the internet.
+const PRIVATE_RANGES: [&str; 6] = [
+    "10.0.0.0/8",
+    "100.64.0.0/10",
+    "127.0.0.0/8",
+    "169.254.0.0/16",
+    "172.16.0.0/12",
+    "192.168.0.0/16",
+];
+// reserved ranges that should never show up as real hosts, multicast and
+// broadcast excepted.
+const BOGON_RANGES: [&str; 7] = [
+    "0.0.0.0/8",
+    "192.0.0.0/24",
+    "192.0.2.0/24",
+    "198.18.0.0/15",
+    "198.51.100.0/24",
+    "203.0.113.0/24",
+    "240.0.0.0/4",
+];
+
+/// Kind of addresses the guardrails tell apart.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AddressClass {
+    Private,
+    Public,
+    Bogon,
+    Multicast,
+    Broadcast,
+}
+
+impl AddressClass {
+    pub fn of(ip: Ipv4Addr) -> Self {
+        if ip.is_broadcast() {
+            AddressClass::Broadcast
+        } else if ip.is_multicast() {
+            AddressClass::Multicast
+        } else if ranges(&PRIVATE_RANGES).contains(ip) {
+            AddressClass::Private
+        } else if ranges(&BOGON_RANGES).contains(ip) {
+            AddressClass::Bogon
+        } else {
+            AddressClass::Public
+        }
+    }
+
+    /// Class of `ip` as an address of `target`, whose own broadcast address
+    /// is a broadcast address.
+    pub fn in_target(ip: Ipv4Addr, target: &ScanTarget) -> Self {
+        if subnet_broadcast(target) == Some(ip) {
+            AddressClass::Broadcast
+        } else {
+            AddressClass::of(ip)
+        }
+    }
+}
+
+// point-to-point /31s and /32s have no broadcast address.
+fn subnet_broadcast(target: &ScanTarget) -> Option<Ipv4Addr> {
+    match target {
+        ScanTarget::Subnet(subnet) if subnet.prefix_len() < 31 => Some(subnet.broadcast()),
+        _ => None,
+    }
+}
+
+impl fmt::Display for AddressClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressClass::Private => write!(f, "private"),
+            AddressClass::Public => write!(f, "public"),
+            AddressClass::Bogon => write!(f, "bogon"),
+            AddressClass::Multicast => write!(f, "multicast"),
+            AddressClass::Broadcast => write!(f, "broadcast"),
+        }
+    }
+}
+
+fn ranges(cidrs: &[&str]) -> RangeSet<Ipv4Addr> {
+    cidrs
+        .iter()
+        .map(|cidr| {
+            let subnet = cidr
+                .parse::<Ipv4Net>()
+                .expect("guardrail ranges are valid CIDRs");
+            subnet.network()..=subnet.broadcast()
+        })
+        .collect()
+}
+
+/// Limits every scan is checked against before its first probe.
+///
+/// Only private ranges are scanned unless allowlisted, the broadcast address of
+/// a subnet included, and organization allowlists, when set, restrict every
+/// scan to the ranges they all share whatever else is allowlisted. Scans of more probes than the confirmation threshold must be
+/// confirmed.
+#[derive(Debug, Clone)]
+pub struct Guardrails {
+    confirmation_threshold: u64,
+    confirmed: bool,
+    allowlist: RangeSet<Ipv4Addr>,
+    organization_allowlist: Option<RangeSet<Ipv4Addr>>,
+}
+
+impl Default for Guardrails {
+    fn default() -> Self {
+        Self::new(DEFAULT_CONFIRMATION_THRESHOLD)
+    }
+}
+
+impl Guardrails {
+    pub fn new(confirmation_threshold: u64) -> Self {
+        Self {
+            confirmation_threshold,
+            confirmed: false,
+            allowlist: RangeSet::new(),
+            organization_allowlist: None,
+        }
+    }
+
+    pub fn with_confirmation(mut self, confirmed: bool) -> Self {
+        self.confirmed = confirmed;
+        self
+    }
+
+    /// Public, bogon, multicast or broadcast addresses that may be scanned.
+    pub fn with_allowlist(mut self, allowlist: RangeSet<Ipv4Addr>) -> Self {
+        self.allowlist = allowlist;
+        self
+    }
+
+    /// Restricts every scan to `organization_allowlist`, on top of the
+    /// organization allowlists already set.
+    pub fn with_organization_allowlist(
+        mut self,
+        organization_allowlist: RangeSet<Ipv4Addr>,
+    ) -> Self {
+        self.organization_allowlist = Some(match self.organization_allowlist {
+            Some(allowlist) => allowlist.intersection(&organization_allowlist),
+            None => organization_allowlist,
+        });
+        self
+    }
+
+    /// Checks the scan of `configs` and returns its probe count, the
+    /// excluded hosts and ports are left out of both. A sharded scan counts
+    /// the probes of every shard.
+    pub fn check(&self, configs: &[SubnetScanConfiguration]) -> anyhow::Result<u64> {
+        for config in configs {
+            let hosts = config.hosts();
+
+            if let Some(organization_allowlist) = &self.organization_allowlist {
+                if let Some(address) = hosts
+                    .difference(organization_allowlist)
+                    .iter()
+                    .next()
+                {
+                    bail!(AppErrors::OutsideOrganizationAllowlistError {
+                        target: config.target.to_string(),
+                        address,
+                    })
+                }
+            }
+
+            let mut guarded = hosts.difference(&ranges(&PRIVATE_RANGES));
+            if let Some(broadcast) = subnet_broadcast(&config.target) {
+                if hosts.contains(broadcast) {
+                    guarded.insert(broadcast..=broadcast);
+                }
+            }
+            if let Some(address) = guarded
+                .difference(&self.allowlist)
+                .iter()
+                .next()
+            {
+                bail!(AppErrors::GuardedAddressError {
+                    target: config.target.to_string(),
+                    address,
+                    class: AddressClass::in_target(address, &config.target),
+                })
+            }
+        }
+
+        let probes = configs
+            .iter()
+            .map(SubnetScanConfiguration::total_probe_count)
+            .sum();
+        if probes > self.confirmation_threshold && !self.confirmed {
+            bail!(AppErrors::UnconfirmedScanError {
+                probes,
+                threshold: self.confirmation_threshold,
+            })
+        }
+
+        Ok(probes)
+    }
+}
+
+#[cfg(test)]
+mod guardrails_tests {
+    use std::net::Ipv4Addr;
+
+    use ipnet::Ipv4Net;
+
+    use crate::{
+        guardrails::{AddressClass, Guardrails},
+        models::{HostAddressMode, ScanTarget, SubnetScanConfiguration},
+        target_helpers::RangeSet,
+    };
+
+    fn configs(subnets: &[&str], end_port: u16) -> Vec<SubnetScanConfiguration> {
+        subnets
+            .iter()
+            .map(|subnet| {
+                SubnetScanConfiguration::new(subnet.parse::<Ipv4Net>().unwrap(), 1, end_port)
+            })
+            .collect()
+    }
+
+    fn range_set(cidr: &str) -> RangeSet<Ipv4Addr> {
+        let subnet = cidr.parse::<Ipv4Net>().unwrap();
+        std::iter::once(subnet.network()..=subnet.broadcast()).collect()
+    }
+
+    #[test]
+    fn should_classify_addresses() {
+        let classes: Vec<AddressClass> = [
+            "10.1.2.3",
+            "100.64.0.1",
+            "127.0.0.1",
+            "8.8.8.8",
+            "0.0.0.0",
+            "203.0.113.7",
+            "240.0.0.1",
+            "224.0.0.251",
+            "255.255.255.255",
+        ]
+        .iter()
+        .map(|ip| AddressClass::of(ip.parse().unwrap()))
+        .collect();
+
+        assert_eq!(
+            classes,
+            vec![
+                AddressClass::Private,
+                AddressClass::Private,
+                AddressClass::Private,
+                AddressClass::Public,
+                AddressClass::Bogon,
+                AddressClass::Bogon,
+                AddressClass::Bogon,
+                AddressClass::Multicast,
+                AddressClass::Broadcast,
+            ]
+        );
+    }
+
+    #[test]
+    fn should_refuse_ranges_that_are_not_private_unless_allowlisted() {
+        let guardrails = Guardrails::default().with_confirmation(true);
+
+        for (subnet, message) in [
+            (
+                "0.0.0.0/0",
+                "Target 0.0.0.0/0 reaches bogon address 0.0.0.1, allowlist it to scan it",
+            ),
+            (
+                "8.8.8.0/24",
+                "Target 8.8.8.0/24 reaches public address 8.8.8.1, allowlist it to scan it",
+            ),
+            (
+                "224.0.0.251/32",
+                "Target 224.0.0.251/32 reaches multicast address 224.0.0.251, allowlist it to scan it",
+            ),
+            (
+                "255.255.255.255/32",
+                "Target 255.255.255.255/32 reaches broadcast address 255.255.255.255, allowlist it to scan it",
+            ),
+        ] {
+            assert_eq!(
+                guardrails
+                    .check(&configs(&[subnet], 2))
+                    .err()
+                    .unwrap()
+                    .to_string(),
+                message
+            );
+        }
+
+        assert_eq!(
+            guardrails
+                .clone()
+                .with_allowlist(range_set("8.8.8.0/24"))
+                .check(&configs(&["8.8.8.0/24", "192.168.1.0/24"], 2))
+                .unwrap(),
+            508
+        );
+    }
+
+    #[test]
+    fn should_ask_for_a_confirmation_above_the_threshold() {
+        let scan = configs(&["10.0.0.0/24", "10.0.1.0/24"], 129);
+        let guardrails = Guardrails::new(65_000);
+
+        assert_eq!(
+            guardrails
+                .check(&scan)
+                .err()
+                .unwrap()
+                .to_string(),
+            "Scan of 65024 probes is above the confirmation threshold of 65000, confirm it with --i-know"
+        );
+        assert_eq!(
+            guardrails
+                .with_confirmation(true)
+                .check(&scan)
+                .unwrap(),
+            65024
+        );
+        assert_eq!(
+            Guardrails::new(65_024)
+                .check(&scan)
+                .unwrap(),
+            65024
+        );
+
+        // every shard is a share of the same scan.
+        let first_shard: Vec<SubnetScanConfiguration> = scan
+            .into_iter()
+            .map(|config| config.with_shard("1/2".parse().unwrap()))
+            .collect();
+        assert!(Guardrails::new(65_000)
+            .check(&first_shard)
+            .is_err());
+    }
+
+    #[test]
+    fn should_guard_the_broadcast_address_of_a_subnet() {
+        let subnet = "10.0.0.0/24"
+            .parse::<Ipv4Net>()
+            .unwrap();
+        let every_address = vec![SubnetScanConfiguration::new(subnet, 1, 2)
+            .with_target_hosts(ScanTarget::Subnet(subnet).hosts_with(HostAddressMode::All))];
+        let guardrails = Guardrails::default();
+
+        assert_eq!(
+            AddressClass::in_target(Ipv4Addr::new(10, 0, 0, 255), &ScanTarget::Subnet(subnet)),
+            AddressClass::Broadcast
+        );
+        assert_eq!(
+            guardrails
+                .check(&every_address)
+                .err()
+                .unwrap()
+                .to_string(),
+            "Target 10.0.0.0/24 reaches broadcast address 10.0.0.255, allowlist it to scan it"
+        );
+        assert_eq!(
+            guardrails
+                .with_allowlist(range_set("10.0.0.255/32"))
+                .check(&every_address)
+                .unwrap(),
+            256
+        );
+        assert!(Guardrails::default()
+            .check(&configs(&["10.0.0.0/24", "10.0.1.0/31"], 2))
+            .is_ok());
+    }
+
+    #[test]
+    fn should_keep_every_scan_within_the_organization_allowlist() {
+        let guardrails = Guardrails::default()
+            .with_allowlist(range_set("8.8.8.0/24"))
+            .with_organization_allowlist(range_set("10.0.0.0/16"));
+
+        assert_eq!(
+            guardrails
+                .check(&configs(&["10.0.3.0/24"], 2))
+                .unwrap(),
+            254
+        );
+        for (subnet, address) in [("10.1.0.0/24", "10.1.0.1"), ("8.8.8.8/32", "8.8.8.8")] {
+            assert_eq!(
+                guardrails
+                    .check(&configs(&[subnet], 2))
+                    .err()
+                    .unwrap()
+                    .to_string(),
+                format!(
+                    "Target {} reaches {}, outside the organization allowlist",
+                    subnet, address
+                )
+            );
+        }
+    }
+}
@@ -0,0 +1,598 @@
+use std::{
+    fmt,
+    net::Ipv4Addr,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Context};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension, Row};
+use serde::Serialize;
+
+use crate::{errors::AppErrors, models::PortState, scan_report::ScanReport};
+
+/// Schema changes, applied in order. The schema version stored in the
+/// database is the number of migrations applied, so entries are only ever
+/// appended.
+const MIGRATIONS: &[&str] = &[
+    // 1: scans, the hosts they covered and the state of every port they probed.
+    "CREATE TABLE scans (
+        id INTEGER PRIMARY KEY,
+        started_at INTEGER NOT NULL,
+        duration_ms INTEGER NOT NULL,
+        total_probes INTEGER NOT NULL
+    );
+    CREATE TABLE hosts (
+        scan_id INTEGER NOT NULL REFERENCES scans (id) ON DELETE CASCADE,
+        target TEXT NOT NULL,
+        ip TEXT NOT NULL,
+        hostname TEXT,
+        open INTEGER NOT NULL,
+        closed INTEGER NOT NULL,
+        timeout INTEGER NOT NULL,
+        PRIMARY KEY (scan_id, target, ip)
+    );
+    CREATE TABLE ports (
+        scan_id INTEGER NOT NULL REFERENCES scans (id) ON DELETE CASCADE,
+        ip TEXT NOT NULL,
+        port INTEGER NOT NULL,
+        state TEXT NOT NULL,
+        service TEXT,
+        PRIMARY KEY (scan_id, ip, port)
+    );
+    CREATE INDEX ports_by_port ON ports (port, state);
+    CREATE INDEX hosts_by_ip ON hosts (ip, scan_id);",
+];
+
+/// Every scan recorded in a SQLite database, to answer questions about how
+/// the exposure changed over time.
+pub struct ScanHistory {
+    connection: Connection,
+}
+
+/// When a port of a host was seen open.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PortSighting {
+    pub ip: Ipv4Addr,
+    pub port: u16,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+    /// Recorded scans that found the port open.
+    pub scans: u64,
+}
+
+/// A host whose latest recorded scan found the port open.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Exposure {
+    pub ip: Ipv4Addr,
+    pub hostname: Option<String>,
+    pub port: u16,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+}
+
+impl ScanHistory {
+    /// Opens the database at `path`, creating it and migrating its schema as needed.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let connection = Connection::open(path)
+            .context(format!("Unable to open scan history {}", path.display()))?;
+        Self::with_connection(connection)
+    }
+
+    /// Opens the existing database at `path` for queries only, it is neither
+    /// created nor migrated.
+    pub fn open_read_only(path: &Path) -> anyhow::Result<Self> {
+        let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .context(format!("Unable to open scan history {}", path.display()))?;
+        let history = Self { connection };
+        let schema_version = history.schema_version()?;
+        if schema_version > MIGRATIONS.len() {
+            bail!(AppErrors::UnsupportedHistorySchemaError {
+                found: schema_version,
+                supported: MIGRATIONS.len(),
+            })
+        }
+        if schema_version < MIGRATIONS.len() {
+            bail!(AppErrors::OutdatedHistorySchemaError {
+                found: schema_version,
+                supported: MIGRATIONS.len(),
+            })
+        }
+        Ok(history)
+    }
+
+    pub fn open_in_memory() -> anyhow::Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(connection: Connection) -> anyhow::Result<Self> {
+        let mut history = Self { connection };
+        history
+            .connection
+            .pragma_update(None, "foreign_keys", true)?;
+        history.migrate()?;
+        Ok(history)
+    }
+
+    pub fn schema_version(&self) -> anyhow::Result<usize> {
+        Ok(self
+            .connection
+            .pragma_query_value(None, "user_version", |row| row.get(0))?)
+    }
+
+    fn migrate(&mut self) -> anyhow::Result<()> {
+        let schema_version = self.schema_version()?;
+        if schema_version > MIGRATIONS.len() {
+            bail!(AppErrors::UnsupportedHistorySchemaError {
+                found: schema_version,
+                supported: MIGRATIONS.len(),
+            })
+        }
+
+        for (applied, migration) in MIGRATIONS
+            .iter()
+            .enumerate()
+            .skip(schema_version)
+        {
+            let transaction = self.connection.transaction()?;
+            transaction
+                .execute_batch(migration)
+                .context(format!(
+                    "Unable to migrate the scan history to version {}",
+                    applied + 1
+                ))?;
+            transaction.pragma_update(None, "user_version", applied + 1)?;
+            transaction.commit()?;
+        }
+
+        Ok(())
+    }
+
+    /// Stores the hosts of `scan_report` and the state of every port probed on
+    /// them, returns the id of the scan.
+    pub fn record(&mut self, scan_report: &ScanReport) -> anyhow::Result<i64> {
+        let transaction = self.connection.transaction()?;
+        transaction
+            .execute(
+                "INSERT INTO scans (started_at, duration_ms, total_probes) VALUES (?1, ?2, ?3)",
+                params![
+                    unix_seconds(scan_report.started_at),
+                    scan_report.duration.as_millis() as i64,
+                    scan_report.total_probes() as i64,
+                ],
+            )
+            .context("Unable to record the scan in the history")?;
+        let scan_id = transaction.last_insert_rowid();
+
+        // a host in several targets of a scan is a single host of the scan.
+        {
+            let mut insert_host = transaction.prepare(
+                "INSERT OR IGNORE INTO hosts (scan_id, target, ip, hostname, open, closed, timeout)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?;
+            let mut insert_port = transaction.prepare(
+                "INSERT OR IGNORE INTO ports (scan_id, ip, port, state, service)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+
+            for subnet in &scan_report.subnets {
+                for host in &subnet.hosts {
+                    insert_host.execute(params![
+                        scan_id,
+                        subnet.target.to_string(),
+                        host.ip.to_string(),
+                        host.hostname,
+                        host.state_counts.open as i64,
+                        host.state_counts.closed as i64,
+                        host.state_counts.timeout as i64,
+                    ])?;
+                    let ip = host.ip.to_string();
+                    for open_port in &host.open_ports {
+                        insert_port.execute(params![
+                            scan_id,
+                            ip,
+                            open_port.port,
+                            state_name(PortState::Open),
+                            open_port.service,
+                        ])?;
+                    }
+                    for (ports, state) in [
+                        (&host.closed_ports, PortState::Closed),
+                        (&host.timed_out_ports, PortState::TimeOut),
+                    ] {
+                        for port in ports.iter() {
+                            insert_port.execute(params![
+                                scan_id,
+                                ip,
+                                port,
+                                state_name(state),
+                                None::<String>,
+                            ])?;
+                        }
+                    }
+                }
+            }
+        }
+
+        transaction.commit()?;
+        Ok(scan_id)
+    }
+
+    /// When `port` on `ip` was first and last seen open, `None` if it never was.
+    pub fn port_sighting(&self, ip: Ipv4Addr, port: u16) -> anyhow::Result<Option<PortSighting>> {
+        let sighting = self
+            .connection
+            .query_row(
+                "SELECT MIN(scans.started_at), MAX(scans.started_at), COUNT(*)
+                FROM ports JOIN scans ON scans.id = ports.scan_id
+                WHERE ports.ip = ?1 AND ports.port = ?2 AND ports.state = 'open'
+                HAVING COUNT(*) > 0",
+                params![ip.to_string(), port],
+                |row| {
+                    Ok(PortSighting {
+                        ip,
+                        port,
+                        first_seen: system_time(row, 0)?,
+                        last_seen: system_time(row, 1)?,
+                        scans: row.get(2)?,
+                    })
+                },
+            )
+            .optional()?;
+
+        Ok(sighting)
+    }
+
+    /// Hosts whose latest recorded scan of `port` found it open, ordered by
+    /// address. Scans that did not probe the port on a host say nothing about it,
+    /// and scans are ordered by start time, whatever order they were recorded in.
+    pub fn exposing(&self, port: u16) -> anyhow::Result<Vec<Exposure>> {
+        let mut statement = self.connection.prepare(
+            "WITH latest AS (
+                SELECT ip, scan_id FROM (
+                    SELECT ports.ip, ports.scan_id, ROW_NUMBER() OVER (
+                        PARTITION BY ports.ip ORDER BY scans.started_at DESC, ports.scan_id DESC
+                    ) AS recency
+                    FROM ports JOIN scans ON scans.id = ports.scan_id
+                    WHERE ports.port = ?1
+                )
+                WHERE recency = 1
+            )
+            SELECT ports.ip, MAX(hosts.hostname), scans.started_at,
+                (SELECT MIN(first_scans.started_at)
+                FROM ports AS first_ports JOIN scans AS first_scans
+                    ON first_scans.id = first_ports.scan_id
+                WHERE first_ports.ip = ports.ip AND first_ports.port = ports.port
+                    AND first_ports.state = 'open')
+            FROM latest
+            JOIN ports ON ports.scan_id = latest.scan_id AND ports.ip = latest.ip
+            JOIN hosts ON hosts.scan_id = ports.scan_id AND hosts.ip = ports.ip
+            JOIN scans ON scans.id = ports.scan_id
+            WHERE ports.port = ?1 AND ports.state = 'open'
+            GROUP BY ports.ip",
+        )?;
+
+        let mut exposures = statement
+            .query_map(params![port], |row| {
+                let ip: String = row.get(0)?;
+                Ok(Exposure {
+                    ip: ip.parse().map_err(|parse_error| {
+                        rusqlite::Error::FromSqlConversionFailure(
+                            0,
+                            rusqlite::types::Type::Text,
+                            Box::new(parse_error),
+                        )
+                    })?,
+                    hostname: row.get(1)?,
+                    port,
+                    last_seen: system_time(row, 2)?,
+                    first_seen: system_time(row, 3)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        exposures.sort_by_key(|exposure| exposure.ip);
+
+        Ok(exposures)
+    }
+}
+
+fn state_name(state: PortState) -> &'static str {
+    match state {
+        PortState::Open => "open",
+        PortState::Closed => "closed",
+        PortState::TimeOut => "timeout",
+    }
+}
+
+fn unix_seconds(time: SystemTime) -> i64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs() as i64)
+}
+
+fn system_time(row: &Row, index: usize) -> rusqlite::Result<SystemTime> {
+    let seconds: i64 = row.get(index)?;
+    Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds.max(0) as u64))
+}
+
+impl fmt::Display for PortSighting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{} first seen open {}, last seen open {}, open in {} scan(s)",
+            self.ip,
+            self.port,
+            humantime::format_rfc3339_seconds(self.first_seen),
+            humantime::format_rfc3339_seconds(self.last_seen),
+            self.scans
+        )
+    }
+}
+
+impl fmt::Display for Exposure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let host = match &self.hostname {
+            Some(hostname) => format!("{} ({})", self.ip, hostname),
+            None => self.ip.to_string(),
+        };
+        write!(
+            f,
+            "{:<40} port {:<6} open since {}, last seen {}",
+            host,
+            self.port,
+            humantime::format_rfc3339_seconds(self.first_seen),
+            humantime::format_rfc3339_seconds(self.last_seen)
+        )
+    }
+}
+
+#[cfg(test)]
+mod history_tests {
+    use std::{
+        net::Ipv4Addr,
+        time::{Duration, SystemTime},
+    };
+
+    use crate::{
+        history::{ScanHistory, MIGRATIONS},
+        models::{IpPortScanResult, PortState, ScanTarget},
+        scan_report::{ScanReport, ScanReportCollector},
+        services::ServiceRegistry,
+    };
+
+    fn scan_report(started_at: u64, results: &[([u8; 4], u16, PortState)]) -> ScanReport {
+        let target = ScanTarget::Subnet("10.0.0.0/29".parse().unwrap());
+        let mut collector = ScanReportCollector::new([target.clone()]);
+        let services = ServiceRegistry::embedded();
+        for (ip, port, state) in results {
+            collector.record(
+                &target,
+                &IpPortScanResult {
+                    ip: Ipv4Addr::from(*ip),
+                    port: *port,
+                    state: *state,
+                    hostname: None,
+                    service: services.name(*port),
+                },
+            );
+        }
+
+        collector.finish(
+            Vec::new(),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(started_at),
+            Duration::from_secs(1),
+        )
+    }
+
+    fn at(seconds: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn should_tell_when_a_port_was_first_seen_open() {
+        let mut history = ScanHistory::open_in_memory().unwrap();
+        history
+            .record(&scan_report(100, &[([10, 0, 0, 5], 22, PortState::Closed)]))
+            .unwrap();
+        history
+            .record(&scan_report(200, &[([10, 0, 0, 5], 22, PortState::Open)]))
+            .unwrap();
+        history
+            .record(&scan_report(300, &[([10, 0, 0, 5], 22, PortState::Open)]))
+            .unwrap();
+
+        let sighting = history
+            .port_sighting(Ipv4Addr::new(10, 0, 0, 5), 22)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(sighting.first_seen, at(200));
+        assert_eq!(sighting.last_seen, at(300));
+        assert_eq!(sighting.scans, 2);
+        assert_eq!(
+            history
+                .port_sighting(Ipv4Addr::new(10, 0, 0, 5), 23)
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn should_list_the_hosts_whose_latest_scan_exposes_a_port() {
+        let mut history = ScanHistory::open_in_memory().unwrap();
+        history
+            .record(&scan_report(
+                100,
+                &[
+                    ([10, 0, 0, 3], 6379, PortState::Open),
+                    ([10, 0, 0, 4], 6379, PortState::Open),
+                ],
+            ))
+            .unwrap();
+        // .4 closed the port, .6 opened it since.
+        history
+            .record(&scan_report(
+                200,
+                &[
+                    ([10, 0, 0, 4], 6379, PortState::Closed),
+                    ([10, 0, 0, 6], 6379, PortState::Open),
+                ],
+            ))
+            .unwrap();
+
+        let exposures = history.exposing(6379).unwrap();
+
+        let exposed: Vec<(Ipv4Addr, SystemTime, SystemTime)> = exposures
+            .iter()
+            .map(|exposure| (exposure.ip, exposure.first_seen, exposure.last_seen))
+            .collect();
+        assert_eq!(
+            exposed,
+            vec![
+                (Ipv4Addr::new(10, 0, 0, 3), at(100), at(100)),
+                (Ipv4Addr::new(10, 0, 0, 6), at(200), at(200)),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_take_the_latest_scan_by_start_time() {
+        let mut history = ScanHistory::open_in_memory().unwrap();
+        // an older report imported after a newer one.
+        history
+            .record(&scan_report(
+                200,
+                &[([10, 0, 0, 3], 6379, PortState::Closed)],
+            ))
+            .unwrap();
+        history
+            .record(&scan_report(100, &[([10, 0, 0, 3], 6379, PortState::Open)]))
+            .unwrap();
+
+        assert!(history
+            .exposing(6379)
+            .unwrap()
+            .is_empty());
+
+        history
+            .record(&scan_report(300, &[([10, 0, 0, 3], 6379, PortState::Open)]))
+            .unwrap();
+        history
+            .record(&scan_report(
+                150,
+                &[([10, 0, 0, 3], 6379, PortState::Closed)],
+            ))
+            .unwrap();
+
+        let exposures = history.exposing(6379).unwrap();
+        assert_eq!(exposures.len(), 1);
+        assert_eq!(exposures[0].last_seen, at(300));
+        assert_eq!(exposures[0].first_seen, at(100));
+    }
+
+    #[test]
+    fn should_record_the_state_of_every_probed_port() {
+        let mut history = ScanHistory::open_in_memory().unwrap();
+        history
+            .record(&scan_report(
+                100,
+                &[
+                    ([10, 0, 0, 5], 22, PortState::Open),
+                    ([10, 0, 0, 5], 23, PortState::Closed),
+                    ([10, 0, 0, 5], 24, PortState::Closed),
+                    ([10, 0, 0, 6], 22, PortState::TimeOut),
+                ],
+            ))
+            .unwrap();
+
+        let mut statement = history
+            .connection
+            .prepare("SELECT ip, port, state, service FROM ports ORDER BY ip, port")
+            .unwrap();
+        let ports: Vec<(String, u16, String, Option<String>)> = statement
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(
+            ports,
+            vec![
+                ("10.0.0.5".into(), 22, "open".into(), Some("ssh".into())),
+                ("10.0.0.5".into(), 23, "closed".into(), None),
+                ("10.0.0.5".into(), 24, "closed".into(), None),
+                ("10.0.0.6".into(), 22, "timeout".into(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_ignore_the_scans_that_did_not_probe_a_port() {
+        let mut history = ScanHistory::open_in_memory().unwrap();
+        history
+            .record(&scan_report(100, &[([10, 0, 0, 3], 6379, PortState::Open)]))
+            .unwrap();
+        // a later scan of other ports only.
+        history
+            .record(&scan_report(200, &[([10, 0, 0, 3], 22, PortState::Closed)]))
+            .unwrap();
+
+        let exposures = history.exposing(6379).unwrap();
+
+        assert_eq!(exposures.len(), 1);
+        assert_eq!(exposures[0].ip, Ipv4Addr::new(10, 0, 0, 3));
+        assert_eq!(exposures[0].last_seen, at(100));
+    }
+
+    #[test]
+    fn should_only_query_a_history_opened_read_only() {
+        let path = std::env::temp_dir().join(format!(
+            "humble_port_scanner_read_only_history_{}.sqlite",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        assert!(ScanHistory::open_read_only(&path).is_err());
+        assert!(!path.exists());
+
+        ScanHistory::open(&path)
+            .unwrap()
+            .record(&scan_report(100, &[([10, 0, 0, 5], 22, PortState::Open)]))
+            .unwrap();
+        let mut read_only = ScanHistory::open_read_only(&path).unwrap();
+
+        assert!(read_only
+            .port_sighting(Ipv4Addr::new(10, 0, 0, 5), 22)
+            .unwrap()
+            .is_some());
+        assert!(read_only
+            .record(&scan_report(200, &[([10, 0, 0, 5], 22, PortState::Open)]))
+            .is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn should_migrate_a_database_once() {
+        let path = std::env::temp_dir().join(format!(
+            "humble_port_scanner_history_{}.sqlite",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        ScanHistory::open(&path)
+            .unwrap()
+            .record(&scan_report(100, &[([10, 0, 0, 5], 22, PortState::Open)]))
+            .unwrap();
+        let reopened = ScanHistory::open(&path).unwrap();
+
+        assert_eq!(reopened.schema_version().unwrap(), MIGRATIONS.len());
+        assert!(reopened
+            .port_sighting(Ipv4Addr::new(10, 0, 0, 5), 22)
+            .unwrap()
+            .is_some());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,29 @@
+pub mod app;
+pub mod arg_helpers;
+pub mod change_sinks;
+pub mod connector;
+pub mod coordinator;
+pub mod errors;
+pub mod guardrails;
+pub mod history;
+pub mod metrics_helpers;
+pub mod models;
+pub mod permutation_helpers;
+pub mod policy;
+pub mod port_helpers;
+pub mod progress_helper;
+pub mod resolve_helpers;
+pub mod scan_diff;
+pub mod scan_report;
+pub mod scan_server;
+pub mod scan_stream;
+pub mod services;
+pub mod simulated_network;
+pub mod subnet_helpers;
+pub mod target_helpers;
+pub mod task_supervisor;
+pub mod tokio_helpers;
+pub mod tracing_helpers;
+pub mod watch_helpers;
+pub mod work_protocol;
+pub mod worker;
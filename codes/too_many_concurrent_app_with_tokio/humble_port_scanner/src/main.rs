@@ -1,48 +1,537 @@
-use std::sync::Arc;
-use std::time::Duration;
+use std::{
+    net::SocketAddr,
+    process::ExitCode,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
-use app::SubnetScannerApp;
 use clap::Parser;
+use tokio::{runtime::Runtime, task::JoinHandle};
 
-use anyhow::bail;
+use anyhow::{bail, Context};
 
-use crate::models::PortScannerArgs;
-
-mod app;
-mod arg_helpers;
-mod errors;
-mod models;
-mod port_helpers;
-mod progress_helper;
-mod scan_stream;
-mod subnet_helpers;
-mod tokio_helpers;
+use humble_port_scanner::{
+    app::{SubnetScannerApp, SubnetScannerAppBuilder},
+    arg_helpers,
+    change_sinks::SinkSpec,
+    connector::NetworkConnector,
+    coordinator::Coordinator,
+    history::ScanHistory,
+    metrics_helpers::{self, ScanMetrics},
+    models::{
+        CheckArgs, Command, CoordinatorArgs, DiffArgs, HistoryArgs, HistoryQuery, MergeArgs,
+        OutputFormat, PortScannerArgs, ScanArgs, ServeArgs, SourceBinding, SubnetScanConfiguration,
+        WatchArgs, WorkerArgs,
+    },
+    policy::Policy,
+    resolve_helpers::HostResolver,
+    scan_diff,
+    scan_report::ScanReport,
+    scan_server::{self, ScanServer},
+    target_helpers::TargetList,
+    task_supervisor::TaskOutcome,
+    tokio_helpers, tracing_helpers,
+    watch_helpers::{self, WatchSchedule},
+    worker,
+};
 
 const SCAN_TIMEOUT_SEC: u64 = 1;
 
-fn main() -> anyhow::Result<()> {
-    let PortScannerArgs { subnets, ports } = PortScannerArgs::parse();
+// `diff` and `check` follow the diff(1) convention: 0 unchanged or compliant,
+// 1 changed or in violation, 2 trouble.
+const DIFFERENCES_EXIT_CODE: u8 = 1;
+const ERROR_EXIT_CODE: u8 = 2;
+
+fn main() -> anyhow::Result<ExitCode> {
+    let PortScannerArgs {
+        command,
+        scan,
+        log_format,
+        otlp_endpoint,
+    } = PortScannerArgs::parse();
+
+    let _tracing_guard = tracing_helpers::setup_tracing(log_format, otlp_endpoint)?;
 
-    if subnets.len() != ports.len() {
-        bail!(
-            "Number of subnets and ports must be equal. subnets count was {}, ports count was: {}",
-            subnets.len(),
-            ports.len()
-        )
+    match command {
+        Some(Command::Diff(diff_args)) => Ok(or_error_exit_code(run_diff(diff_args))),
+        Some(Command::Merge(merge_args)) => run_merge(merge_args),
+        Some(Command::Check(check_args)) => Ok(or_error_exit_code(run_check(check_args))),
+        Some(Command::Watch(watch_args)) => run_watch(*watch_args),
+        Some(Command::Coordinator(coordinator_args)) => run_coordinator(*coordinator_args),
+        Some(Command::Worker(worker_args)) => run_worker(worker_args),
+        Some(Command::Serve(serve_args)) => run_serve(serve_args),
+        Some(Command::History(history_args)) => Ok(or_error_exit_code(run_history(history_args))),
+        None => run_scan(scan),
     }
+}
+
+fn or_error_exit_code(result: anyhow::Result<ExitCode>) -> ExitCode {
+    result.unwrap_or_else(|error| {
+        eprintln!("Error: {:#}", error);
+        ExitCode::from(ERROR_EXIT_CODE)
+    })
+}
+
+fn prepare_scan_configurations(
+    scan_args: &ScanArgs,
+    target_lists: &[TargetList],
+) -> anyhow::Result<Vec<SubnetScanConfiguration>> {
+    let ScanArgs {
+        subnets,
+        resolver,
+        ports,
+        services_file,
+        exclude,
+        exclude_file,
+        exclude_ports,
+        host_addresses,
+        seed,
+        shard,
+        proxy,
+        source_address,
+        source_ports,
+        interface,
+        i_know,
+        guardrails,
+        ..
+    } = scan_args.clone();
+
+    let services = arg_helpers::prepare_services(services_file)?;
+    let exclusions = Arc::new(arg_helpers::prepare_exclusions(
+        exclude,
+        exclude_file,
+        exclude_ports,
+        &services,
+    )?);
+    let source = Arc::new(SourceBinding {
+        address: source_address,
+        ports: source_ports
+            .map(arg_helpers::parse_port_ranges)
+            .transpose()?,
+        device: interface,
+    });
+    let resolver = resolver.map_or(HostResolver::System, HostResolver::Dns);
+    let configs: Vec<SubnetScanConfiguration> = arg_helpers::prepare_scan_configurations(
+        subnets,
+        target_lists,
+        ports,
+        host_addresses,
+        &services,
+        &resolver,
+    )?
+    .into_iter()
+    .map(|config| {
+        config
+            .with_exclusions(exclusions.clone())
+            .with_order(seed.into())
+            .with_shard(shard.unwrap_or_default())
+            .with_route(proxy.unwrap_or_default())
+            .with_source(source.clone())
+    })
+    .collect();
+
+    let probes = arg_helpers::prepare_guardrails(guardrails)?
+        .with_confirmation(i_know)
+        .check(&configs)?;
+    tracing::info!(
+        probes,
+        targets = configs.len(),
+        "scan cleared the guardrails"
+    );
+
+    Ok(configs)
+}
+
+// everything but the runtime and the metrics endpoint, which `watch` keeps
+// across its scans.
+fn scan_app_builder(
+    scan_args: &ScanArgs,
+    target_lists: &[TargetList],
+) -> anyhow::Result<SubnetScannerAppBuilder> {
+    let ScanArgs {
+        channel_capacity,
+        max_restarts,
+        time_budget,
+        deadline,
+        ..
+    } = scan_args.clone();
 
-    let subnet_scan_configurations = arg_helpers::prepare_subnets_and_port_ranges(subnets, ports)?;
-    let runtime = Arc::new(tokio_helpers::setup_tokio_runtime());
-    let _scan_timeout = Duration::from_secs(SCAN_TIMEOUT_SEC);
+    // before resolving the targets, which `watch` checks the deadline against.
+    let time_budget = match (time_budget, deadline) {
+        (Some(time_budget), _) => Some(time_budget),
+        (None, Some(deadline)) => Some(
+            SystemTime::from(deadline)
+                .duration_since(SystemTime::now())
+                .ok()
+                .context(format!("Deadline {} has already passed", deadline))?,
+        ),
+        (None, None) => None,
+    };
 
-    let mut app = SubnetScannerApp::builder()
+    let subnet_scan_configurations = prepare_scan_configurations(scan_args, target_lists)?;
+    let mut app_builder = SubnetScannerApp::builder()
         .set_configs(subnet_scan_configurations)
         .set_scan_timeout(Duration::from_secs(SCAN_TIMEOUT_SEC))
-        .set_runtime(&runtime)
-        .build()?;
+        .set_channel_capacity(channel_capacity)
+        .set_max_restarts(max_restarts);
+    if let Some(time_budget) = time_budget {
+        app_builder = app_builder.set_time_budget(time_budget);
+    }
+
+    Ok(app_builder)
+}
 
+fn build_scan_app(scan_args: &ScanArgs) -> anyhow::Result<SubnetScannerApp> {
+    let target_lists = arg_helpers::read_target_lists(&scan_args.targets_file)?;
+    let mut app_builder = scan_app_builder(scan_args, &target_lists)?
+        .set_runtime_config(scan_args.runtime.clone().into());
+    if let Some(metrics_addr) = scan_args.metrics_addr {
+        app_builder = app_builder.set_metrics_addr(metrics_addr);
+    }
+
+    let mut app = app_builder.build()?;
     app.start_subnet_scans();
-    app.run();
+
+    Ok(app)
+}
+
+fn run_scan(scan_args: ScanArgs) -> anyhow::Result<ExitCode> {
+    let policy = scan_args
+        .policy
+        .as_deref()
+        .map(Policy::load)
+        .transpose()?;
+
+    let scan_report = build_scan_app(&scan_args)?.run();
+    report_scan(&scan_args, scan_report, policy)
+}
+
+// prints and saves the results of a scan and checks them against the policy.
+fn report_scan(
+    scan_args: &ScanArgs,
+    scan_report: ScanReport,
+    policy: Option<Policy>,
+) -> anyhow::Result<ExitCode> {
+    print!("{}", scan_report);
+    save_scan(scan_args, &scan_report)?;
+
+    let policy_report = policy.map(|policy| policy.evaluate(&scan_report));
+    if let Some(policy_report) = &policy_report {
+        print!("{}", policy_report);
+    }
+
+    let policy_violated = policy_report.is_some_and(|policy_report| !policy_report.is_compliant());
+    if scan_report.has_failures() || policy_violated {
+        Ok(ExitCode::FAILURE)
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+// writes the results wherever the scan arguments ask to keep them.
+fn save_scan(scan_args: &ScanArgs, scan_report: &ScanReport) -> anyhow::Result<()> {
+    if let Some(output) = &scan_args.output {
+        scan_report.save(output)?;
+    }
+    if let Some(history) = &scan_args.history {
+        ScanHistory::open(history)?.record(scan_report)?;
+    }
 
     Ok(())
 }
+
+fn run_coordinator(coordinator_args: CoordinatorArgs) -> anyhow::Result<ExitCode> {
+    let CoordinatorArgs {
+        scan,
+        listen,
+        chunk_hosts,
+        worker_timeout,
+    } = coordinator_args;
+    if scan.time_budget.is_some() || scan.deadline.is_some() {
+        bail!("--time-budget and --deadline are not supported by the coordinator, its workers scan every chunk to the end")
+    }
+
+    let policy = scan
+        .policy
+        .as_deref()
+        .map(Policy::load)
+        .transpose()?;
+    let target_lists = arg_helpers::read_target_lists(&scan.targets_file)?;
+    let configs = prepare_scan_configurations(&scan, &target_lists)?;
+    let runtime = tokio_helpers::setup_tokio_runtime(&scan.runtime.clone().into())?;
+    let metrics = Arc::new(ScanMetrics::new());
+    let mut metrics_endpoint = start_metrics_endpoint(scan.metrics_addr, &runtime, &metrics)?;
+
+    let scan_report = runtime.block_on(async {
+        let listener = tokio::net::TcpListener::bind(listen)
+            .await
+            .context(format!("Unable to listen for workers on {}", listen))?;
+        let coordinator = Coordinator::new(
+            listener,
+            configs,
+            chunk_hosts,
+            Duration::from_secs(SCAN_TIMEOUT_SEC),
+            worker_timeout,
+        )?
+        .with_metrics(metrics);
+        tracing::info!(addr = %coordinator.local_addr()?, "waiting for workers");
+
+        anyhow::Ok(coordinator.run().await)
+    })?;
+
+    let endpoint_failed = metrics_endpoint_failed(&runtime, &mut metrics_endpoint);
+    stop_metrics_endpoint(metrics_endpoint);
+    let exit_code = report_scan(&scan, scan_report, policy)?;
+    if endpoint_failed {
+        Ok(ExitCode::FAILURE)
+    } else {
+        Ok(exit_code)
+    }
+}
+
+// serves `metrics` and the metrics of `runtime` on `metrics_addr`, for the
+// subcommands running more than a single app.
+fn start_metrics_endpoint(
+    metrics_addr: Option<SocketAddr>,
+    runtime: &Runtime,
+    metrics: &Arc<ScanMetrics>,
+) -> anyhow::Result<Option<JoinHandle<anyhow::Result<()>>>> {
+    let Some(metrics_addr) = metrics_addr else {
+        return Ok(None);
+    };
+
+    metrics.register_runtime(runtime.handle().clone())?;
+    let listener = metrics_helpers::bind_metrics_endpoint(metrics_addr)?;
+    Ok(Some(metrics_helpers::spawn_metrics_endpoint(
+        runtime,
+        listener,
+        metrics.clone(),
+    )))
+}
+
+// the endpoint only stops on its own when it failed, its error is logged once.
+fn metrics_endpoint_failed(
+    runtime: &Runtime,
+    metrics_endpoint: &mut Option<JoinHandle<anyhow::Result<()>>>,
+) -> bool {
+    match metrics_endpoint.take_if(|endpoint| endpoint.is_finished()) {
+        Some(stopped_endpoint) => {
+            let outcome = TaskOutcome::from_join_result(runtime.block_on(stopped_endpoint));
+            tracing::error!(%outcome, "metrics endpoint stopped");
+            true
+        }
+        None => false,
+    }
+}
+
+fn stop_metrics_endpoint(metrics_endpoint: Option<JoinHandle<anyhow::Result<()>>>) {
+    if let Some(metrics_endpoint) = metrics_endpoint {
+        metrics_endpoint.abort();
+    }
+}
+
+fn run_worker(worker_args: WorkerArgs) -> anyhow::Result<ExitCode> {
+    let WorkerArgs {
+        coordinator,
+        name,
+        runtime,
+    } = worker_args;
+
+    let worker_name = name.unwrap_or_else(|| format!("worker-{}", std::process::id()));
+    let runtime = tokio_helpers::setup_tokio_runtime(&runtime.into())?;
+    let chunks_scanned = runtime.block_on(worker::run_worker(
+        coordinator,
+        worker_name,
+        Arc::new(NetworkConnector),
+    ))?;
+    println!("Scanned {} chunks", chunks_scanned);
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn run_serve(serve_args: ServeArgs) -> anyhow::Result<ExitCode> {
+    let ServeArgs {
+        listen,
+        services_file,
+        guardrails,
+        runtime,
+    } = serve_args;
+
+    let guardrails = arg_helpers::prepare_guardrails(guardrails)?;
+    let services = arg_helpers::prepare_services(services_file)?;
+    let runtime = Arc::new(tokio_helpers::setup_tokio_runtime(&runtime.into())?);
+    let server = Arc::new(
+        ScanServer::new(runtime.clone(), Duration::from_secs(SCAN_TIMEOUT_SEC))
+            .with_guardrails(guardrails)
+            .with_services(services),
+    );
+
+    runtime.block_on(async {
+        let listener = tokio::net::TcpListener::bind(listen)
+            .await
+            .context(format!("Unable to serve scans on {}", listen))?;
+        tracing::info!(addr = %listen, "serving scan jobs");
+
+        scan_server::serve_scans(listener, server).await
+    })?;
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn run_watch(watch_args: WatchArgs) -> anyhow::Result<ExitCode> {
+    let WatchArgs {
+        scan,
+        interval,
+        schedule,
+        sinks,
+        max_cycles,
+    } = watch_args;
+
+    let watch_schedule = match (interval, schedule) {
+        (Some(interval), _) => WatchSchedule::Interval(interval),
+        (None, Some(schedule)) => WatchSchedule::Cron(Box::new(schedule)),
+        (None, None) => bail!("Either --interval or --schedule must be provided"),
+    };
+    let mut change_sinks = sinks
+        .iter()
+        .map(SinkSpec::open)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    // the runtime and the metrics endpoint outlive the scans, so the endpoint
+    // keeps its address and counters from one scan to the next.
+    let runtime = Arc::new(tokio_helpers::setup_tokio_runtime(
+        &scan.runtime.clone().into(),
+    )?);
+    let metrics = Arc::new(ScanMetrics::new());
+    let mut metrics_endpoint = start_metrics_endpoint(scan.metrics_addr, &runtime, &metrics)?;
+
+    // stdin can only be read once, so every scan reuses the target lists.
+    let target_lists = arg_helpers::read_target_lists(&scan.targets_file)?;
+    // fail fast on arguments that would make every scan fail.
+    scan_app_builder(&scan, &target_lists)?;
+
+    let mut cycle_failed = false;
+    let until = scan.deadline.map(SystemTime::from);
+    watch_helpers::watch(
+        &watch_schedule,
+        max_cycles,
+        until,
+        &mut change_sinks,
+        || {
+            cycle_failed |= metrics_endpoint_failed(&runtime, &mut metrics_endpoint);
+
+            let scan_report = scan_app_builder(&scan, &target_lists).and_then(|app_builder| {
+                let mut app = app_builder
+                    .set_runtime(&runtime)
+                    .set_metrics(metrics.clone())
+                    .build()?;
+                app.start_subnet_scans();
+                let scan_report = app.run();
+                save_scan(&scan, &scan_report)?;
+                Ok(scan_report)
+            });
+            cycle_failed |= scan_report
+                .as_ref()
+                .map_or(true, ScanReport::has_failures);
+            scan_report
+        },
+    );
+
+    stop_metrics_endpoint(metrics_endpoint);
+
+    if cycle_failed {
+        Ok(ExitCode::FAILURE)
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+fn run_diff(diff_args: DiffArgs) -> anyhow::Result<ExitCode> {
+    let old_report = ScanReport::load(&diff_args.old)?;
+    let new_report = ScanReport::load(&diff_args.new)?;
+    let scan_diff = scan_diff::diff_reports(&old_report, &new_report);
+
+    match diff_args.format {
+        OutputFormat::Human => print!("{}", scan_diff),
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&scan_diff).context("Unable to serialize the diff")?
+        ),
+    }
+
+    if scan_diff.is_empty() {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::from(DIFFERENCES_EXIT_CODE))
+    }
+}
+
+fn run_merge(merge_args: MergeArgs) -> anyhow::Result<ExitCode> {
+    let scan_reports = merge_args
+        .results
+        .iter()
+        .map(|results| ScanReport::load(results))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let scan_report = ScanReport::merge(scan_reports)?;
+    print!("{}", scan_report);
+
+    if let Some(output) = merge_args.output {
+        scan_report.save(&output)?;
+    }
+
+    if scan_report.has_failures() {
+        Ok(ExitCode::FAILURE)
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+fn run_check(check_args: CheckArgs) -> anyhow::Result<ExitCode> {
+    let policy = Policy::load(&check_args.policy)?;
+    let scan_report = ScanReport::load(&check_args.results)?;
+    let policy_report = policy.evaluate(&scan_report);
+
+    match check_args.format {
+        OutputFormat::Human => print!("{}", policy_report),
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&policy_report)
+                .context("Unable to serialize the policy report")?
+        ),
+    }
+
+    if policy_report.is_compliant() {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::from(DIFFERENCES_EXIT_CODE))
+    }
+}
+
+fn run_history(history_args: HistoryArgs) -> anyhow::Result<ExitCode> {
+    let history = ScanHistory::open_read_only(&history_args.db)?;
+
+    match history_args.query {
+        HistoryQuery::FirstSeen { ip, port } => {
+            let sighting = history.port_sighting(ip, port)?;
+            match (history_args.format, &sighting) {
+                (OutputFormat::Json, _) => println!("{}", serde_json::to_string_pretty(&sighting)?),
+                (OutputFormat::Human, Some(sighting)) => println!("{}", sighting),
+                (OutputFormat::Human, None) => println!("{}:{} was never seen open", ip, port),
+            }
+        }
+        HistoryQuery::Exposing { port } => {
+            let exposures = history.exposing(port)?;
+            match history_args.format {
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&exposures)?),
+                OutputFormat::Human if exposures.is_empty() => {
+                    println!("No host exposes port {}", port)
+                }
+                OutputFormat::Human => exposures
+                    .iter()
+                    .for_each(|exposure| println!("{}", exposure)),
+            }
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
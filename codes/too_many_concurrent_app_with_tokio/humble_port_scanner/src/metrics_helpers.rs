@@ -0,0 +1,496 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use axum::{extract::State, http::header, response::IntoResponse, routing::get, Router};
+use prometheus::{
+    core::{Collector, Desc},
+    proto::MetricFamily,
+    CounterVec, Encoder, Gauge, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use tokio::{
+    net::TcpListener,
+    runtime::{Handle, Runtime},
+    task::JoinHandle,
+};
+
+use crate::{
+    models::{IpPortScanResult, PortState, ScanTarget},
+    tokio_helpers,
+};
+
+const METRICS_NAMESPACE: &str = "humble_port_scanner";
+pub const METRICS_ENDPOINT_TASK: &str = "metrics_endpoint";
+
+// connect latencies are dominated by the scan timeout, so the buckets stop at
+// a few seconds.
+const CONNECT_LATENCY_BUCKETS: [f64; 12] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+pub struct ScanMetrics {
+    registry: Registry,
+    probes_sent: IntCounter,
+    probes_in_flight: IntGauge,
+    scan_results: IntCounterVec,
+    connect_latency: HistogramVec,
+    channel_depth: IntGaugeVec,
+    producer_blocked: IntCounterVec,
+    producer_blocked_duration: CounterVec,
+}
+
+impl Default for ScanMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScanMetrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some(String::from(METRICS_NAMESPACE)), None)
+            .expect("Failed to create the metrics registry.");
+
+        let probes_sent =
+            IntCounter::new("probes_sent_total", "Number of TCP probes issued.").unwrap();
+        let probes_in_flight = IntGauge::new(
+            "probes_in_flight",
+            "Number of TCP probes waiting for a connect result.",
+        )
+        .unwrap();
+        let scan_results = IntCounterVec::new(
+            Opts::new(
+                "scan_results_total",
+                "Number of scan results by port state.",
+            ),
+            &["state"],
+        )
+        .unwrap();
+        let connect_latency = HistogramVec::new(
+            HistogramOpts::new(
+                "connect_latency_seconds",
+                "Time taken by a TCP probe to reach a port state.",
+            )
+            .buckets(CONNECT_LATENCY_BUCKETS.to_vec()),
+            &["state"],
+        )
+        .unwrap();
+        let channel_depth = IntGaugeVec::new(
+            Opts::new(
+                "result_channel_depth",
+                "Number of scan results queued in a scan target channel.",
+            ),
+            &["target"],
+        )
+        .unwrap();
+
+        let producer_blocked = IntCounterVec::new(
+            Opts::new(
+                "producer_blocked_total",
+                "Number of times a target scan waited on a full result channel.",
+            ),
+            &["target"],
+        )
+        .unwrap();
+        let producer_blocked_duration = CounterVec::new(
+            Opts::new(
+                "producer_blocked_seconds_total",
+                "Time target scans spent waiting on a full result channel.",
+            ),
+            &["target"],
+        )
+        .unwrap();
+
+        registry
+            .register(Box::new(probes_sent.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(probes_in_flight.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(scan_results.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(connect_latency.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(channel_depth.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(producer_blocked.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(producer_blocked_duration.clone()))
+            .unwrap();
+
+        Self {
+            registry,
+            probes_sent,
+            probes_in_flight,
+            scan_results,
+            connect_latency,
+            channel_depth,
+            producer_blocked,
+            producer_blocked_duration,
+        }
+    }
+
+    /// Exports the metrics of the runtime behind `handle` alongside the scan metrics.
+    pub fn register_runtime(&self, handle: Handle) -> anyhow::Result<()> {
+        self.registry
+            .register(Box::new(RuntimeMetricsCollector::new(handle)))
+            .context("Unable to register the tokio runtime metrics")
+    }
+
+    pub fn probe_started(&self) {
+        self.probes_sent.inc();
+        self.probes_in_flight.inc();
+    }
+
+    pub fn probe_finished(&self, scan_result: &IpPortScanResult, latency: Duration) {
+        let state = port_state_label(scan_result.state);
+
+        self.probes_in_flight.dec();
+        self.scan_results
+            .with_label_values(&[state])
+            .inc();
+        self.connect_latency
+            .with_label_values(&[state])
+            .observe(latency.as_secs_f64());
+    }
+
+    /// A result probed elsewhere, e.g. by a worker of the coordinator.
+    pub fn result_received(&self, scan_result: &IpPortScanResult) {
+        self.scan_results
+            .with_label_values(&[port_state_label(scan_result.state)])
+            .inc();
+    }
+
+    /// A probe that ended without a port state, e.g. when its proxy failed.
+    pub fn probe_failed(&self) {
+        self.probes_in_flight.dec();
+    }
+
+    pub fn result_queued(&self, target: &ScanTarget) {
+        self.channel_depth
+            .with_label_values(&[&target.to_string()])
+            .inc();
+    }
+
+    pub fn result_dequeued(&self, target: &ScanTarget) {
+        self.channel_depth
+            .with_label_values(&[&target.to_string()])
+            .dec();
+    }
+
+    pub fn producer_blocked(&self, target: &ScanTarget, blocked_for: Duration) {
+        let target = target.to_string();
+        self.producer_blocked
+            .with_label_values(&[&target])
+            .inc();
+        self.producer_blocked_duration
+            .with_label_values(&[&target])
+            .inc_by(blocked_for.as_secs_f64());
+    }
+
+    pub fn probes_sent(&self) -> u64 {
+        self.probes_sent.get()
+    }
+
+    pub fn producer_blocked_count(&self, target: &ScanTarget) -> u64 {
+        self.producer_blocked
+            .with_label_values(&[&target.to_string()])
+            .get()
+    }
+
+    pub fn encode(&self) -> anyhow::Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .context("Unable to encode the metrics in prometheus text format")?;
+
+        String::from_utf8(buffer).context("Prometheus text encoder produced invalid utf-8")
+    }
+}
+
+fn port_state_label(state: PortState) -> &'static str {
+    match state {
+        PortState::Open => "open",
+        PortState::Closed => "closed",
+        PortState::TimeOut => "timeout",
+    }
+}
+
+/// Reads the tokio runtime metrics on every scrape.
+struct RuntimeMetricsCollector {
+    handle: Handle,
+    descs: Vec<Desc>,
+}
+
+impl RuntimeMetricsCollector {
+    fn new(handle: Handle) -> Self {
+        let descs = Self::metrics(&handle)
+            .iter()
+            .flat_map(|collector| collector.desc())
+            .cloned()
+            .collect();
+
+        Self { handle, descs }
+    }
+
+    fn metrics(handle: &Handle) -> Vec<Box<dyn Collector>> {
+        let runtime_metrics = handle.metrics();
+
+        let workers = IntGauge::new("tokio_workers", "Number of tokio worker threads.").unwrap();
+        let active_tasks = IntGauge::new(
+            "tokio_active_tasks",
+            "Number of tasks alive in the runtime.",
+        )
+        .unwrap();
+        let global_queue_depth = IntGauge::new(
+            "tokio_global_queue_depth",
+            "Number of tasks waiting in the runtime global queue.",
+        )
+        .unwrap();
+        let busy_duration = CounterVec::new(
+            Opts::new(
+                "tokio_worker_busy_seconds_total",
+                "Time each worker spent polling tasks.",
+            ),
+            &["worker"],
+        )
+        .unwrap();
+        let polls = IntCounterVec::new(
+            Opts::new(
+                "tokio_worker_polls_total",
+                "Number of tasks polled by each worker.",
+            ),
+            &["worker"],
+        )
+        .unwrap();
+        // tokio counts the polls of each duration range but not their total
+        // time, so the ranges are exported as plain counters rather than as a
+        // prometheus histogram, which would need a sum.
+        let polls_by_duration = IntCounterVec::new(
+            Opts::new(
+                "tokio_polls_by_duration_total",
+                "Number of polls whose duration fell in the range ending at `max_micros` microseconds.",
+            ),
+            &["max_micros"],
+        )
+        .unwrap();
+        let mean_poll_time = Gauge::new(
+            "tokio_mean_poll_time_seconds",
+            "Mean poll time averaged across workers.",
+        )
+        .unwrap();
+
+        let num_workers = runtime_metrics.num_workers();
+        workers.set(num_workers as i64);
+        active_tasks.set(runtime_metrics.num_alive_tasks() as i64);
+        global_queue_depth.set(runtime_metrics.global_queue_depth() as i64);
+
+        let mut total_mean_poll_time = 0.0;
+        for worker in 0..num_workers {
+            let worker_label = worker.to_string();
+            busy_duration
+                .with_label_values(&[&worker_label])
+                .inc_by(
+                    runtime_metrics
+                        .worker_total_busy_duration(worker)
+                        .as_secs_f64(),
+                );
+            polls
+                .with_label_values(&[&worker_label])
+                .inc_by(runtime_metrics.worker_poll_count(worker));
+            total_mean_poll_time += runtime_metrics
+                .worker_mean_poll_time(worker)
+                .as_secs_f64();
+        }
+        if num_workers > 0 {
+            mean_poll_time.set(total_mean_poll_time / num_workers as f64);
+        }
+
+        if runtime_metrics.poll_time_histogram_enabled() {
+            for bucket in 0..runtime_metrics.poll_time_histogram_num_buckets() {
+                let bucket_end = runtime_metrics
+                    .poll_time_histogram_bucket_range(bucket)
+                    .end;
+                let bucket_label = if bucket_end == Duration::MAX {
+                    String::from("+Inf")
+                } else {
+                    bucket_end.as_micros().to_string()
+                };
+                let bucket_polls: u64 = (0..num_workers)
+                    .map(|worker| runtime_metrics.poll_time_histogram_bucket_count(worker, bucket))
+                    .sum();
+                polls_by_duration
+                    .with_label_values(&[&bucket_label])
+                    .inc_by(bucket_polls);
+            }
+        }
+
+        vec![
+            Box::new(workers),
+            Box::new(active_tasks),
+            Box::new(global_queue_depth),
+            Box::new(busy_duration),
+            Box::new(polls),
+            Box::new(polls_by_duration),
+            Box::new(mean_poll_time),
+        ]
+    }
+}
+
+impl Collector for RuntimeMetricsCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        Self::metrics(&self.handle)
+            .iter()
+            .flat_map(|collector| collector.collect())
+            .collect()
+    }
+}
+
+async fn metrics_handler(State(metrics): State<Arc<ScanMetrics>>) -> impl IntoResponse {
+    match metrics.encode() {
+        Ok(body) => (
+            axum::http::StatusCode::OK,
+            [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+            body,
+        ),
+        Err(encode_error) => (
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+            [(header::CONTENT_TYPE, "text/plain")],
+            encode_error.to_string(),
+        ),
+    }
+}
+
+pub fn metrics_router(metrics: Arc<ScanMetrics>) -> Router {
+    Router::new()
+        .route("/metrics", get(metrics_handler))
+        .with_state(metrics)
+}
+
+pub async fn serve_metrics(listener: TcpListener, metrics: Arc<ScanMetrics>) -> anyhow::Result<()> {
+    axum::serve(listener, metrics_router(metrics))
+        .await
+        .context("Metrics endpoint stopped unexpectedly")
+}
+
+/// Binds the `/metrics` endpoint listener, so a busy address fails before any scan starts.
+pub fn bind_metrics_endpoint(metrics_addr: SocketAddr) -> anyhow::Result<std::net::TcpListener> {
+    let listener = std::net::TcpListener::bind(metrics_addr).context(format!(
+        "Unable to bind the metrics endpoint on {}",
+        metrics_addr
+    ))?;
+    listener.set_nonblocking(true)?;
+
+    Ok(listener)
+}
+
+/// Serves `metrics` on `listener` from a task of `runtime` until the task is aborted.
+pub fn spawn_metrics_endpoint(
+    runtime: &Runtime,
+    listener: std::net::TcpListener,
+    metrics: Arc<ScanMetrics>,
+) -> JoinHandle<anyhow::Result<()>> {
+    tokio_helpers::spawn_named_task(METRICS_ENDPOINT_TASK, runtime, async move {
+        serve_metrics(TcpListener::from_std(listener)?, metrics).await
+    })
+}
+
+#[cfg(test)]
+mod metrics_tests {
+    use std::{net::Ipv4Addr, sync::Arc, time::Duration};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        runtime::Handle,
+    };
+
+    use crate::{
+        metrics_helpers::{serve_metrics, ScanMetrics},
+        models::{IpPortScanResult, PortState, ScanTarget},
+    };
+
+    fn scan_result(state: PortState) -> IpPortScanResult {
+        IpPortScanResult {
+            ip: Ipv4Addr::new(127, 0, 0, 1),
+            port: 8080,
+            state,
+            hostname: None,
+            service: None,
+        }
+    }
+
+    #[test]
+    fn should_count_probes_and_results_by_state() {
+        let metrics = ScanMetrics::new();
+        let target = ScanTarget::Subnet("10.0.0.0/30".parse().unwrap());
+
+        metrics.probe_started();
+        metrics.probe_started();
+        metrics.probe_started();
+        metrics.probe_finished(&scan_result(PortState::Open), Duration::from_millis(2));
+        metrics.probe_finished(&scan_result(PortState::Closed), Duration::from_millis(1));
+        metrics.result_queued(&target);
+        metrics.result_queued(&target);
+        metrics.result_dequeued(&target);
+
+        let encoded = metrics.encode().unwrap();
+
+        assert!(encoded.contains("humble_port_scanner_probes_sent_total 3"));
+        assert!(encoded.contains("humble_port_scanner_probes_in_flight 1"));
+        assert!(encoded.contains("humble_port_scanner_scan_results_total{state=\"open\"} 1"));
+        assert!(encoded.contains("humble_port_scanner_scan_results_total{state=\"closed\"} 1"));
+        assert!(
+            encoded.contains("humble_port_scanner_connect_latency_seconds_count{state=\"open\"} 1")
+        );
+        assert!(
+            encoded.contains("humble_port_scanner_result_channel_depth{target=\"10.0.0.0/30\"} 1")
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn should_serve_scan_and_runtime_metrics_over_http() {
+        let metrics = Arc::new(ScanMetrics::new());
+        metrics
+            .register_runtime(Handle::current())
+            .unwrap();
+        metrics.probe_started();
+
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let metrics_addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_metrics(listener, metrics));
+
+        let mut stream = TcpStream::connect(metrics_addr)
+            .await
+            .unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .await
+            .unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("humble_port_scanner_probes_sent_total 1"));
+        assert!(response.contains("humble_port_scanner_tokio_workers 2"));
+        assert!(response.contains("humble_port_scanner_tokio_worker_polls_total{worker=\"1\"}"));
+        assert!(response.contains("# TYPE humble_port_scanner_tokio_worker_polls_total counter"));
+        assert!(
+            response.contains("# TYPE humble_port_scanner_tokio_worker_busy_seconds_total counter")
+        );
+    }
+}
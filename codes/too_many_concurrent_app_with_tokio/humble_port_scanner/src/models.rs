@@ -1,34 +1,893 @@
-use std::net::Ipv4Addr;
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use ipnet::Ipv4Net;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    change_sinks::SinkSpec,
+    errors::AppErrors,
+    guardrails,
+    target_helpers::{self, RangeSet},
+    tracing_helpers::LogFormat,
+};
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
 pub struct PortScannerArgs {
-    #[arg(short, long, value_parser, num_args = 1.., value_delimiter = ' ')]
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub scan: ScanArgs,
+    /// Log output format, filtered through `RUST_LOG`.
+    #[arg(long, value_enum, default_value_t = LogFormat::Pretty, global = true)]
+    pub log_format: LogFormat,
+    /// Export spans to an OTLP/HTTP collector, e.g. http://127.0.0.1:4318/v1/traces.
+    #[arg(long, global = true)]
+    pub otlp_endpoint: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Compare two saved scan results and report exposure changes.
+    Diff(DiffArgs),
+    /// Combine the result files of sharded scans into one report.
+    Merge(MergeArgs),
+    /// Rescan on an interval or schedule and report only what changed.
+    Watch(Box<WatchArgs>),
+    /// Check a saved scan result against the expected exposure rules.
+    Check(CheckArgs),
+    /// Split the scan into chunks of hosts and hand them out to connecting workers.
+    Coordinator(Box<CoordinatorArgs>),
+    /// Scan the chunks handed out by a coordinator.
+    Worker(WorkerArgs),
+    /// Run scan jobs submitted over an HTTP/JSON API.
+    Serve(ServeArgs),
+    /// Query the scans recorded with `--history`.
+    History(HistoryArgs),
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct ScanArgs {
+    /// Targets to scan: CIDRs, single IPs, dash ranges such as 10.0.0.5-10.0.0.40 or hostnames.
+    #[arg(short, long, visible_alias = "targets", value_parser, num_args = 1.., value_delimiter = ' ')]
     pub subnets: Vec<String>,
+    /// Files listing targets one per line, `#` starts a comment and `-` reads stdin.
+    #[arg(long)]
+    pub targets_file: Vec<PathBuf>,
+    /// DNS server resolving hostname targets, e.g. 127.0.0.1:5353, instead of the system resolver.
+    #[arg(long)]
+    pub resolver: Option<SocketAddr>,
+    /// Port specs, one per target in order with the target files last, or a single one for
+    /// every target. A spec lists ports, [begin_port]:[end_port] ranges and service names,
+    /// e.g. ssh,https,8000:8100.
     #[arg(short, long, value_parser, num_args = 1.., value_delimiter = ' ')]
     pub ports: Vec<String>,
+    /// File naming services in the /etc/services format, taking over the embedded and system names.
+    #[arg(long)]
+    pub services_file: Option<PathBuf>,
+    /// Addresses to skip: IPs, CIDRs or dash ranges such as 10.0.0.5-10.0.0.40.
+    #[arg(long, value_delimiter = ',')]
+    pub exclude: Vec<String>,
+    /// File listing addresses to skip, one per line, `#` starts a comment.
+    #[arg(long)]
+    pub exclude_file: Option<PathBuf>,
+    /// Ports to skip: single ports, service names or [begin_port]:[end_port] ranges.
+    #[arg(long, value_delimiter = ',')]
+    pub exclude_ports: Vec<String>,
+    /// Network and broadcast addresses of subnet targets to scan, /31 and /32 subnets are always scanned whole.
+    #[arg(long, value_enum, default_value_t = HostAddressMode::Usable)]
+    pub host_addresses: HostAddressMode,
+    /// Probe the hosts and ports of each target in a random order, the same seed gives the same order.
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Only probe this share of every target, e.g. 2/3 for the second of three scanner processes.
+    #[arg(long)]
+    pub shard: Option<Shard>,
+    /// Route every probe through a proxy: socks5://<ip>:<port> or http://<ip>:<port> for HTTP CONNECT.
+    #[arg(long)]
+    pub proxy: Option<ProbeRoute>,
+    /// Local address the probes are sent from, it must be assigned to this host.
+    #[arg(long)]
+    pub source_address: Option<Ipv4Addr>,
+    /// Source ports the probes take in turn, as a [begin_port]:[end_port] range.
+    #[arg(long)]
+    pub source_ports: Option<String>,
+    /// Network device the probes leave through, e.g. eth1. Linux only.
+    #[arg(long)]
+    pub interface: Option<String>,
+    /// Save the scan results as JSON, e.g. to compare them later with `diff`.
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// Record the scan results in this SQLite database, see the `history` subcommand.
+    #[arg(long)]
+    pub history: Option<PathBuf>,
+    /// Check the results against the expected exposure rules in this TOML or JSON file.
+    #[arg(long)]
+    pub policy: Option<PathBuf>,
+    /// Serve prometheus metrics on this address, e.g. 127.0.0.1:9898.
+    #[arg(long)]
+    pub metrics_addr: Option<SocketAddr>,
+    /// Scan results each subnet may queue before probing slows down to the consumer's pace.
+    #[arg(long, default_value_t = 1024)]
+    pub channel_capacity: usize,
+    /// Times a failed subnet scan is resumed before it is reported as failed.
+    #[arg(long, default_value_t = 0)]
+    pub max_restarts: u32,
+    /// Stop issuing probes once the scan has run this long, e.g. `45m`, the rest is reported as not scanned.
+    #[arg(long, value_parser = humantime::parse_duration, conflicts_with = "deadline")]
+    pub time_budget: Option<Duration>,
+    /// Stop issuing probes at this time, e.g. `2024-05-04T06:00:00Z`, the rest is reported as not scanned.
+    /// `watch` stops watching at this time.
+    #[arg(long)]
+    pub deadline: Option<humantime::Timestamp>,
+    /// Run a scan of more probes than the confirmation threshold.
+    #[arg(long)]
+    pub i_know: bool,
+    #[command(flatten)]
+    pub guardrails: GuardrailArgs,
+    #[command(flatten)]
+    pub runtime: RuntimeArgs,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct GuardrailArgs {
+    /// Probes a scan may make without being confirmed with --i-know.
+    #[arg(long, default_value_t = guardrails::DEFAULT_CONFIRMATION_THRESHOLD)]
+    pub confirm_above: u64,
+    /// Public, bogon, multicast or broadcast addresses that may be scanned: IPs, CIDRs or dash ranges.
+    #[arg(long, value_delimiter = ',')]
+    pub allow: Vec<String>,
+    /// Only the addresses listed in this file may ever be scanned, one per line.
+    /// /etc/humble_port_scanner/allowlist is enforced too when present, a scan
+    /// must then stay within both files.
+    #[arg(long)]
+    pub allowlist_file: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct DiffArgs {
+    /// Earlier scan result file.
+    pub old: PathBuf,
+    /// Later scan result file.
+    pub new: PathBuf,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+    pub format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+pub struct MergeArgs {
+    /// Scan result files to combine, e.g. one per shard.
+    #[arg(required = true)]
+    pub results: Vec<PathBuf>,
+    /// Save the merged scan results as JSON.
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct CheckArgs {
+    /// Scan result file to check.
+    pub results: PathBuf,
+    /// Expected exposure rules, as TOML or JSON.
+    #[arg(long)]
+    pub policy: PathBuf,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+    pub format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+pub struct WatchArgs {
+    #[command(flatten)]
+    pub scan: ScanArgs,
+    /// Time between the starts of two scans, e.g. `15m` or `1h 30m`.
+    #[arg(long, value_parser = humantime::parse_duration, required_unless_present = "schedule", conflicts_with = "schedule")]
+    pub interval: Option<Duration>,
+    /// Cron expression with a seconds field evaluated in UTC, e.g. `0 */15 * * * *`.
+    #[arg(long)]
+    pub schedule: Option<cron::Schedule>,
+    /// Where change events are sent: `stdout`, `jsonl:<path>` or `webhook:<url>`.
+    #[arg(long = "sink", default_value = "stdout")]
+    pub sinks: Vec<SinkSpec>,
+    /// Stop after this many scans instead of watching forever. The exit code is a
+    /// failure when any of the scans failed.
+    #[arg(long)]
+    pub max_cycles: Option<u64>,
+}
+
+#[derive(Args, Debug)]
+pub struct CoordinatorArgs {
+    #[command(flatten)]
+    pub scan: ScanArgs,
+    /// Address the workers connect to.
+    #[arg(long, default_value = "127.0.0.1:7878")]
+    pub listen: SocketAddr,
+    /// Hosts of a target in every chunk handed to a worker.
+    #[arg(long, default_value_t = 16)]
+    pub chunk_hosts: u64,
+    /// Silence after which a worker is taken for dead and its chunk handed to another.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "10s")]
+    pub worker_timeout: Duration,
+}
+
+#[derive(Args, Debug)]
+pub struct WorkerArgs {
+    /// Address of the coordinator handing out the chunks.
+    #[arg(long)]
+    pub coordinator: SocketAddr,
+    /// Name of the worker in the coordinator's logs, `worker-<pid>` by default.
+    #[arg(long)]
+    pub name: Option<String>,
+    #[command(flatten)]
+    pub runtime: RuntimeArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct HistoryArgs {
+    /// SQLite database the scans were recorded in.
+    #[arg(long)]
+    pub db: PathBuf,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+    pub format: OutputFormat,
+    #[command(subcommand)]
+    pub query: HistoryQuery,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum HistoryQuery {
+    /// When a port of a host was first and last seen open.
+    FirstSeen {
+        #[arg(long)]
+        ip: Ipv4Addr,
+        #[arg(long)]
+        port: u16,
+    },
+    /// Hosts whose latest recorded scan found a port open.
+    Exposing {
+        #[arg(long)]
+        port: u16,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    /// Address of the HTTP API.
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    pub listen: SocketAddr,
+    /// File naming services in the /etc/services format, taking over the embedded and system names.
+    #[arg(long)]
+    pub services_file: Option<PathBuf>,
+    #[command(flatten)]
+    pub guardrails: GuardrailArgs,
+    #[command(flatten)]
+    pub runtime: RuntimeArgs,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Human,
+    Json,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct RuntimeArgs {
+    /// Tokio scheduler used to drive the scans.
+    #[arg(long, value_enum, default_value_t = RuntimeFlavor::MultiThread)]
+    pub runtime_flavor: RuntimeFlavor,
+    /// Number of worker threads, ignored by the current-thread runtime.
+    #[arg(long, default_value_t = 4)]
+    pub worker_threads: usize,
+    /// Upper limit on the threads spawned for blocking operations.
+    #[arg(long)]
+    pub max_blocking_threads: Option<usize>,
+    /// Number of scheduler ticks between polls for external events (io, timers).
+    #[arg(long)]
+    pub event_interval: Option<u32>,
+    /// Number of scheduler ticks between polls of the global task queue.
+    #[arg(long)]
+    pub global_queue_interval: Option<u32>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum RuntimeFlavor {
+    MultiThread,
+    CurrentThread,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeConfiguration {
+    pub flavor: RuntimeFlavor,
+    pub worker_threads: usize,
+    pub max_blocking_threads: Option<usize>,
+    pub event_interval: Option<u32>,
+    pub global_queue_interval: Option<u32>,
+}
+
+impl Default for RuntimeConfiguration {
+    fn default() -> Self {
+        Self {
+            flavor: RuntimeFlavor::MultiThread,
+            worker_threads: 4,
+            max_blocking_threads: None,
+            event_interval: None,
+            global_queue_interval: None,
+        }
+    }
+}
+
+impl From<RuntimeArgs> for RuntimeConfiguration {
+    fn from(args: RuntimeArgs) -> Self {
+        Self {
+            flavor: args.runtime_flavor,
+            worker_threads: args.worker_threads,
+            max_blocking_threads: args.max_blocking_threads,
+            event_interval: args.event_interval,
+            global_queue_interval: args.global_queue_interval,
+        }
+    }
+}
+
+/// Hosts and ports left out of every scan target.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Exclusions {
+    pub hosts: RangeSet<Ipv4Addr>,
+    pub ports: RangeSet<u16>,
+}
+
+/// What a scan task covers. It names the task, its progress bar, metrics and
+/// report section.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "String", from = "String")]
+pub enum ScanTarget {
+    Subnet(Ipv4Net),
+    /// Inclusive address range, a single IP when both ends are equal.
+    Range(Ipv4Addr, Ipv4Addr),
+    /// A hostname, or targets read from a file or stdin. Their hosts are
+    /// resolved into the configuration.
+    Named(String),
+}
+
+impl ScanTarget {
+    /// Addresses covered by the target, empty for named targets. Subnets leave
+    /// out their network and broadcast addresses.
+    pub fn hosts(&self) -> RangeSet<Ipv4Addr> {
+        self.hosts_with(HostAddressMode::default())
+    }
+
+    /// Addresses covered by the target, subnets keep the network and broadcast
+    /// addresses included by `mode`.
+    pub fn hosts_with(&self, mode: HostAddressMode) -> RangeSet<Ipv4Addr> {
+        match self {
+            // point-to-point /31s (RFC 3021) and /32s have neither address.
+            ScanTarget::Subnet(subnet) if subnet.prefix_len() >= 31 => {
+                std::iter::once(subnet.network()..=subnet.broadcast()).collect()
+            }
+            ScanTarget::Subnet(subnet) => {
+                let mut first = u32::from(subnet.network());
+                let mut last = u32::from(subnet.broadcast());
+                if !mode.includes_network() {
+                    first += 1;
+                }
+                if !mode.includes_broadcast() {
+                    last -= 1;
+                }
+                std::iter::once(Ipv4Addr::from(first)..=Ipv4Addr::from(last)).collect()
+            }
+            ScanTarget::Range(start, end) => std::iter::once(*start..=*end).collect(),
+            ScanTarget::Named(_) => RangeSet::new(),
+        }
+    }
+}
+
+/// Whether the network and broadcast addresses of subnet targets are scanned.
+/// /31 and /32 subnets are scanned whole in every mode.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum HostAddressMode {
+    /// Skip the network and broadcast addresses.
+    #[default]
+    Usable,
+    /// Also scan the network address, e.g. of a point-to-point link.
+    IncludeNetwork,
+    /// Also scan the broadcast address.
+    IncludeBroadcast,
+    /// Scan every address of the subnet.
+    All,
+}
+
+impl HostAddressMode {
+    pub fn includes_network(self) -> bool {
+        matches!(self, HostAddressMode::IncludeNetwork | HostAddressMode::All)
+    }
+
+    pub fn includes_broadcast(self) -> bool {
+        matches!(
+            self,
+            HostAddressMode::IncludeBroadcast | HostAddressMode::All
+        )
+    }
+}
+
+impl From<Ipv4Net> for ScanTarget {
+    fn from(subnet: Ipv4Net) -> Self {
+        ScanTarget::Subnet(subnet)
+    }
+}
+
+impl fmt::Display for ScanTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScanTarget::Subnet(subnet) => write!(f, "{}", subnet),
+            ScanTarget::Range(start, end) if start == end => write!(f, "{}", start),
+            ScanTarget::Range(start, end) => write!(f, "{}-{}", start, end),
+            ScanTarget::Named(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl From<ScanTarget> for String {
+    fn from(target: ScanTarget) -> Self {
+        target.to_string()
+    }
+}
+
+// saved reports only keep the name of hostnames and lists, anything that is
+// not an address target is read back as a named one.
+impl From<String> for ScanTarget {
+    fn from(target: String) -> Self {
+        target_helpers::parse_scan_target(&target).unwrap_or(ScanTarget::Named(target))
+    }
+}
+
+/// Order in which the (host, port) pairs of a target are probed.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScanOrder {
+    /// Every port of a host before moving on to the next host.
+    #[default]
+    Sequential,
+    /// A seeded permutation spreading the probes over all hosts.
+    Random { seed: u64 },
+}
+
+impl From<Option<u64>> for ScanOrder {
+    fn from(seed: Option<u64>) -> Self {
+        seed.map_or(ScanOrder::Sequential, |seed| ScanOrder::Random { seed })
+    }
+}
+
+/// One of `count` disjoint shares of the probes of a target, `index` counts from 1.
+///
+/// Shard `i/n` takes every n-th probe position starting at position `i - 1`, so
+/// the n shards together probe every (host, port) pair exactly once.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Shard {
+    pub index: u64,
+    pub count: u64,
 }
 
-#[derive(Debug, Copy, Clone)]
+impl Shard {
+    /// The first probe position of the shard.
+    pub fn offset(&self) -> u64 {
+        self.index - 1
+    }
+
+    /// Number of positions of `0..probe_count` belonging to the shard.
+    pub fn probe_count(&self, probe_count: u64) -> u64 {
+        probe_count
+            .saturating_sub(self.offset())
+            .div_ceil(self.count)
+    }
+}
+
+impl Default for Shard {
+    fn default() -> Self {
+        Self { index: 1, count: 1 }
+    }
+}
+
+impl FromStr for Shard {
+    type Err = AppErrors;
+
+    fn from_str(shard: &str) -> Result<Self, Self::Err> {
+        let invalid_shard = || AppErrors::InvalidShardError {
+            shard: shard.to_string(),
+        };
+
+        let (index, count) = shard
+            .split_once('/')
+            .ok_or_else(invalid_shard)?;
+        let index = index
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| invalid_shard())?;
+        let count = count
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| invalid_shard())?;
+        if index == 0 || index > count {
+            return Err(invalid_shard());
+        }
+
+        Ok(Self { index, count })
+    }
+}
+
+impl fmt::Display for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+/// How probes reach the scanned hosts.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProbeRoute {
+    /// A TCP connection straight from this host.
+    #[default]
+    Direct,
+    /// Through a SOCKS5 proxy that needs no authentication.
+    Socks5(SocketAddr),
+    /// Through an HTTP proxy supporting the CONNECT method.
+    HttpConnect(SocketAddr),
+}
+
+impl FromStr for ProbeRoute {
+    type Err = AppErrors;
+
+    fn from_str(proxy: &str) -> Result<Self, Self::Err> {
+        let invalid_proxy = || AppErrors::InvalidProxyError {
+            proxy: proxy.to_string(),
+        };
+
+        let (scheme, proxy_addr) = proxy
+            .split_once("://")
+            .ok_or_else(invalid_proxy)?;
+        let proxy_addr = proxy_addr
+            .trim_end_matches('/')
+            .parse::<SocketAddr>()
+            .map_err(|_| invalid_proxy())?;
+
+        match scheme {
+            "socks5" => Ok(ProbeRoute::Socks5(proxy_addr)),
+            "http" => Ok(ProbeRoute::HttpConnect(proxy_addr)),
+            _ => Err(invalid_proxy()),
+        }
+    }
+}
+
+impl fmt::Display for ProbeRoute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProbeRoute::Direct => write!(f, "direct"),
+            ProbeRoute::Socks5(proxy_addr) => write!(f, "socks5://{}", proxy_addr),
+            ProbeRoute::HttpConnect(proxy_addr) => write!(f, "http://{}", proxy_addr),
+        }
+    }
+}
+
+/// Local end of the probes, whatever is left unset is picked by the system.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceBinding {
+    pub address: Option<Ipv4Addr>,
+    /// Source ports taken in turn, the end port is exclusive.
+    pub ports: Option<(u16, u16)>,
+    /// Network device the probes leave through, bound with SO_BINDTODEVICE.
+    pub device: Option<String>,
+}
+
+impl SourceBinding {
+    pub fn is_unbound(&self) -> bool {
+        self == &SourceBinding::default()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SubnetScanConfiguration {
-    pub subnet: Ipv4Net,
-    pub begin_port: u16,
-    pub end_port: u16,
+    pub target: ScanTarget,
+    pub target_hosts: Arc<RangeSet<Ipv4Addr>>,
+    pub target_ports: Arc<RangeSet<u16>>,
+    pub exclusions: Arc<Exclusions>,
+    /// Names the hosts were resolved from, kept on their scan results.
+    pub hostnames: Arc<HashMap<Ipv4Addr, Arc<str>>>,
+    /// Names of the services on the ports, kept on their scan results.
+    #[serde(default)]
+    pub service_names: Arc<HashMap<u16, Arc<str>>>,
+    pub order: ScanOrder,
+    pub shard: Shard,
+    pub route: ProbeRoute,
+    pub source: Arc<SourceBinding>,
 }
 
-#[derive(Debug, Copy, Clone)]
+impl SubnetScanConfiguration {
+    /// Scans the hosts of `target` on the ports from `begin_port` up to,
+    /// excluding, `end_port`.
+    pub fn new(target: impl Into<ScanTarget>, begin_port: u16, end_port: u16) -> Self {
+        let mut target_ports = RangeSet::new();
+        if begin_port < end_port {
+            target_ports.insert(begin_port..=end_port - 1);
+        }
+
+        Self::for_ports(target, target_ports)
+    }
+
+    /// Scans the hosts of `target` on `target_ports`.
+    pub fn for_ports(target: impl Into<ScanTarget>, target_ports: RangeSet<u16>) -> Self {
+        let target = target.into();
+
+        Self {
+            target_hosts: Arc::new(target.hosts()),
+            target,
+            target_ports: Arc::new(target_ports),
+            exclusions: Arc::default(),
+            hostnames: Arc::default(),
+            service_names: Arc::default(),
+            order: ScanOrder::default(),
+            shard: Shard::default(),
+            route: ProbeRoute::default(),
+            source: Arc::default(),
+        }
+    }
+
+    /// Replaces the hosts derived from the target, as needed by target lists.
+    pub fn with_target_hosts(mut self, target_hosts: RangeSet<Ipv4Addr>) -> Self {
+        self.target_hosts = Arc::new(target_hosts);
+        self
+    }
+
+    pub fn with_hostnames(mut self, hostnames: HashMap<Ipv4Addr, Arc<str>>) -> Self {
+        self.hostnames = Arc::new(hostnames);
+        self
+    }
+
+    pub fn with_service_names(mut self, service_names: HashMap<u16, Arc<str>>) -> Self {
+        self.service_names = Arc::new(service_names);
+        self
+    }
+
+    pub fn with_order(mut self, order: ScanOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    pub fn with_shard(mut self, shard: Shard) -> Self {
+        self.shard = shard;
+        self
+    }
+
+    pub fn with_route(mut self, route: ProbeRoute) -> Self {
+        self.route = route;
+        self
+    }
+
+    pub fn with_source(mut self, source: Arc<SourceBinding>) -> Self {
+        self.source = source;
+        self
+    }
+
+    pub fn with_exclusions(mut self, exclusions: Arc<Exclusions>) -> Self {
+        self.exclusions = exclusions;
+        self
+    }
+
+    /// Hosts of the target that are not excluded, in scan order.
+    pub fn hosts(&self) -> RangeSet<Ipv4Addr> {
+        self.target_hosts
+            .difference(&self.exclusions.hosts)
+    }
+
+    /// Ports of the target that are not excluded, probed on every host.
+    pub fn ports(&self) -> RangeSet<u16> {
+        self.target_ports
+            .difference(&self.exclusions.ports)
+    }
+
+    /// Probes of the target, its hosts times its ports.
+    pub fn total_probe_count(&self) -> u64 {
+        self.hosts().len() * self.ports().len()
+    }
+
+    /// Probes this process makes, the share of the total taken by its shard.
+    pub fn probe_count(&self) -> u64 {
+        self.shard
+            .probe_count(self.total_probe_count())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct IpPortScanResult {
     pub ip: Ipv4Addr,
     pub port: u16,
     pub state: PortState,
+    /// Hostname the address was resolved from, when scanned by name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<Arc<str>>,
+    /// Name of the service registered for the port.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<Arc<str>>,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum PortState {
     Open,
     Closed,
     TimeOut,
 }
+
+#[cfg(test)]
+mod host_address_mode_tests {
+    use std::net::Ipv4Addr;
+
+    use ipnet::Ipv4Net;
+
+    use crate::models::{HostAddressMode, ScanTarget, SubnetScanConfiguration};
+
+    const MODES: [HostAddressMode; 4] = [
+        HostAddressMode::Usable,
+        HostAddressMode::IncludeNetwork,
+        HostAddressMode::IncludeBroadcast,
+        HostAddressMode::All,
+    ];
+
+    fn subnet(subnet: &str) -> ScanTarget {
+        ScanTarget::Subnet(subnet.parse::<Ipv4Net>().unwrap())
+    }
+
+    fn hosts(target: &ScanTarget, mode: HostAddressMode) -> Vec<Ipv4Addr> {
+        target
+            .hosts_with(mode)
+            .iter()
+            .collect()
+    }
+
+    #[test]
+    fn should_include_the_network_and_broadcast_addresses_of_the_mode() {
+        let target = subnet("10.0.0.0/30");
+        let ip = |last_octet| Ipv4Addr::new(10, 0, 0, last_octet);
+
+        assert_eq!(hosts(&target, HostAddressMode::Usable), vec![ip(1), ip(2)]);
+        assert_eq!(
+            hosts(&target, HostAddressMode::IncludeNetwork),
+            vec![ip(0), ip(1), ip(2)]
+        );
+        assert_eq!(
+            hosts(&target, HostAddressMode::IncludeBroadcast),
+            vec![ip(1), ip(2), ip(3)]
+        );
+        assert_eq!(
+            hosts(&target, HostAddressMode::All),
+            vec![ip(0), ip(1), ip(2), ip(3)]
+        );
+        assert_eq!(target.hosts(), target.hosts_with(HostAddressMode::Usable));
+    }
+
+    #[test]
+    fn should_scan_point_to_point_and_single_host_subnets_whole() {
+        for mode in MODES {
+            assert_eq!(
+                hosts(&subnet("192.168.7.6/31"), mode),
+                vec![Ipv4Addr::new(192, 168, 7, 6), Ipv4Addr::new(192, 168, 7, 7)]
+            );
+            assert_eq!(
+                hosts(&subnet("192.168.7.9/32"), mode),
+                vec![Ipv4Addr::new(192, 168, 7, 9)]
+            );
+        }
+    }
+
+    #[test]
+    fn should_count_the_hosts_of_the_widest_prefixes() {
+        let expected_counts = [
+            (
+                "0.0.0.0/0",
+                [(1 << 32) - 2, (1 << 32) - 1, (1 << 32) - 1, 1 << 32],
+            ),
+            (
+                "0.0.0.0/1",
+                [(1 << 31) - 2, (1 << 31) - 1, (1 << 31) - 1, 1 << 31],
+            ),
+            ("10.0.0.0/29", [6, 7, 7, 8]),
+        ];
+        for (target, counts) in expected_counts {
+            let host_counts: Vec<u64> = MODES
+                .iter()
+                .map(|mode| {
+                    subnet(target)
+                        .hosts_with(*mode)
+                        .len()
+                })
+                .collect();
+            assert_eq!(host_counts, counts, "{}", target);
+        }
+    }
+
+    #[test]
+    fn should_plan_no_probes_without_ports() {
+        for target in ["10.0.0.0/24", "10.0.0.0/31", "10.0.0.1/32"] {
+            let config = SubnetScanConfiguration::new(subnet(target), 80, 80);
+
+            assert!(config.ports().is_empty());
+            assert_eq!(config.total_probe_count(), 0);
+            assert_eq!(config.probe_count(), 0);
+        }
+        let reversed_ports = SubnetScanConfiguration::new(subnet("10.0.0.0/24"), 81, 80);
+        assert_eq!(reversed_ports.total_probe_count(), 0);
+    }
+}
+
+#[cfg(test)]
+mod shard_tests {
+    use crate::models::Shard;
+
+    #[test]
+    fn should_parse_shards() {
+        assert_eq!(
+            "2/3".parse::<Shard>().unwrap(),
+            Shard { index: 2, count: 3 }
+        );
+        for invalid_shard in ["0/3", "4/3", "1", "a/3"] {
+            assert_eq!(
+                invalid_shard
+                    .parse::<Shard>()
+                    .err()
+                    .unwrap()
+                    .to_string(),
+                format!(
+                    "Invalid shard `{}`, expected <index>/<count> with 1 <= index <= count",
+                    invalid_shard
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn should_split_the_probes_between_shards() {
+        let probe_counts: Vec<u64> = (1..=4)
+            .map(|index| Shard { index, count: 4 }.probe_count(10))
+            .collect();
+
+        assert_eq!(probe_counts, vec![3, 3, 2, 2]);
+        assert_eq!(Shard { index: 3, count: 3 }.probe_count(1), 0);
+        assert_eq!(Shard::default().probe_count(10), 10);
+    }
+}
+
+#[cfg(test)]
+mod probe_route_tests {
+    use crate::models::ProbeRoute;
+
+    #[test]
+    fn should_parse_proxies() {
+        assert_eq!(
+            "socks5://127.0.0.1:1080"
+                .parse::<ProbeRoute>()
+                .unwrap(),
+            ProbeRoute::Socks5("127.0.0.1:1080".parse().unwrap())
+        );
+        assert_eq!(
+            "http://10.0.0.1:3128/"
+                .parse::<ProbeRoute>()
+                .unwrap(),
+            ProbeRoute::HttpConnect("10.0.0.1:3128".parse().unwrap())
+        );
+        for invalid_proxy in ["127.0.0.1:1080", "ftp://127.0.0.1:21", "socks5://bastion"] {
+            assert_eq!(
+                invalid_proxy
+                    .parse::<ProbeRoute>()
+                    .err()
+                    .unwrap()
+                    .to_string(),
+                format!(
+                    "Invalid proxy `{}`, expected socks5://<ip>:<port> or http://<ip>:<port>",
+                    invalid_proxy
+                )
+            );
+        }
+    }
+}
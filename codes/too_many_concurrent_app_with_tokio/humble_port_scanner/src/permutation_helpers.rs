@@ -0,0 +1,129 @@
+const ROUNDS: usize = 4;
+
+/// A seeded bijection of `0..size`, computed one index at a time so even the
+/// probes of a /8 never have to be materialized.
+///
+/// A balanced Feistel network permutes the smallest power of four covering
+/// `size`, and values falling outside `0..size` are walked through the
+/// network again until they land inside it.
+#[derive(Debug, Clone)]
+pub struct FeistelPermutation {
+    size: u64,
+    half_bits: u32,
+    round_keys: [u64; ROUNDS],
+}
+
+impl FeistelPermutation {
+    pub fn new(size: u64, seed: u64) -> Self {
+        let index_bits = u64::BITS
+            - size
+                .saturating_sub(1)
+                .leading_zeros();
+        let half_bits = index_bits.div_ceil(2).max(1);
+
+        let mut key_state = seed;
+        let round_keys = std::array::from_fn(|_| {
+            key_state = key_state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            mix(key_state)
+        });
+
+        Self {
+            size,
+            half_bits,
+            round_keys,
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Position of `index` in the permuted order, `index` must be below the size.
+    pub fn permute(&self, index: u64) -> u64 {
+        debug_assert!(index < self.size, "index {} out of 0..{}", index, self.size);
+
+        let mut value = self.encrypt(index);
+        while value >= self.size {
+            value = self.encrypt(value);
+        }
+        value
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u64> + '_ {
+        (0..self.size).map(|index| self.permute(index))
+    }
+
+    fn encrypt(&self, value: u64) -> u64 {
+        let half_mask = (1 << self.half_bits) - 1;
+        let mut left = value >> self.half_bits;
+        let mut right = value & half_mask;
+
+        for round_key in self.round_keys {
+            let next_right = left ^ (mix(right ^ round_key) & half_mask);
+            left = right;
+            right = next_right;
+        }
+
+        (left << self.half_bits) | right
+    }
+}
+
+// splitmix64 finalizer, spreads every input bit over the whole output.
+pub(crate) fn mix(value: u64) -> u64 {
+    let mut value = value;
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    value ^ (value >> 31)
+}
+
+#[cfg(test)]
+mod permutation_tests {
+    use std::collections::HashSet;
+
+    use crate::permutation_helpers::FeistelPermutation;
+
+    #[test]
+    fn should_visit_every_index_exactly_once() {
+        for size in [1, 2, 3, 7, 64, 1000, 4097] {
+            let permutation = FeistelPermutation::new(size, 42);
+            let visited: HashSet<u64> = permutation.iter().collect();
+
+            assert_eq!(visited.len() as u64, size);
+            assert!(visited
+                .iter()
+                .all(|position| *position < size));
+        }
+    }
+
+    #[test]
+    fn should_be_reproducible_for_a_seed() {
+        let first: Vec<u64> = FeistelPermutation::new(1000, 7)
+            .iter()
+            .collect();
+        let again: Vec<u64> = FeistelPermutation::new(1000, 7)
+            .iter()
+            .collect();
+        let other_seed: Vec<u64> = FeistelPermutation::new(1000, 8)
+            .iter()
+            .collect();
+
+        assert_eq!(first, again);
+        assert_ne!(first, other_seed);
+        assert_ne!(first, (0..1000).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn should_permute_huge_spaces_lazily() {
+        // every port of every address of a /8.
+        let permutation = FeistelPermutation::new((1 << 24) * 65536, 1);
+
+        let positions: HashSet<u64> = (0..10_000)
+            .map(|index| permutation.permute(index))
+            .collect();
+
+        assert_eq!(positions.len(), 10_000);
+        assert!(positions
+            .iter()
+            .all(|position| *position < permutation.size()));
+    }
+}
@@ -0,0 +1,359 @@
+use std::{fmt, fs, net::Ipv4Addr, path::Path};
+
+use anyhow::{bail, Context};
+use ipnet::Ipv4Net;
+use serde::{Deserialize, Serialize};
+
+use crate::{errors::AppErrors, scan_report::ScanReport};
+
+/// Exposure a rule allows on the hosts it applies to.
+///
+/// A rule without a subnet applies to every scanned host. `allow` lists the
+/// only ports that may be open, `deny` the ports that must never be open.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PolicyRule {
+    pub name: Option<String>,
+    pub subnet: Option<Ipv4Net>,
+    pub allow: Option<Vec<u16>>,
+    #[serde(default)]
+    pub deny: Vec<u16>,
+}
+
+impl PolicyRule {
+    fn applies_to(&self, ip: Ipv4Addr) -> bool {
+        self.subnet
+            .is_none_or(|subnet| subnet.contains(&ip))
+    }
+
+    fn violation_kind(&self, port: u16) -> Option<ViolationKind> {
+        if self.deny.contains(&port) {
+            return Some(ViolationKind::Denied);
+        }
+
+        match &self.allow {
+            Some(allowed_ports) if !allowed_ports.contains(&port) => {
+                Some(ViolationKind::NotAllowed)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for PolicyRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(name) = &self.name {
+            return write!(f, "{}", name);
+        }
+
+        match self.subnet {
+            Some(subnet) => write!(f, "{}", subnet)?,
+            None => write!(f, "any host")?,
+        }
+        if let Some(allowed_ports) = &self.allow {
+            write!(f, " allow {:?}", allowed_ports)?;
+        }
+        if !self.deny.is_empty() {
+            write!(f, " deny {:?}", self.deny)?;
+        }
+        Ok(())
+    }
+}
+
+/// Expected exposure, loaded from a TOML (or `.json`) rules file:
+///
+/// ```toml
+/// [[rule]]
+/// subnet = "10.1.0.0/24"
+/// allow = [22, 443]
+///
+/// [[rule]]
+/// name = "no exposed databases"
+/// deny = [3306, 5432]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    #[serde(rename = "rule", default)]
+    pub rules: Vec<PolicyRule>,
+}
+
+impl Policy {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        Self::parse(path).context(format!("Invalid policy rules in {}", path.display()))
+    }
+
+    fn parse(path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path).context(format!(
+            "Unable to read policy rules from {}",
+            path.display()
+        ))?;
+
+        let policy: Policy = match path.extension() {
+            Some(extension) if extension == "json" => serde_json::from_str(&content)?,
+            _ => toml::from_str(&content)?,
+        };
+        policy.validate()?;
+
+        Ok(policy)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for rule in &self.rules {
+            if rule.allow.is_none() && rule.deny.is_empty() {
+                bail!(AppErrors::InvalidPolicyRuleError {
+                    rule: rule.to_string(),
+                    reason: String::from("a rule needs an allow or a deny list"),
+                })
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks every open port of `scan_report` against the rules applying to its host.
+    pub fn evaluate(&self, scan_report: &ScanReport) -> PolicyReport {
+        let mut violations = Vec::new();
+
+        for host in scan_report.hosts() {
+            for open_port in &host.open_ports {
+                for rule in self
+                    .rules
+                    .iter()
+                    .filter(|rule| rule.applies_to(host.ip))
+                {
+                    if let Some(kind) = rule.violation_kind(open_port.port) {
+                        violations.push(Violation {
+                            rule: rule.to_string(),
+                            ip: host.ip,
+                            port: open_port.port,
+                            service: open_port.service.clone(),
+                            kind,
+                        });
+                    }
+                }
+            }
+        }
+
+        PolicyReport {
+            violations,
+            scan_errors: scan_report.errors(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ViolationKind {
+    NotAllowed,
+    Denied,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Violation {
+    pub rule: String,
+    pub ip: Ipv4Addr,
+    pub port: u16,
+    pub service: Option<String>,
+    pub kind: ViolationKind,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            ViolationKind::NotAllowed => "is not allowed",
+            ViolationKind::Denied => "is denied",
+        };
+        write!(
+            f,
+            "{}:{}/tcp ({}) {} by rule `{}`",
+            self.ip,
+            self.port,
+            self.service
+                .as_deref()
+                .unwrap_or("unknown"),
+            kind,
+            self.rule
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyReport {
+    pub violations: Vec<Violation>,
+    /// Failed scans, whose hosts could not be fully checked.
+    pub scan_errors: Vec<String>,
+}
+
+impl PolicyReport {
+    /// Compliance is only vouched for when the scan covered everything.
+    pub fn is_compliant(&self) -> bool {
+        self.violations.is_empty() && self.scan_errors.is_empty()
+    }
+}
+
+impl fmt::Display for PolicyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Policy check: {} violation(s), {} scan error(s)",
+            self.violations.len(),
+            self.scan_errors.len()
+        )?;
+
+        for violation in &self.violations {
+            writeln!(f, "violation: {}", violation)?;
+        }
+        for scan_error in &self.scan_errors {
+            writeln!(f, "incomplete: {}", scan_error)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod policy_tests {
+    use std::{
+        net::Ipv4Addr,
+        time::{Duration, SystemTime},
+    };
+
+    use crate::{
+        models::ScanTarget,
+        policy::{Policy, ViolationKind},
+        scan_report::{HostReport, OpenPort, ScanReport, StateCounts, SubnetReport},
+        services,
+        target_helpers::RangeSet,
+        task_supervisor::TaskOutcome,
+    };
+
+    const RULES: &str = r#"
+        [[rule]]
+        subnet = "10.1.0.0/24"
+        allow = [22, 443]
+
+        [[rule]]
+        name = "no exposed databases"
+        deny = [3306]
+    "#;
+
+    fn subnet_report(
+        subnet: &str,
+        hosts: &[(Ipv4Addr, &[u16])],
+        outcome: TaskOutcome,
+    ) -> SubnetReport {
+        SubnetReport {
+            target: ScanTarget::Subnet(subnet.parse().unwrap()),
+            hosts: hosts
+                .iter()
+                .map(|(ip, open_ports)| HostReport {
+                    ip: *ip,
+                    hostname: None,
+                    open_ports: open_ports
+                        .iter()
+                        .map(|port| OpenPort {
+                            port: *port,
+                            service: services::service_name(*port).map(String::from),
+                        })
+                        .collect(),
+                    closed_ports: RangeSet::new(),
+                    timed_out_ports: RangeSet::new(),
+                    state_counts: StateCounts::default(),
+                })
+                .collect(),
+            state_counts: StateCounts::default(),
+            outcome: Some(outcome),
+            restarts: 0,
+            planned_probes: 0,
+            not_scanned: 0,
+            unscanned: Vec::new(),
+        }
+    }
+
+    fn report(subnets: Vec<SubnetReport>) -> ScanReport {
+        ScanReport {
+            subnets,
+            task_failures: Vec::new(),
+            started_at: SystemTime::UNIX_EPOCH,
+            duration: Duration::from_secs(1),
+        }
+    }
+
+    #[test]
+    fn should_report_ports_outside_the_allowed_and_denied_ones() {
+        let policy: Policy = toml::from_str(RULES).unwrap();
+        let scan_report = report(vec![
+            subnet_report(
+                "10.1.0.0/24",
+                &[
+                    (Ipv4Addr::new(10, 1, 0, 1), &[22, 443]),
+                    (Ipv4Addr::new(10, 1, 0, 2), &[22, 3306]),
+                ],
+                TaskOutcome::Completed,
+            ),
+            subnet_report(
+                "10.2.0.0/24",
+                &[(Ipv4Addr::new(10, 2, 0, 1), &[80, 3306])],
+                TaskOutcome::Completed,
+            ),
+        ]);
+
+        let policy_report = policy.evaluate(&scan_report);
+        let violations: Vec<(Ipv4Addr, u16, ViolationKind)> = policy_report
+            .violations
+            .iter()
+            .map(|violation| (violation.ip, violation.port, violation.kind))
+            .collect();
+
+        assert!(!policy_report.is_compliant());
+        assert_eq!(
+            violations,
+            vec![
+                (Ipv4Addr::new(10, 1, 0, 2), 3306, ViolationKind::NotAllowed),
+                (Ipv4Addr::new(10, 1, 0, 2), 3306, ViolationKind::Denied),
+                (Ipv4Addr::new(10, 2, 0, 1), 3306, ViolationKind::Denied),
+            ]
+        );
+        assert_eq!(
+            policy_report.violations[0].to_string(),
+            "10.1.0.2:3306/tcp (mysql) is not allowed by rule `10.1.0.0/24 allow [22, 443]`"
+        );
+        assert_eq!(policy_report.violations[1].rule, "no exposed databases");
+    }
+
+    #[test]
+    fn should_not_vouch_for_incomplete_scans() {
+        let policy: Policy = toml::from_str(RULES).unwrap();
+        let scan_report = report(vec![subnet_report(
+            "10.1.0.0/24",
+            &[(Ipv4Addr::new(10, 1, 0, 1), &[22])],
+            TaskOutcome::Failed(String::from("channel closed")),
+        )]);
+
+        let policy_report = policy.evaluate(&scan_report);
+
+        assert!(policy_report.violations.is_empty());
+        assert!(!policy_report.is_compliant());
+    }
+
+    #[test]
+    fn should_reject_rules_without_ports() {
+        let policy: Policy = toml::from_str(
+            r#"
+            [[rule]]
+            subnet = "10.1.0.0/24"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            policy
+                .validate()
+                .err()
+                .unwrap()
+                .to_string(),
+            "Invalid policy rule `10.1.0.0/24`: a rule needs an allow or a deny list"
+        );
+        assert!(toml::from_str::<Policy>("[[rule]]\nports = [22]").is_err());
+    }
+}
@@ -1,61 +1,479 @@
-use std::{net::Ipv4Addr, time::Duration};
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
 
-use crate::models::{IpPortScanResult, PortState};
+use anyhow::{bail, Context};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpSocket, TcpStream},
+    time,
+};
+use tracing::Span;
 
-use tokio::{net::TcpStream, time};
+use crate::{
+    connector::Connector,
+    errors::AppErrors,
+    models::{IpPortScanResult, PortState, ProbeRoute, SourceBinding},
+};
 
+const SOCKS_VERSION: u8 = 5;
+const SOCKS_NO_AUTHENTICATION: u8 = 0;
+const SOCKS_CONNECT: u8 = 1;
+const SOCKS_IPV4: u8 = 1;
+
+// rotates over the source port range, shared by every probe of the process.
+static NEXT_SOURCE_PORT: AtomicU64 = AtomicU64::new(0);
+
+/// Probes `port` on `ip` with `connector`, along `route` and from `source`. A
+/// port that does not answer within `timeout` is reported as timed out, a proxy
+/// that cannot be used is an error rather than a port state.
+#[tracing::instrument(
+    name = "probe",
+    level = "debug",
+    skip(connector, route, source, timeout),
+    fields(state = tracing::field::Empty, latency_us = tracing::field::Empty)
+)]
 pub async fn check_port_status_with_timeout(
+    connector: &dyn Connector,
+    route: ProbeRoute,
+    source: &SourceBinding,
     ip: Ipv4Addr,
     port: u16,
     timeout: Duration,
-) -> IpPortScanResult {
-    let stream = time::timeout(timeout, async { TcpStream::connect((ip, port)).await }).await;
-
-    let state = match stream {
+) -> anyhow::Result<IpPortScanResult> {
+    let probe_start = Instant::now();
+    let state = match time::timeout(timeout, connector.connect(route, source, ip, port)).await {
         Err(_) => PortState::TimeOut,
-        Ok(tcp_result) if tcp_result.is_ok() => PortState::Open,
-        _ => PortState::Closed,
+        Ok(state) => state?,
+    };
+
+    let span = Span::current();
+    span.record("state", tracing::field::debug(state));
+    span.record("latency_us", probe_start.elapsed().as_micros() as u64);
+
+    Ok(IpPortScanResult {
+        ip,
+        port,
+        state,
+        hostname: None,
+        service: None,
+    })
+}
+
+/// Connects to `port` on `ip` over the network, along `route` and from `source`.
+pub(crate) async fn connect(
+    route: ProbeRoute,
+    source: &SourceBinding,
+    ip: Ipv4Addr,
+    port: u16,
+) -> anyhow::Result<PortState> {
+    match route {
+        ProbeRoute::Direct => match connect_from(source, (ip, port).into())?.await {
+            Ok(_) => Ok(PortState::Open),
+            // the local end could not be set up, which says nothing of the port.
+            Err(connect_error) if is_local_error(&connect_error) => {
+                Err(connect_error).context(format!("Unable to send a probe to {}:{}", ip, port))
+            }
+            Err(_) => Ok(PortState::Closed),
+        },
+        ProbeRoute::Socks5(proxy_addr) => {
+            let stream = connect_to_proxy(source, proxy_addr).await?;
+            connect_through_socks5(stream, proxy_addr, ip, port).await
+        }
+        ProbeRoute::HttpConnect(proxy_addr) => {
+            let stream = connect_to_proxy(source, proxy_addr).await?;
+            connect_through_http(stream, proxy_addr, ip, port).await
+        }
+    }
+}
+
+fn is_local_error(connect_error: &io::Error) -> bool {
+    matches!(
+        connect_error.kind(),
+        io::ErrorKind::AddrNotAvailable | io::ErrorKind::AddrInUse
+    )
+}
+
+// a socket that cannot be bound as asked is an error, only the outcome of the
+// connection itself tells the port state.
+fn connect_from(
+    source: &SourceBinding,
+    addr: SocketAddr,
+) -> anyhow::Result<impl std::future::Future<Output = io::Result<TcpStream>>> {
+    let socket = bind_source_socket(source, addr)?;
+    Ok(socket.connect(addr))
+}
+
+async fn connect_to_proxy(
+    source: &SourceBinding,
+    proxy_addr: SocketAddr,
+) -> anyhow::Result<TcpStream> {
+    connect_from(source, proxy_addr)?
+        .await
+        .context(format!("Unable to connect to the proxy at {}", proxy_addr))
+}
+
+/// A socket for connecting to `addr`, bound as asked by `source`.
+fn bind_source_socket(source: &SourceBinding, addr: SocketAddr) -> anyhow::Result<TcpSocket> {
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
     };
+    if source.is_unbound() {
+        return Ok(socket);
+    }
 
-    IpPortScanResult { ip, port, state }
+    if let Some(device) = &source.device {
+        bind_device(&socket, device)
+            .context(format!("Unable to bind probes to device {}", device))?;
+    }
+
+    let address = source
+        .address
+        .unwrap_or(Ipv4Addr::UNSPECIFIED);
+    match source.ports {
+        Some((begin_port, end_port)) => {
+            validate_source_ports(source)?;
+            // SO_REUSEADDR lets the probes take the ports back from their
+            // connections in TIME_WAIT, probes sharing a port still have their
+            // own destinations.
+            socket.set_reuseaddr(true)?;
+            let port_count = u64::from(end_port - begin_port);
+            let offset = NEXT_SOURCE_PORT.fetch_add(1, Ordering::Relaxed) % port_count;
+            let source_port = begin_port + offset as u16;
+            socket
+                .bind((address, source_port).into())
+                .context(format!(
+                    "Unable to bind probes to {}:{}",
+                    address, source_port
+                ))?;
+        }
+        None if source.address.is_some() => {
+            socket
+                .bind((address, 0).into())
+                .context(format!("Unable to bind probes to {}", address))?;
+        }
+        None => {}
+    }
+
+    Ok(socket)
+}
+
+#[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+fn bind_device(socket: &TcpSocket, device: &str) -> io::Result<()> {
+    socket.bind_device(Some(device.as_bytes()))
+}
+
+#[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+fn bind_device(_socket: &TcpSocket, _device: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "binding to a device is only supported on Linux",
+    ))
+}
+
+/// Checks that probes can be sent from `source`: its address is assigned to
+/// this host, its port range is not empty and its device can be bound.
+pub fn validate_source(source: &SourceBinding) -> anyhow::Result<()> {
+    if let Some(address) = source.address {
+        std::net::UdpSocket::bind((address, 0))
+            .map_err(|_| AppErrors::SourceAddressNotLocalError { address })?;
+    }
+    validate_source_ports(source)?;
+    if let Some(device) = &source.device {
+        bind_device(&TcpSocket::new_v4()?, device)
+            .context(format!("Unable to bind probes to device {}", device))?;
+    }
+
+    Ok(())
+}
+
+/// Checks that the source port range of `source` is not empty, the part of the
+/// source that does not depend on the host sending the probes.
+pub fn validate_source_ports(source: &SourceBinding) -> anyhow::Result<()> {
+    if let Some((begin_port, end_port)) = source.ports {
+        if begin_port >= end_port {
+            bail!("Source port range {}:{} is empty", begin_port, end_port)
+        }
+    }
+
+    Ok(())
+}
+
+// RFC 1928, the reply code tells how the proxy's own connection attempt went.
+async fn connect_through_socks5(
+    mut stream: TcpStream,
+    proxy_addr: SocketAddr,
+    ip: Ipv4Addr,
+    port: u16,
+) -> anyhow::Result<PortState> {
+    let proxy_closed = || format!("SOCKS5 proxy at {} closed the connection", proxy_addr);
+
+    stream
+        .write_all(&[SOCKS_VERSION, 1, SOCKS_NO_AUTHENTICATION])
+        .await?;
+    let mut method = [0_u8; 2];
+    stream
+        .read_exact(&mut method)
+        .await
+        .with_context(proxy_closed)?;
+    if method != [SOCKS_VERSION, SOCKS_NO_AUTHENTICATION] {
+        bail!(
+            "SOCKS5 proxy at {} requires an authentication, which is not supported",
+            proxy_addr
+        )
+    }
+
+    let mut request = vec![SOCKS_VERSION, SOCKS_CONNECT, 0, SOCKS_IPV4];
+    request.extend_from_slice(&ip.octets());
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0_u8; 2];
+    stream
+        .read_exact(&mut reply)
+        .await
+        .with_context(proxy_closed)?;
+
+    match reply[1] {
+        0x00 => Ok(PortState::Open),
+        // proxies such as OpenSSH report any failed connection, refused ones
+        // included, as a general failure.
+        0x01 | 0x05 => Ok(PortState::Closed),
+        // network or host unreachable and TTL expired: nothing answered. A
+        // destination the ruleset does not allow is filtered, as by a firewall.
+        0x02 | 0x03 | 0x04 | 0x06 => Ok(PortState::TimeOut),
+        reply_code => bail!(
+            "SOCKS5 proxy at {} failed to connect to {}:{} with reply code {:#04x}",
+            proxy_addr,
+            ip,
+            port,
+            reply_code
+        ),
+    }
+}
+
+// proxies report a refused connection as a bad gateway or an unavailable
+// service, and one that got no answer as a gateway timeout. A destination the
+// proxy forbids is filtered, as by a firewall.
+async fn connect_through_http(
+    stream: TcpStream,
+    proxy_addr: SocketAddr,
+    ip: Ipv4Addr,
+    port: u16,
+) -> anyhow::Result<PortState> {
+    let mut stream = BufReader::new(stream);
+
+    stream
+        .write_all(format!("CONNECT {ip}:{port} HTTP/1.1\r\nHost: {ip}:{port}\r\n\r\n").as_bytes())
+        .await?;
+    let mut status_line = String::new();
+    stream
+        .read_line(&mut status_line)
+        .await
+        .context(format!(
+            "HTTP proxy at {} closed the connection",
+            proxy_addr
+        ))?;
+    let status_code = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status_code| status_code.parse::<u16>().ok())
+        .context(format!(
+            "Invalid response from the HTTP proxy at {}: {:?}",
+            proxy_addr, status_line
+        ))?;
+
+    match status_code {
+        200..=299 => Ok(PortState::Open),
+        502 | 503 => Ok(PortState::Closed),
+        403 | 504 => Ok(PortState::TimeOut),
+        _ => bail!(
+            "HTTP proxy at {} refused to connect to {}:{}: {}",
+            proxy_addr,
+            ip,
+            port,
+            status_line.trim()
+        ),
+    }
+}
+
+/// SOCKS5 and HTTP CONNECT proxies standing in for real ones in tests. They
+/// connect to the requested address and answer accordingly, unless an
+/// address is given a fixed reply.
+#[cfg(test)]
+pub(crate) mod proxy_stand_in {
+    use std::{collections::HashMap, net::SocketAddr};
+
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::{TcpListener, TcpStream},
+    };
+
+    /// `replies` maps target addresses to a SOCKS5 reply code.
+    pub async fn spawn_socks5(replies: &[(SocketAddr, u8)]) -> SocketAddr {
+        let replies: HashMap<SocketAddr, u8> = replies.iter().copied().collect();
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            while let Ok((mut client, _)) = listener.accept().await {
+                let replies = replies.clone();
+                tokio::spawn(async move {
+                    let mut greeting = [0_u8; 3];
+                    client
+                        .read_exact(&mut greeting)
+                        .await?;
+                    client.write_all(&[5, 0]).await?;
+
+                    let mut request = [0_u8; 10];
+                    client
+                        .read_exact(&mut request)
+                        .await?;
+                    let target = SocketAddr::from((
+                        [request[4], request[5], request[6], request[7]],
+                        u16::from_be_bytes([request[8], request[9]]),
+                    ));
+                    let reply_code = match replies.get(&target) {
+                        Some(reply_code) => *reply_code,
+                        None if TcpStream::connect(target)
+                            .await
+                            .is_ok() =>
+                        {
+                            0x00
+                        }
+                        None => 0x05,
+                    };
+
+                    client
+                        .write_all(&[5, reply_code, 0, 1, 0, 0, 0, 0, 0, 0])
+                        .await
+                });
+            }
+        });
+
+        proxy_addr
+    }
+
+    /// `replies` maps target addresses to an HTTP status code.
+    pub async fn spawn_http(replies: &[(SocketAddr, u16)]) -> SocketAddr {
+        let replies: HashMap<SocketAddr, u16> = replies.iter().copied().collect();
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            while let Ok((client, _)) = listener.accept().await {
+                let replies = replies.clone();
+                tokio::spawn(async move {
+                    let mut client = BufReader::new(client);
+                    let mut request_line = String::new();
+                    client
+                        .read_line(&mut request_line)
+                        .await?;
+                    let target: SocketAddr = request_line
+                        .split_whitespace()
+                        .nth(1)
+                        .and_then(|target| target.parse().ok())
+                        .unwrap();
+
+                    let status_code = match replies.get(&target) {
+                        Some(status_code) => *status_code,
+                        None if TcpStream::connect(target)
+                            .await
+                            .is_ok() =>
+                        {
+                            200
+                        }
+                        None => 502,
+                    };
+
+                    client
+                        .write_all(format!("HTTP/1.1 {} Stand-in\r\n\r\n", status_code).as_bytes())
+                        .await
+                });
+            }
+        });
+
+        proxy_addr
+    }
 }
 
 #[cfg(test)]
 mod port_status_tests {
-    use std::{net::Ipv4Addr, time::Duration};
+    use std::{
+        net::{Ipv4Addr, SocketAddr},
+        time::Duration,
+    };
 
     use anyhow::Context;
 
     use crate::{
-        models::{IpPortScanResult, PortState},
-        port_helpers::check_port_status_with_timeout,
+        connector::NetworkConnector,
+        models::{IpPortScanResult, PortState, ProbeRoute, SourceBinding},
+        port_helpers::{check_port_status_with_timeout, proxy_stand_in, validate_source},
+        simulated_network::{SimulatedLink, SimulatedNetwork},
     };
 
+    async fn probe_through(route: ProbeRoute, target: SocketAddr) -> anyhow::Result<PortState> {
+        let SocketAddr::V4(target) = target else {
+            panic!("stand-in targets are IPv4");
+        };
+
+        check_port_status_with_timeout(
+            &NetworkConnector,
+            route,
+            &SourceBinding::default(),
+            *target.ip(),
+            target.port(),
+            Duration::from_secs(1),
+        )
+        .await
+        .map(|scan_result| scan_result.state)
+    }
+
     #[tokio::test]
     async fn should_return_closed_state_for_a_closed_port() {
         let scan_result = check_port_status_with_timeout(
+            &NetworkConnector,
+            ProbeRoute::Direct,
+            &SourceBinding::default(),
             "127.0.0.1"
                 .parse::<Ipv4Addr>()
                 .unwrap(),
             15411_u16,
             Duration::from_secs(1),
         )
-        .await;
+        .await
+        .unwrap();
         assert_eq!(scan_result.state, PortState::Closed);
     }
 
-    #[tokio::test]
-    async fn should_return_timeout_state_for_non_routable_ip() {
-        // hopefully there is no NAT setup on this ip.
+    #[tokio::test(start_paused = true)]
+    async fn should_return_timeout_state_for_a_port_that_never_answers() {
+        let network = SimulatedNetwork::default()
+            .with_host(Ipv4Addr::new(172, 31, 255, 255), SimulatedLink::dropped());
+        let probe_start = tokio::time::Instant::now();
+
         let scan_result = check_port_status_with_timeout(
-            "172.31.255.255"
-                .parse::<Ipv4Addr>()
-                .unwrap(),
+            &network,
+            ProbeRoute::Direct,
+            &SourceBinding::default(),
+            Ipv4Addr::new(172, 31, 255, 255),
             5432_u16,
             Duration::from_secs(1),
         )
-        .await;
+        .await
+        .unwrap();
+
         assert_eq!(scan_result.state, PortState::TimeOut);
+        assert_eq!(probe_start.elapsed(), Duration::from_secs(1));
     }
 
     #[tokio::test]
@@ -94,6 +512,7 @@ mod port_status_tests {
         // define the time interval in which this task needs to succeed, otherwise fail it.
         let mut test_interval_timeout = tokio::time::interval(Duration::from_secs(3));
 
+        let source = SourceBinding::default();
         let mut received_open_port_scan_result = false;
         loop {
             tokio::select! {
@@ -101,11 +520,14 @@ mod port_status_tests {
                     break;
                 },
                 scan_result = check_port_status_with_timeout(
+                        &NetworkConnector,
+                        ProbeRoute::Direct,
+                        &source,
                         "127.0.0.1".parse::<Ipv4Addr>().unwrap(),
                         random_port,
                         Duration::from_secs(1)) => {
 
-                    if let IpPortScanResult{state: PortState::Open, ..} = scan_result {
+                    if let Ok(IpPortScanResult{state: PortState::Open, ..}) = scan_result {
                         received_open_port_scan_result = true;
                         break;
                     }
@@ -113,9 +535,263 @@ mod port_status_tests {
             } // select!
         } // loop
 
-        assert_eq!(
-            received_open_port_scan_result, true,
+        assert!(
+            received_open_port_scan_result,
             "Failed to receive open port scan result in time"
         );
     }
+
+    #[tokio::test]
+    async fn should_map_socks5_replies_to_port_states() {
+        let open_port = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let open_addr = open_port.local_addr().unwrap();
+        let closed_addr: SocketAddr = "127.0.0.1:15411".parse().unwrap();
+        let unreachable_addr: SocketAddr = "10.255.0.1:22".parse().unwrap();
+        let ttl_expired_addr: SocketAddr = "10.255.0.2:22".parse().unwrap();
+        let forbidden_addr: SocketAddr = "10.255.0.3:22".parse().unwrap();
+        let failed_addr: SocketAddr = "10.255.0.4:22".parse().unwrap();
+        let unsupported_addr: SocketAddr = "10.255.0.5:22".parse().unwrap();
+        let proxy_addr = proxy_stand_in::spawn_socks5(&[
+            (unreachable_addr, 0x04),
+            (ttl_expired_addr, 0x06),
+            (forbidden_addr, 0x02),
+            (failed_addr, 0x01),
+            (unsupported_addr, 0x07),
+        ])
+        .await;
+        let route = ProbeRoute::Socks5(proxy_addr);
+
+        assert_eq!(
+            probe_through(route, open_addr)
+                .await
+                .unwrap(),
+            PortState::Open
+        );
+        assert_eq!(
+            probe_through(route, closed_addr)
+                .await
+                .unwrap(),
+            PortState::Closed
+        );
+        assert_eq!(
+            probe_through(route, unreachable_addr)
+                .await
+                .unwrap(),
+            PortState::TimeOut
+        );
+        assert_eq!(
+            probe_through(route, ttl_expired_addr)
+                .await
+                .unwrap(),
+            PortState::TimeOut
+        );
+        assert_eq!(
+            probe_through(route, forbidden_addr)
+                .await
+                .unwrap(),
+            PortState::TimeOut
+        );
+        assert_eq!(
+            probe_through(route, failed_addr)
+                .await
+                .unwrap(),
+            PortState::Closed
+        );
+        assert_eq!(
+            probe_through(route, unsupported_addr)
+                .await
+                .err()
+                .unwrap()
+                .to_string(),
+            format!(
+                "SOCKS5 proxy at {} failed to connect to 10.255.0.5:22 with reply code 0x07",
+                proxy_addr
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn should_map_http_connect_statuses_to_port_states() {
+        let open_port = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let open_addr = open_port.local_addr().unwrap();
+        let closed_addr: SocketAddr = "127.0.0.1:15411".parse().unwrap();
+        let silent_addr: SocketAddr = "10.255.0.1:22".parse().unwrap();
+        let authenticated_addr: SocketAddr = "10.255.0.2:22".parse().unwrap();
+        let forbidden_addr: SocketAddr = "10.255.0.3:22".parse().unwrap();
+        let proxy_addr = proxy_stand_in::spawn_http(&[
+            (silent_addr, 504),
+            (authenticated_addr, 407),
+            (forbidden_addr, 403),
+        ])
+        .await;
+        let route = ProbeRoute::HttpConnect(proxy_addr);
+
+        assert_eq!(
+            probe_through(route, open_addr)
+                .await
+                .unwrap(),
+            PortState::Open
+        );
+        assert_eq!(
+            probe_through(route, closed_addr)
+                .await
+                .unwrap(),
+            PortState::Closed
+        );
+        assert_eq!(
+            probe_through(route, silent_addr)
+                .await
+                .unwrap(),
+            PortState::TimeOut
+        );
+        assert_eq!(
+            probe_through(route, forbidden_addr)
+                .await
+                .unwrap(),
+            PortState::TimeOut
+        );
+        assert!(probe_through(route, authenticated_addr)
+            .await
+            .err()
+            .unwrap()
+            .to_string()
+            .ends_with("refused to connect to 10.255.0.2:22: HTTP/1.1 407 Stand-in"));
+    }
+
+    #[tokio::test]
+    async fn should_fail_probes_when_the_proxy_is_unreachable() {
+        let unused_port = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let proxy_addr = unused_port.local_addr().unwrap();
+        drop(unused_port);
+
+        let probe_result = probe_through(
+            ProbeRoute::Socks5(proxy_addr),
+            "127.0.0.1:15411".parse().unwrap(),
+        )
+        .await;
+
+        assert_eq!(
+            probe_result
+                .err()
+                .unwrap()
+                .to_string(),
+            format!("Unable to connect to the proxy at {}", proxy_addr)
+        );
+    }
+
+    #[tokio::test]
+    async fn should_send_probes_from_the_source_address_and_ports() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let target_port = listener
+            .local_addr()
+            .unwrap()
+            .port();
+        let source = SourceBinding {
+            address: Some(Ipv4Addr::new(127, 0, 0, 2)),
+            ports: Some((47100, 47110)),
+            device: None,
+        };
+        validate_source(&source).unwrap();
+
+        for _ in 0..3 {
+            let scan_result = check_port_status_with_timeout(
+                &NetworkConnector,
+                ProbeRoute::Direct,
+                &source,
+                Ipv4Addr::LOCALHOST,
+                target_port,
+                Duration::from_secs(1),
+            )
+            .await
+            .unwrap();
+            let (_, peer_addr) = listener.accept().await.unwrap();
+
+            assert_eq!(scan_result.state, PortState::Open);
+            assert_eq!(peer_addr.ip(), Ipv4Addr::new(127, 0, 0, 2));
+            assert!((47100..47110).contains(&peer_addr.port()));
+        }
+    }
+
+    #[tokio::test]
+    async fn should_reuse_a_source_port_after_its_connection_closed() {
+        let first_listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let second_listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let source = SourceBinding {
+            address: Some(Ipv4Addr::new(127, 0, 0, 3)),
+            ports: Some((47120, 47121)),
+            device: None,
+        };
+
+        // the probe closes first, its port is left in TIME_WAIT.
+        for listener in [&first_listener, &second_listener] {
+            let scan_result = check_port_status_with_timeout(
+                &NetworkConnector,
+                ProbeRoute::Direct,
+                &source,
+                Ipv4Addr::LOCALHOST,
+                listener
+                    .local_addr()
+                    .unwrap()
+                    .port(),
+                Duration::from_secs(1),
+            )
+            .await
+            .unwrap();
+            let (_, peer_addr) = listener.accept().await.unwrap();
+
+            assert_eq!(scan_result.state, PortState::Open);
+            assert_eq!(peer_addr.port(), 47120);
+        }
+    }
+
+    #[test]
+    fn should_refuse_sources_that_cannot_be_bound() {
+        let foreign_address = SourceBinding {
+            address: Some(Ipv4Addr::new(192, 0, 2, 1)),
+            ..SourceBinding::default()
+        };
+        let empty_ports = SourceBinding {
+            ports: Some((47100, 47100)),
+            ..SourceBinding::default()
+        };
+        let missing_device = SourceBinding {
+            device: Some(String::from("nosuchdev0")),
+            ..SourceBinding::default()
+        };
+
+        assert_eq!(
+            validate_source(&foreign_address)
+                .err()
+                .unwrap()
+                .to_string(),
+            "Source address 192.0.2.1 is not assigned to a local interface"
+        );
+        assert_eq!(
+            validate_source(&empty_ports)
+                .err()
+                .unwrap()
+                .to_string(),
+            "Source port range 47100:47100 is empty"
+        );
+        assert_eq!(
+            validate_source(&missing_device)
+                .err()
+                .unwrap()
+                .to_string(),
+            "Unable to bind probes to device nosuchdev0"
+        );
+        assert!(validate_source(&SourceBinding::default()).is_ok());
+    }
 }
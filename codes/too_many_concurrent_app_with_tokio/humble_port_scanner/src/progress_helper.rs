@@ -1,54 +1,69 @@
 use std::collections::HashMap;
 
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use ipnet::Ipv4Net;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+use crate::models::ScanTarget;
 
 pub struct ScanProgressTracker {
-    subnet_to_pb: HashMap<Ipv4Net, ProgressBar>,
-    subnet_progress: HashMap<Ipv4Net, u64>,
-    subnet_total_scans: HashMap<Ipv4Net, u64>,
+    target_to_pb: HashMap<ScanTarget, ProgressBar>,
+    target_progress: HashMap<ScanTarget, u64>,
+    target_total_scans: HashMap<ScanTarget, u64>,
     multi_pb: MultiProgress,
     progress_bar_size: u64,
 }
 
 impl ScanProgressTracker {
     pub fn new(progress_bar_size: u64) -> Self {
-        let multi_pb = MultiProgress::new();
-        let subnet_to_pb: HashMap<Ipv4Net, ProgressBar> = HashMap::new();
-        let subnet_progress: HashMap<Ipv4Net, u64> = HashMap::new();
-        let subnet_total_scans: HashMap<Ipv4Net, u64> = HashMap::new();
+        Self::with_multi_progress(MultiProgress::new(), progress_bar_size)
+    }
+
+    /// Tracks the progress without drawing it, e.g. for scans run by a server.
+    pub fn hidden(progress_bar_size: u64) -> Self {
+        Self::with_multi_progress(
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+            progress_bar_size,
+        )
+    }
+
+    fn with_multi_progress(multi_pb: MultiProgress, progress_bar_size: u64) -> Self {
+        let target_to_pb: HashMap<ScanTarget, ProgressBar> = HashMap::new();
+        let target_progress: HashMap<ScanTarget, u64> = HashMap::new();
+        let target_total_scans: HashMap<ScanTarget, u64> = HashMap::new();
 
         Self {
-            subnet_progress,
-            subnet_to_pb,
+            target_progress,
+            target_to_pb,
             multi_pb,
-            subnet_total_scans,
+            target_total_scans,
             progress_bar_size,
         }
     }
 
-    pub fn initate_subnet_progress(&mut self, subnet: Ipv4Net, num_ports: u64) {
+    pub fn initate_target_progress(&mut self, target: &ScanTarget, total_scans: u64) {
         let style = Self::get_style();
         let pb = self
             .multi_pb
             .add(ProgressBar::new(self.progress_bar_size));
         pb.set_style(style.clone());
-        self.subnet_to_pb
-            .insert(subnet, pb);
-        self.subnet_progress
-            .insert(subnet, 0);
-        self.subnet_total_scans
-            .insert(subnet, (subnet.hosts().count() as u64) * num_ports);
+        self.target_to_pb
+            .insert(target.clone(), pb);
+        self.target_progress
+            .insert(target.clone(), 0);
+        self.target_total_scans
+            .insert(target.clone(), total_scans);
     }
 
-    pub fn update_progress(&mut self, subnet: Ipv4Net) {
-        self.subnet_progress
-            .entry(subnet)
-            .and_modify(|v| *v += 1);
+    pub fn update_progress(&mut self, target: &ScanTarget) {
+        if let Some(progress) = self
+            .target_progress
+            .get_mut(target)
+        {
+            *progress += 1;
+        }
 
-        let position = self.progress_bar_size
-            * (self.subnet_progress[&subnet] / self.subnet_total_scans[&subnet]);
-        self.subnet_to_pb[&subnet].set_position(position);
+        let position = self.progress_bar_size * self.target_progress[target]
+            / self.target_total_scans[target].max(1);
+        self.target_to_pb[target].set_position(position);
     }
 
     fn get_style() -> ProgressStyle {
@@ -59,8 +74,8 @@ impl ScanProgressTracker {
         .progress_chars("##-")
     }
 
-    pub fn complete_progress(&mut self, subnet: Ipv4Net) {
-        self.subnet_to_pb[&subnet]
-            .finish_with_message(format!("subnet {} scanning is done!", subnet));
+    pub fn complete_progress(&mut self, target: &ScanTarget) {
+        self.target_to_pb[target]
+            .finish_with_message(format!("target {} scanning is done!", target));
     }
 }
@@ -0,0 +1,207 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs};
+
+use anyhow::{bail, Context};
+use hickory_resolver::{
+    config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+    Resolver,
+};
+
+use crate::errors::AppErrors;
+
+/// How hostname targets are turned into addresses.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HostResolver {
+    /// The system resolver, honouring `/etc/hosts` and the configured DNS servers.
+    #[default]
+    System,
+    /// A single DNS server queried over UDP, such as a local DNS stand-in.
+    Dns(SocketAddr),
+}
+
+impl HostResolver {
+    /// Every IPv4 address of `hostname`, sorted and without duplicates.
+    ///
+    /// Blocks until resolution is done, so it must not be called from within
+    /// a tokio runtime.
+    pub fn resolve(&self, hostname: &str) -> anyhow::Result<Vec<Ipv4Addr>> {
+        let mut addresses = match self {
+            HostResolver::System => resolve_with_system(hostname),
+            HostResolver::Dns(server) => resolve_with_dns_server(hostname, *server),
+        }
+        .context(format!("Unable to resolve host: {}", hostname))?;
+
+        addresses.sort_unstable();
+        addresses.dedup();
+        if addresses.is_empty() {
+            bail!(AppErrors::NoIpv4AddressError {
+                hostname: hostname.to_string(),
+            })
+        }
+
+        Ok(addresses)
+    }
+}
+
+fn resolve_with_system(hostname: &str) -> anyhow::Result<Vec<Ipv4Addr>> {
+    Ok((hostname, 0)
+        .to_socket_addrs()?
+        .filter_map(|socket_addr| match socket_addr.ip() {
+            IpAddr::V4(ip) => Some(ip),
+            IpAddr::V6(_) => None,
+        })
+        .collect())
+}
+
+fn resolve_with_dns_server(hostname: &str, server: SocketAddr) -> anyhow::Result<Vec<Ipv4Addr>> {
+    let name_servers = NameServerConfigGroup::from_ips_clear(&[server.ip()], server.port(), true);
+    let resolver = Resolver::new(
+        ResolverConfig::from_parts(None, Vec::new(), name_servers),
+        ResolverOpts::default(),
+    )?;
+
+    Ok(resolver
+        .ipv4_lookup(hostname)?
+        .iter()
+        .map(|record| record.0)
+        .collect())
+}
+
+/// Whether `spec` is shaped like a DNS hostname rather than an address.
+pub fn is_hostname(spec: &str) -> bool {
+    let spec = spec
+        .strip_suffix('.')
+        .unwrap_or(spec);
+    let labels: Vec<&str> = spec.split('.').collect();
+
+    let valid_labels = labels.iter().all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .chars()
+                .all(|character| character.is_ascii_alphanumeric() || character == '-')
+    });
+    // an all numeric last label is a mistyped address, never a top level domain.
+    let numeric_top_level = labels.last().is_some_and(|label| {
+        label
+            .chars()
+            .all(|character| character.is_ascii_digit())
+    });
+
+    !spec.is_empty() && spec.len() <= 253 && valid_labels && !numeric_top_level
+}
+
+/// A DNS server answering A queries from a fixed table, standing in for a real
+/// one in tests.
+#[cfg(test)]
+pub(crate) mod dns_stand_in {
+    use std::{
+        collections::HashMap,
+        net::{Ipv4Addr, SocketAddr, UdpSocket},
+    };
+
+    const NO_ERROR: u16 = 0x8180;
+    const NAME_ERROR: u16 = 0x8183;
+
+    pub fn spawn(records: &[(&str, &[Ipv4Addr])]) -> SocketAddr {
+        let records: HashMap<String, Vec<Ipv4Addr>> = records
+            .iter()
+            .map(|(name, addresses)| (name.to_ascii_lowercase(), addresses.to_vec()))
+            .collect();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = socket.local_addr().unwrap();
+
+        std::thread::spawn(move || {
+            let mut query = [0_u8; 512];
+            while let Ok((query_len, client)) = socket.recv_from(&mut query) {
+                if let Some(response) = answer(&query[..query_len], &records) {
+                    let _ = socket.send_to(&response, client);
+                }
+            }
+        });
+
+        server
+    }
+
+    fn answer(query: &[u8], records: &HashMap<String, Vec<Ipv4Addr>>) -> Option<Vec<u8>> {
+        let mut labels = Vec::new();
+        let mut position = 12;
+        while *query.get(position)? != 0 {
+            let label_len = query[position] as usize;
+            labels.push(String::from_utf8_lossy(
+                query.get(position + 1..position + 1 + label_len)?,
+            ));
+            position += 1 + label_len;
+        }
+        let question_end = position + 5;
+        let name = labels
+            .join(".")
+            .to_ascii_lowercase();
+
+        let addresses = records
+            .get(&name)
+            .cloned()
+            .unwrap_or_default();
+        let flags = if records.contains_key(&name) {
+            NO_ERROR
+        } else {
+            NAME_ERROR
+        };
+
+        let mut response = Vec::with_capacity(question_end + addresses.len() * 16);
+        response.extend_from_slice(query.get(..2)?);
+        response.extend_from_slice(&flags.to_be_bytes());
+        response.extend_from_slice(&[0, 1]);
+        response.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+        response.extend_from_slice(&[0, 0, 0, 0]);
+        response.extend_from_slice(query.get(12..question_end)?);
+        for address in addresses {
+            // name pointer to the question, type A, class IN, 60s ttl, 4 bytes of data.
+            response.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+            response.extend_from_slice(&address.octets());
+        }
+
+        Some(response)
+    }
+}
+
+#[cfg(test)]
+mod resolve_tests {
+    use std::net::Ipv4Addr;
+
+    use crate::resolve_helpers::{dns_stand_in, is_hostname, HostResolver};
+
+    #[test]
+    fn should_resolve_every_address_through_the_configured_server() {
+        let server = dns_stand_in::spawn(&[(
+            "web.scan.test",
+            &[Ipv4Addr::new(10, 0, 0, 7), Ipv4Addr::new(10, 0, 0, 3)],
+        )]);
+        let resolver = HostResolver::Dns(server);
+
+        assert_eq!(
+            resolver
+                .resolve("web.scan.test")
+                .unwrap(),
+            vec![Ipv4Addr::new(10, 0, 0, 3), Ipv4Addr::new(10, 0, 0, 7)]
+        );
+        assert!(resolver
+            .resolve("missing.scan.test")
+            .err()
+            .unwrap()
+            .to_string()
+            .starts_with("Unable to resolve host: missing.scan.test"));
+    }
+
+    #[test]
+    fn should_tell_hostnames_from_addresses() {
+        assert!(is_hostname("localhost"));
+        assert!(is_hostname("db-1.internal.example.com."));
+        assert!(!is_hostname("10.0.0.300"));
+        assert!(!is_hostname("10.0.0.5-10.0.0.40"));
+        assert!(!is_hostname("-bad.example.com"));
+        assert!(!is_hostname("under_score.example.com"));
+        assert!(!is_hostname(""));
+    }
+}
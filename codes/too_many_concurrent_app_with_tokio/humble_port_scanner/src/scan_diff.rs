@@ -0,0 +1,404 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    net::Ipv4Addr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::scan_report::{HostReport, ScanReport};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortChange {
+    pub ip: Ipv4Addr,
+    pub port: u16,
+    pub service: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceChange {
+    pub ip: Ipv4Addr,
+    pub port: u16,
+    pub old_service: Option<String>,
+    pub new_service: Option<String>,
+}
+
+/// Exposure changes between an earlier and a later scan.
+///
+/// Only hosts scanned by both runs are compared port by port, and a port only
+/// counts as opened or closed when both runs probed it, so a narrower later
+/// scan does not report everything it skipped as closed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanDiff {
+    pub opened_ports: Vec<PortChange>,
+    pub closed_ports: Vec<PortChange>,
+    pub new_hosts: Vec<Ipv4Addr>,
+    pub disappeared_hosts: Vec<Ipv4Addr>,
+    pub service_changes: Vec<ServiceChange>,
+}
+
+impl ScanDiff {
+    pub fn is_empty(&self) -> bool {
+        self.opened_ports.is_empty()
+            && self.closed_ports.is_empty()
+            && self.new_hosts.is_empty()
+            && self.disappeared_hosts.is_empty()
+            && self.service_changes.is_empty()
+    }
+}
+
+fn hosts_by_ip<'a>(
+    hosts: impl IntoIterator<Item = &'a HostReport>,
+) -> BTreeMap<Ipv4Addr, &'a HostReport> {
+    hosts
+        .into_iter()
+        .map(|host| (host.ip, host))
+        .collect()
+}
+
+// whether `port` was probed on `host` and found closed or unanswered.
+fn was_shut(host: &HostReport, port: u16) -> bool {
+    host.closed_ports.contains(port) || host.timed_out_ports.contains(port)
+}
+
+fn open_ports(host: &HostReport) -> BTreeMap<u16, Option<String>> {
+    host.open_ports
+        .iter()
+        .map(|open_port| (open_port.port, open_port.service.clone()))
+        .collect()
+}
+
+pub fn diff_reports(old: &ScanReport, new: &ScanReport) -> ScanDiff {
+    diff_hosts(old.hosts(), new.hosts())
+}
+
+/// Compares the hosts known before with the hosts scanned since, see [`ScanDiff`].
+pub fn diff_hosts<'a>(
+    old: impl IntoIterator<Item = &'a HostReport>,
+    new: impl IntoIterator<Item = &'a HostReport>,
+) -> ScanDiff {
+    let old_hosts = hosts_by_ip(old);
+    let new_hosts = hosts_by_ip(new);
+    let mut scan_diff = ScanDiff::default();
+
+    let scanned_ips: BTreeSet<Ipv4Addr> = old_hosts
+        .keys()
+        .chain(new_hosts.keys())
+        .copied()
+        .collect();
+
+    for ip in scanned_ips {
+        let (old_host, new_host) = match (old_hosts.get(&ip), new_hosts.get(&ip)) {
+            (Some(old_host), Some(new_host)) => (old_host, new_host),
+            (None, Some(new_host)) => {
+                if new_host.is_up() {
+                    scan_diff.new_hosts.push(ip);
+                    scan_diff.opened_ports.extend(
+                        open_ports(new_host)
+                            .into_iter()
+                            .map(|(port, service)| PortChange { ip, port, service }),
+                    );
+                }
+                continue;
+            }
+            // not scanned this time, so nothing is known about it.
+            _ => continue,
+        };
+
+        match (old_host.is_up(), new_host.is_up()) {
+            (false, true) => scan_diff.new_hosts.push(ip),
+            (true, false) => scan_diff
+                .disappeared_hosts
+                .push(ip),
+            _ => {}
+        }
+
+        let old_ports = open_ports(old_host);
+        let new_ports = open_ports(new_host);
+
+        for (port, service) in &new_ports {
+            match old_ports.get(port) {
+                None if was_shut(old_host, *port) => scan_diff
+                    .opened_ports
+                    .push(PortChange {
+                        ip,
+                        port: *port,
+                        service: service.clone(),
+                    }),
+                Some(old_service) if old_service != service => {
+                    scan_diff
+                        .service_changes
+                        .push(ServiceChange {
+                            ip,
+                            port: *port,
+                            old_service: old_service.clone(),
+                            new_service: service.clone(),
+                        })
+                }
+                _ => {}
+            }
+        }
+
+        for (port, service) in old_ports {
+            if was_shut(new_host, port) {
+                scan_diff
+                    .closed_ports
+                    .push(PortChange { ip, port, service });
+            }
+        }
+    }
+
+    scan_diff
+}
+
+fn service_label(service: &Option<String>) -> &str {
+    service
+        .as_deref()
+        .unwrap_or("unknown")
+}
+
+impl fmt::Display for ScanDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No exposure changes.");
+        }
+
+        for ip in &self.new_hosts {
+            writeln!(f, "+ host {}", ip)?;
+        }
+        for ip in &self.disappeared_hosts {
+            writeln!(f, "- host {}", ip)?;
+        }
+        for change in &self.opened_ports {
+            writeln!(
+                f,
+                "+ {}:{}/tcp opened ({})",
+                change.ip,
+                change.port,
+                service_label(&change.service)
+            )?;
+        }
+        for change in &self.closed_ports {
+            writeln!(
+                f,
+                "- {}:{}/tcp closed ({})",
+                change.ip,
+                change.port,
+                service_label(&change.service)
+            )?;
+        }
+        for change in &self.service_changes {
+            writeln!(
+                f,
+                "~ {}:{}/tcp service {} -> {}",
+                change.ip,
+                change.port,
+                service_label(&change.old_service),
+                service_label(&change.new_service)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod scan_diff_tests {
+    use std::{
+        net::Ipv4Addr,
+        time::{Duration, SystemTime},
+    };
+
+    use crate::{
+        models::ScanTarget,
+        scan_diff::{diff_reports, PortChange, ServiceChange},
+        scan_report::{HostReport, OpenPort, ScanReport, StateCounts, SubnetReport},
+        task_supervisor::TaskOutcome,
+    };
+
+    fn host(
+        last_octet: u8,
+        open_ports: &[(u16, Option<&str>)],
+        closed_ports: &[u16],
+        timed_out_ports: &[u16],
+    ) -> HostReport {
+        HostReport {
+            ip: Ipv4Addr::new(10, 0, 0, last_octet),
+            hostname: None,
+            open_ports: open_ports
+                .iter()
+                .map(|(port, service)| OpenPort {
+                    port: *port,
+                    service: service.map(String::from),
+                })
+                .collect(),
+            closed_ports: closed_ports
+                .iter()
+                .map(|port| *port..=*port)
+                .collect(),
+            timed_out_ports: timed_out_ports
+                .iter()
+                .map(|port| *port..=*port)
+                .collect(),
+            state_counts: StateCounts {
+                open: open_ports.len() as u64,
+                closed: closed_ports.len() as u64,
+                timeout: timed_out_ports.len() as u64,
+            },
+        }
+    }
+
+    fn report(hosts: Vec<HostReport>) -> ScanReport {
+        ScanReport {
+            subnets: vec![SubnetReport {
+                target: ScanTarget::Subnet("10.0.0.0/24".parse().unwrap()),
+                hosts,
+                state_counts: StateCounts::default(),
+                outcome: Some(TaskOutcome::Completed),
+                restarts: 0,
+                planned_probes: 0,
+                not_scanned: 0,
+                unscanned: Vec::new(),
+            }],
+            task_failures: Vec::new(),
+            started_at: SystemTime::UNIX_EPOCH,
+            duration: Duration::from_secs(1),
+        }
+    }
+
+    #[test]
+    fn should_report_no_changes_for_identical_scans() {
+        let scan = report(vec![host(1, &[(22, Some("ssh"))], &[23], &[])]);
+        let scan_diff = diff_reports(&scan, &scan);
+
+        assert!(scan_diff.is_empty());
+        assert_eq!(scan_diff.to_string(), "No exposure changes.\n");
+    }
+
+    #[test]
+    fn should_detect_port_host_and_service_changes() {
+        let old = report(vec![
+            host(1, &[(22, Some("ssh")), (80, Some("http"))], &[3306], &[]),
+            host(2, &[(443, Some("https"))], &[], &[]),
+            host(3, &[], &[], &[8080]),
+        ]);
+        let new = report(vec![
+            host(
+                1,
+                &[(22, Some("ssh")), (80, Some("http-alt")), (3306, None)],
+                &[],
+                &[],
+            ),
+            host(2, &[], &[], &[443]),
+            host(3, &[(8080, None)], &[], &[]),
+            host(4, &[(6379, Some("redis"))], &[], &[]),
+        ]);
+
+        let scan_diff = diff_reports(&old, &new);
+
+        assert_eq!(
+            scan_diff.new_hosts,
+            vec![Ipv4Addr::new(10, 0, 0, 3), Ipv4Addr::new(10, 0, 0, 4)]
+        );
+        assert_eq!(
+            scan_diff.disappeared_hosts,
+            vec![Ipv4Addr::new(10, 0, 0, 2)]
+        );
+        assert_eq!(
+            scan_diff.opened_ports,
+            vec![
+                PortChange {
+                    ip: Ipv4Addr::new(10, 0, 0, 1),
+                    port: 3306,
+                    service: None
+                },
+                PortChange {
+                    ip: Ipv4Addr::new(10, 0, 0, 3),
+                    port: 8080,
+                    service: None
+                },
+                PortChange {
+                    ip: Ipv4Addr::new(10, 0, 0, 4),
+                    port: 6379,
+                    service: Some(String::from("redis"))
+                },
+            ]
+        );
+        assert_eq!(
+            scan_diff.closed_ports,
+            vec![PortChange {
+                ip: Ipv4Addr::new(10, 0, 0, 2),
+                port: 443,
+                service: Some(String::from("https"))
+            }]
+        );
+        assert_eq!(
+            scan_diff.service_changes,
+            vec![ServiceChange {
+                ip: Ipv4Addr::new(10, 0, 0, 1),
+                port: 80,
+                old_service: Some(String::from("http")),
+                new_service: Some(String::from("http-alt"))
+            }]
+        );
+
+        let rendered = scan_diff.to_string();
+        assert!(rendered.contains("+ 10.0.0.4:6379/tcp opened (redis)"));
+        assert!(rendered.contains("- 10.0.0.2:443/tcp closed (https)"));
+        assert!(rendered.contains("~ 10.0.0.1:80/tcp service http -> http-alt"));
+    }
+
+    #[test]
+    fn should_ignore_hosts_missing_from_the_later_scan() {
+        let old = report(vec![host(1, &[(22, Some("ssh"))], &[], &[])]);
+        let new = report(vec![]);
+
+        assert!(diff_reports(&old, &new).is_empty());
+    }
+
+    #[test]
+    fn should_only_compare_ports_probed_by_both_scans() {
+        // -p 1:1024 earlier, -p 20:30 later.
+        let old = report(vec![host(
+            1,
+            &[(22, Some("ssh")), (80, Some("http")), (443, Some("https"))],
+            &[1..=21, 23..=79, 81..=442, 444..=1023]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>(),
+            &[],
+        )]);
+        let new = report(vec![host(
+            1,
+            &[(25, Some("smtp"))],
+            &[20..=24, 26..=29]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>(),
+            &[],
+        )]);
+
+        let scan_diff = diff_reports(&old, &new);
+
+        assert_eq!(
+            scan_diff.opened_ports,
+            vec![PortChange {
+                ip: Ipv4Addr::new(10, 0, 0, 1),
+                port: 25,
+                service: Some(String::from("smtp"))
+            }]
+        );
+        assert_eq!(
+            scan_diff.closed_ports,
+            vec![PortChange {
+                ip: Ipv4Addr::new(10, 0, 0, 1),
+                port: 22,
+                service: Some(String::from("ssh"))
+            }]
+        );
+        assert!(scan_diff.new_hosts.is_empty());
+        assert!(scan_diff
+            .disappeared_hosts
+            .is_empty());
+    }
+}
@@ -0,0 +1,1043 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+    net::Ipv4Addr,
+    ops::RangeInclusive,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    models::{IpPortScanResult, PortState, ScanTarget, Shard, SubnetScanConfiguration},
+    target_helpers::{RangeSet, RangeValue},
+    task_supervisor::{TaskOutcome, TaskReport},
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateCounts {
+    pub open: u64,
+    pub closed: u64,
+    pub timeout: u64,
+}
+
+impl StateCounts {
+    pub fn record(&mut self, state: PortState) {
+        match state {
+            PortState::Open => self.open += 1,
+            PortState::Closed => self.closed += 1,
+            PortState::TimeOut => self.timeout += 1,
+        }
+    }
+
+    pub fn add(&mut self, other: &StateCounts) {
+        self.open += other.open;
+        self.closed += other.closed;
+        self.timeout += other.timeout;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.open + self.closed + self.timeout
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpenPort {
+    pub port: u16,
+    pub service: Option<String>,
+}
+
+impl fmt::Display for OpenPort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.service {
+            Some(service) => write!(f, "{}/tcp ({})", self.port, service),
+            None => write!(f, "{}/tcp", self.port),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostReport {
+    pub ip: Ipv4Addr,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    pub open_ports: Vec<OpenPort>,
+    /// Ports refusing the connection, empty in the reports of older versions.
+    #[serde(default, skip_serializing_if = "RangeSet::is_empty")]
+    pub closed_ports: RangeSet<u16>,
+    /// Ports that never answered, empty in the reports of older versions.
+    #[serde(default, skip_serializing_if = "RangeSet::is_empty")]
+    pub timed_out_ports: RangeSet<u16>,
+    pub state_counts: StateCounts,
+}
+
+impl HostReport {
+    fn new(ip: Ipv4Addr, hostname: Option<String>) -> Self {
+        Self {
+            ip,
+            hostname,
+            open_ports: Vec::new(),
+            closed_ports: RangeSet::new(),
+            timed_out_ports: RangeSet::new(),
+            state_counts: StateCounts::default(),
+        }
+    }
+
+    /// The address, followed by the hostname it was resolved from if any.
+    pub fn label(&self) -> String {
+        match &self.hostname {
+            Some(hostname) => format!("{} ({})", self.ip, hostname),
+            None => self.ip.to_string(),
+        }
+    }
+
+    /// A host answering with a connect or a reset is up, only silence means down.
+    pub fn is_up(&self) -> bool {
+        self.state_counts.open + self.state_counts.closed > 0
+    }
+
+    fn merge(&mut self, other: HostReport) {
+        self.state_counts
+            .add(&other.state_counts);
+        for open_port in other.open_ports {
+            if !self
+                .open_ports
+                .iter()
+                .any(|known| known.port == open_port.port)
+            {
+                self.open_ports.push(open_port);
+            }
+        }
+        self.open_ports
+            .sort_by_key(|open_port| open_port.port);
+        for ports in other.closed_ports.ranges() {
+            self.closed_ports.insert(ports);
+        }
+        for ports in other.timed_out_ports.ranges() {
+            self.timed_out_ports.insert(ports);
+        }
+        if self.hostname.is_none() {
+            self.hostname = other.hostname;
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SubnetReport {
+    /// Saved as `subnet` by the versions scanning subnets only.
+    #[serde(alias = "subnet")]
+    pub target: ScanTarget,
+    pub hosts: Vec<HostReport>,
+    pub state_counts: StateCounts,
+    pub outcome: Option<TaskOutcome>,
+    pub restarts: u32,
+    /// Probes the scan set out to make, 0 in the reports of older versions.
+    #[serde(default)]
+    pub planned_probes: u64,
+    /// Planned probes never issued, e.g. left over when the time budget ran out.
+    #[serde(default)]
+    pub not_scanned: u64,
+    /// Hosts and ports of the probes never issued, not listed for sharded scans.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unscanned: Vec<UnscannedProbes>,
+}
+
+/// Probes of every port of `ports` on every host of `hosts` that were never issued.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnscannedProbes {
+    pub hosts: RangeSet<Ipv4Addr>,
+    pub ports: RangeSet<u16>,
+}
+
+impl fmt::Display for UnscannedProbes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ports {}",
+            format_ranges(&self.hosts),
+            format_ranges(&self.ports)
+        )
+    }
+}
+
+fn format_ranges<T: RangeValue + PartialEq + fmt::Display>(range_set: &RangeSet<T>) -> String {
+    range_set
+        .ranges()
+        .map(|range| {
+            if range.start() == range.end() {
+                range.start().to_string()
+            } else {
+                format!("{}-{}", range.start(), range.end())
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl SubnetReport {
+    pub fn hosts_up(&self) -> usize {
+        self.hosts
+            .iter()
+            .filter(|host| host.is_up())
+            .count()
+    }
+
+    pub fn hosts_down(&self) -> usize {
+        self.hosts.len() - self.hosts_up()
+    }
+
+    /// Percentage of the planned probes that were made, unknown without a plan.
+    pub fn coverage(&self) -> Option<f64> {
+        (self.planned_probes > 0).then(|| {
+            (self.planned_probes - self.not_scanned) as f64 * 100.0 / self.planned_probes as f64
+        })
+    }
+
+    // a failure in any of the merged scans is the outcome of the whole.
+    fn merge(&mut self, other: SubnetReport) {
+        let mut hosts: BTreeMap<Ipv4Addr, HostReport> = self
+            .hosts
+            .drain(..)
+            .map(|host| (host.ip, host))
+            .collect();
+        for host in other.hosts {
+            match hosts.get_mut(&host.ip) {
+                Some(known) => known.merge(host),
+                None => {
+                    hosts.insert(host.ip, host);
+                }
+            }
+        }
+        self.hosts = hosts.into_values().collect();
+
+        self.state_counts
+            .add(&other.state_counts);
+        self.restarts += other.restarts;
+        self.planned_probes += other.planned_probes;
+        self.not_scanned += other.not_scanned;
+        self.unscanned
+            .extend(other.unscanned);
+        self.outcome = match (self.outcome.take(), other.outcome) {
+            (Some(outcome), _) if !outcome.is_success() => Some(outcome),
+            (_, Some(outcome)) if !outcome.is_success() => Some(outcome),
+            (outcome, other_outcome) => outcome.or(other_outcome),
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScanReport {
+    pub subnets: Vec<SubnetReport>,
+    /// Failed tasks that do not belong to a single target.
+    pub task_failures: Vec<TaskReport>,
+    pub started_at: SystemTime,
+    pub duration: Duration,
+}
+
+impl ScanReport {
+    /// Combines the reports of disjoint scans, such as the shards of one scan.
+    ///
+    /// Sections of the same target are folded together and a host found in
+    /// several reports adds up its probes and open ports.
+    pub fn merge(reports: Vec<ScanReport>) -> anyhow::Result<Self> {
+        let started_at = reports
+            .iter()
+            .map(|report| report.started_at)
+            .min()
+            .context("No scan results to merge")?;
+        let finished_at = reports
+            .iter()
+            .map(|report| report.started_at + report.duration)
+            .max()
+            .unwrap_or(started_at);
+
+        let mut subnets: Vec<SubnetReport> = Vec::new();
+        let mut task_failures = Vec::new();
+        for report in reports {
+            task_failures.extend(report.task_failures);
+            for subnet_report in report.subnets {
+                match subnets
+                    .iter_mut()
+                    .find(|merged| merged.target == subnet_report.target)
+                {
+                    Some(merged) => merged.merge(subnet_report),
+                    None => subnets.push(subnet_report),
+                }
+            }
+        }
+
+        Ok(Self {
+            subnets,
+            task_failures,
+            started_at,
+            duration: finished_at
+                .duration_since(started_at)
+                .unwrap_or_default(),
+        })
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path).context(format!(
+            "Unable to read scan results from {}",
+            path.display()
+        ))?;
+
+        serde_json::from_str(&content).context(format!(
+            "Unable to parse scan results in {}",
+            path.display()
+        ))
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let content = serde_json::to_string_pretty(self)?;
+
+        fs::write(path, content).context(format!(
+            "Unable to write scan results to {}",
+            path.display()
+        ))
+    }
+
+    pub fn hosts(&self) -> impl Iterator<Item = &HostReport> {
+        self.subnets
+            .iter()
+            .flat_map(|subnet_report| subnet_report.hosts.iter())
+    }
+
+    /// Hosts of the subnets whose scan completed, i.e. whose ports were all probed.
+    pub fn completed_hosts(&self) -> impl Iterator<Item = &HostReport> {
+        let results_complete = self.task_failures.is_empty();
+
+        self.subnets
+            .iter()
+            .filter(move |subnet_report| {
+                results_complete
+                    && subnet_report.not_scanned == 0
+                    && subnet_report
+                        .outcome
+                        .as_ref()
+                        .is_some_and(TaskOutcome::is_success)
+            })
+            .flat_map(|subnet_report| subnet_report.hosts.iter())
+    }
+
+    /// Looks up the report of `ip` in whichever target scanned it.
+    pub fn host(&self, ip: Ipv4Addr) -> Option<&HostReport> {
+        self.hosts()
+            .find(|host| host.ip == ip)
+    }
+
+    pub fn state_counts(&self) -> StateCounts {
+        let mut state_counts = StateCounts::default();
+        for subnet_report in &self.subnets {
+            state_counts.add(&subnet_report.state_counts);
+        }
+        state_counts
+    }
+
+    pub fn total_probes(&self) -> u64 {
+        self.state_counts().total()
+    }
+
+    pub fn not_scanned(&self) -> u64 {
+        self.subnets
+            .iter()
+            .map(|subnet_report| subnet_report.not_scanned)
+            .sum()
+    }
+
+    pub fn probe_rate(&self) -> f64 {
+        let seconds = self.duration.as_secs_f64();
+        if seconds == 0.0 {
+            return 0.0;
+        }
+        self.total_probes() as f64 / seconds
+    }
+
+    pub fn total_restarts(&self) -> u32 {
+        self.subnets
+            .iter()
+            .map(|subnet_report| subnet_report.restarts)
+            .sum()
+    }
+
+    pub fn errors(&self) -> Vec<String> {
+        let subnet_errors = self
+            .subnets
+            .iter()
+            .filter_map(|subnet_report| match &subnet_report.outcome {
+                Some(outcome) if !outcome.is_success() => {
+                    Some(format!("target {} {}", subnet_report.target, outcome))
+                }
+                _ => None,
+            });
+        let task_errors = self
+            .task_failures
+            .iter()
+            .map(|task_report| format!("task {} {}", task_report.task_name, task_report.outcome));
+
+        subnet_errors
+            .chain(task_errors)
+            .collect()
+    }
+
+    pub fn has_failures(&self) -> bool {
+        !self.errors().is_empty()
+    }
+}
+
+impl fmt::Display for ScanReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state_counts = self.state_counts();
+        writeln!(
+            f,
+            "Scan summary: {} probes in {:.2?} ({:.1} probes/s), {} open, {} closed, {} timed out, {} restart(s), {} error(s)",
+            self.total_probes(),
+            self.duration,
+            self.probe_rate(),
+            state_counts.open,
+            state_counts.closed,
+            state_counts.timeout,
+            self.total_restarts(),
+            self.errors().len(),
+        )?;
+        if self.not_scanned() > 0 {
+            writeln!(f, "{} probes not scanned", self.not_scanned())?;
+        }
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:<20} {:>8} {:>10} {:>8} {:>8} {:>8} {:>8}  STATUS",
+            "TARGET", "HOSTS UP", "HOSTS DOWN", "OPEN", "CLOSED", "TIMEOUT", "COVERAGE"
+        )?;
+
+        for subnet_report in &self.subnets {
+            let mut status = match &subnet_report.outcome {
+                Some(outcome) => outcome.to_string(),
+                None => String::from("unknown"),
+            };
+            if subnet_report.not_scanned > 0 {
+                status = format!("{}, {} not scanned", status, subnet_report.not_scanned);
+            }
+            let coverage = match subnet_report.coverage() {
+                Some(coverage) => format!("{:.1}%", coverage),
+                None => String::from("-"),
+            };
+            writeln!(
+                f,
+                "{:<20} {:>8} {:>10} {:>8} {:>8} {:>8} {:>8}  {}",
+                subnet_report.target.to_string(),
+                subnet_report.hosts_up(),
+                subnet_report.hosts_down(),
+                subnet_report.state_counts.open,
+                subnet_report.state_counts.closed,
+                subnet_report.state_counts.timeout,
+                coverage,
+                status
+            )?;
+
+            for host in subnet_report
+                .hosts
+                .iter()
+                .filter(|host| host.is_up())
+            {
+                let open_ports = host
+                    .open_ports
+                    .iter()
+                    .map(|open_port| open_port.to_string())
+                    .collect::<Vec<_>>();
+                writeln!(
+                    f,
+                    "  {:<18} open {} closed {} timeout {}{}{}",
+                    host.label(),
+                    host.state_counts.open,
+                    host.state_counts.closed,
+                    host.state_counts.timeout,
+                    if open_ports.is_empty() { "" } else { "  " },
+                    open_ports.join(", ")
+                )?;
+            }
+            for unscanned in &subnet_report.unscanned {
+                writeln!(f, "  not scanned {}", unscanned)?;
+            }
+        }
+
+        for error in self.errors() {
+            writeln!(f, "error: {}", error)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Folds the streamed scan results into per target and per host reports.
+pub struct ScanReportCollector {
+    target_order: Vec<ScanTarget>,
+    hosts: HashMap<ScanTarget, BTreeMap<Ipv4Addr, HostReport>>,
+    planned: HashMap<ScanTarget, PlannedProbes>,
+}
+
+// the probes planned for a target, every port on every host unless sharded.
+#[derive(Default)]
+struct PlannedProbes {
+    probes: u64,
+    hosts: RangeSet<Ipv4Addr>,
+    ports: RangeSet<u16>,
+    sharded: bool,
+}
+
+impl PlannedProbes {
+    // the planned hosts and ports without a result, hosts missing the same
+    // ports grouped together.
+    fn unscanned(&self, hosts: &[HostReport]) -> Vec<UnscannedProbes> {
+        let mut unscanned_hosts: HashMap<Vec<RangeInclusive<u16>>, Vec<RangeInclusive<Ipv4Addr>>> =
+            HashMap::new();
+
+        let probed_hosts: RangeSet<Ipv4Addr> = hosts
+            .iter()
+            .map(|host| host.ip..=host.ip)
+            .collect();
+        let unprobed_hosts = self
+            .hosts
+            .difference(&probed_hosts);
+        if !unprobed_hosts.is_empty() && !self.ports.is_empty() {
+            unscanned_hosts
+                .entry(self.ports.ranges().collect())
+                .or_default()
+                .extend(unprobed_hosts.ranges());
+        }
+
+        for host in hosts
+            .iter()
+            .filter(|host| self.hosts.contains(host.ip))
+        {
+            let scanned_ports: RangeSet<u16> = host
+                .open_ports
+                .iter()
+                .map(|open_port| open_port.port..=open_port.port)
+                .chain(host.closed_ports.ranges())
+                .chain(host.timed_out_ports.ranges())
+                .collect();
+            let missing_ports = self
+                .ports
+                .difference(&scanned_ports);
+            if !missing_ports.is_empty() {
+                unscanned_hosts
+                    .entry(missing_ports.ranges().collect())
+                    .or_default()
+                    .push(host.ip..=host.ip);
+            }
+        }
+
+        let mut unscanned: Vec<UnscannedProbes> = unscanned_hosts
+            .into_iter()
+            .map(|(ports, hosts)| UnscannedProbes {
+                hosts: hosts.into_iter().collect(),
+                ports: ports.into_iter().collect(),
+            })
+            .collect();
+        unscanned.sort_by_key(|unscanned| unscanned.hosts.iter().next());
+        unscanned
+    }
+}
+
+impl ScanReportCollector {
+    pub fn new(targets: impl IntoIterator<Item = ScanTarget>) -> Self {
+        let target_order: Vec<ScanTarget> = targets.into_iter().collect();
+        let hosts = target_order
+            .iter()
+            .map(|target| (target.clone(), BTreeMap::new()))
+            .collect();
+
+        Self {
+            target_order,
+            hosts,
+            planned: HashMap::new(),
+        }
+    }
+
+    /// Adds the probes of `config` to the probes planned for its target, the
+    /// ones without a result are reported as not scanned.
+    pub fn plan(&mut self, config: &SubnetScanConfiguration) {
+        let planned = self
+            .planned
+            .entry(config.target.clone())
+            .or_default();
+        planned.probes += config.probe_count();
+        for hosts in config.hosts().ranges() {
+            planned.hosts.insert(hosts);
+        }
+        for ports in config.ports().ranges() {
+            planned.ports.insert(ports);
+        }
+        planned.sharded |= config.shard != Shard::default();
+    }
+
+    pub fn record(&mut self, target: &ScanTarget, scan_result: &IpPortScanResult) {
+        if !self.hosts.contains_key(target) {
+            self.hosts
+                .insert(target.clone(), BTreeMap::new());
+        }
+
+        let host = self
+            .hosts
+            .get_mut(target)
+            .expect("target hosts were just inserted")
+            .entry(scan_result.ip)
+            .or_insert_with(|| {
+                HostReport::new(
+                    scan_result.ip,
+                    scan_result
+                        .hostname
+                        .as_deref()
+                        .map(String::from),
+                )
+            });
+
+        host.state_counts
+            .record(scan_result.state);
+        match scan_result.state {
+            PortState::Open => host.open_ports.push(OpenPort {
+                port: scan_result.port,
+                service: scan_result
+                    .service
+                    .as_deref()
+                    .map(String::from),
+            }),
+            PortState::Closed => host
+                .closed_ports
+                .insert(scan_result.port..=scan_result.port),
+            PortState::TimeOut => host
+                .timed_out_ports
+                .insert(scan_result.port..=scan_result.port),
+        }
+    }
+
+    pub fn finish(
+        mut self,
+        task_reports: Vec<TaskReport>,
+        started_at: SystemTime,
+        duration: Duration,
+    ) -> ScanReport {
+        let mut target_tasks: HashMap<ScanTarget, TaskReport> = HashMap::new();
+        let mut task_failures = Vec::new();
+        for task_report in task_reports {
+            match &task_report.target {
+                Some(target) => {
+                    target_tasks.insert(target.clone(), task_report);
+                }
+                None if !task_report.outcome.is_success() => task_failures.push(task_report),
+                None => {}
+            }
+        }
+
+        let subnets = self
+            .target_order
+            .into_iter()
+            .map(|target| {
+                let mut hosts: Vec<HostReport> = self
+                    .hosts
+                    .remove(&target)
+                    .unwrap_or_default()
+                    .into_values()
+                    .collect();
+                let mut state_counts = StateCounts::default();
+                for host in hosts.iter_mut() {
+                    host.open_ports
+                        .sort_by_key(|open_port| open_port.port);
+                    state_counts.add(&host.state_counts);
+                }
+
+                let task_report = target_tasks.remove(&target);
+                let planned = self
+                    .planned
+                    .remove(&target)
+                    .unwrap_or_default();
+                let not_scanned = planned
+                    .probes
+                    .saturating_sub(state_counts.total());
+                let unscanned = if not_scanned > 0 && !planned.sharded {
+                    planned.unscanned(&hosts)
+                } else {
+                    Vec::new()
+                };
+                SubnetReport {
+                    target,
+                    hosts,
+                    state_counts,
+                    restarts: task_report
+                        .as_ref()
+                        .map_or(0, |task_report| task_report.restarts),
+                    outcome: task_report.map(|task_report| task_report.outcome),
+                    planned_probes: planned.probes,
+                    not_scanned,
+                    unscanned,
+                }
+            })
+            .collect();
+
+        ScanReport {
+            subnets,
+            task_failures,
+            started_at,
+            duration,
+        }
+    }
+}
+
+#[cfg(test)]
+mod scan_report_tests {
+    use std::{
+        net::Ipv4Addr,
+        sync::Arc,
+        time::{Duration, SystemTime},
+    };
+
+    use ipnet::Ipv4Net;
+
+    use crate::{
+        models::{IpPortScanResult, PortState, ScanTarget, SubnetScanConfiguration},
+        scan_report::{OpenPort, ScanReport, ScanReportCollector, StateCounts, UnscannedProbes},
+        services::ServiceRegistry,
+        task_supervisor::{TaskOutcome, TaskReport},
+    };
+
+    fn scan_result(ip: [u8; 4], port: u16, state: PortState) -> IpPortScanResult {
+        IpPortScanResult {
+            ip: Ipv4Addr::from(ip),
+            port,
+            state,
+            hostname: None,
+            service: ServiceRegistry::embedded().name(port),
+        }
+    }
+
+    fn collect_report() -> ScanReport {
+        let target = ScanTarget::Subnet(
+            "10.0.0.0/30"
+                .parse::<Ipv4Net>()
+                .unwrap(),
+        );
+        let mut collector = ScanReportCollector::new([target.clone()]);
+
+        collector.record(
+            &target,
+            &IpPortScanResult {
+                service: Some(Arc::from("web")),
+                ..scan_result([10, 0, 0, 1], 443, PortState::Open)
+            },
+        );
+        collector.record(&target, &scan_result([10, 0, 0, 1], 22, PortState::Open));
+        collector.record(&target, &scan_result([10, 0, 0, 1], 23, PortState::Closed));
+        collector.record(&target, &scan_result([10, 0, 0, 2], 22, PortState::TimeOut));
+        collector.record(&target, &scan_result([10, 0, 0, 2], 23, PortState::TimeOut));
+
+        collector.finish(
+            vec![
+                TaskReport {
+                    task_name: String::from("scan_10.0.0.0/30"),
+                    target: Some(target),
+                    outcome: TaskOutcome::Completed,
+                    restarts: 1,
+                },
+                TaskReport {
+                    task_name: String::from("stream_progress"),
+                    target: None,
+                    outcome: TaskOutcome::Panicked(String::from("boom")),
+                    restarts: 0,
+                },
+            ],
+            SystemTime::UNIX_EPOCH,
+            Duration::from_secs(2),
+        )
+    }
+
+    #[test]
+    fn should_group_results_by_subnet_and_host() {
+        let report = collect_report();
+        let subnet_report = &report.subnets[0];
+
+        assert_eq!(subnet_report.hosts.len(), 2);
+        assert_eq!(subnet_report.hosts_up(), 1);
+        assert_eq!(subnet_report.hosts_down(), 1);
+        assert_eq!(
+            subnet_report.state_counts,
+            StateCounts {
+                open: 2,
+                closed: 1,
+                timeout: 2
+            }
+        );
+        assert_eq!(
+            subnet_report.hosts[0].open_ports,
+            vec![
+                OpenPort {
+                    port: 22,
+                    service: Some(String::from("ssh"))
+                },
+                OpenPort {
+                    port: 443,
+                    service: Some(String::from("web"))
+                }
+            ]
+        );
+        assert_eq!(subnet_report.restarts, 1);
+    }
+
+    #[test]
+    fn should_compute_totals_and_errors() {
+        let report = collect_report();
+
+        assert_eq!(report.total_probes(), 5);
+        assert_eq!(report.probe_rate(), 2.5);
+        assert_eq!(report.total_restarts(), 1);
+        assert_eq!(
+            report.errors(),
+            vec![String::from("task stream_progress panicked: boom")]
+        );
+        assert!(report.has_failures());
+    }
+
+    #[test]
+    fn should_merge_the_reports_of_shards() {
+        let target = ScanTarget::Subnet(
+            "10.0.0.0/30"
+                .parse::<Ipv4Net>()
+                .unwrap(),
+        );
+        let scan_results = [
+            scan_result([10, 0, 0, 1], 22, PortState::Open),
+            scan_result([10, 0, 0, 1], 23, PortState::Closed),
+            scan_result([10, 0, 0, 1], 443, PortState::Open),
+            scan_result([10, 0, 0, 2], 22, PortState::TimeOut),
+        ];
+        let shard_report = |shard: usize, started_at: u64, outcome: TaskOutcome| {
+            let mut collector = ScanReportCollector::new([target.clone()]);
+            for scan_result in scan_results
+                .iter()
+                .skip(shard)
+                .step_by(2)
+            {
+                collector.record(&target, scan_result);
+            }
+            collector.finish(
+                vec![TaskReport {
+                    task_name: format!("scan_{}", target),
+                    target: Some(target.clone()),
+                    outcome,
+                    restarts: 1,
+                }],
+                SystemTime::UNIX_EPOCH + Duration::from_secs(started_at),
+                Duration::from_secs(2),
+            )
+        };
+
+        let merged = ScanReport::merge(vec![
+            shard_report(0, 10, TaskOutcome::Completed),
+            shard_report(1, 11, TaskOutcome::Failed(String::from("boom"))),
+        ])
+        .unwrap();
+        let subnet_report = &merged.subnets[0];
+
+        assert_eq!(merged.subnets.len(), 1);
+        assert_eq!(merged.total_probes(), 4);
+        assert_eq!(merged.duration, Duration::from_secs(3));
+        assert_eq!(
+            subnet_report.hosts[0]
+                .open_ports
+                .iter()
+                .map(|open_port| open_port.port)
+                .collect::<Vec<_>>(),
+            vec![22, 443]
+        );
+        assert_eq!(
+            subnet_report.hosts[0]
+                .state_counts
+                .closed,
+            1
+        );
+        assert_eq!(subnet_report.hosts_down(), 1);
+        assert_eq!(subnet_report.restarts, 2);
+        assert_eq!(
+            merged.errors(),
+            vec![String::from("target 10.0.0.0/30 failed: boom")]
+        );
+        assert_eq!(
+            ScanReport::merge(Vec::new())
+                .err()
+                .unwrap()
+                .to_string(),
+            "No scan results to merge"
+        );
+    }
+
+    #[test]
+    fn should_round_trip_through_a_results_file() {
+        let report = collect_report();
+        let path = std::env::temp_dir().join(format!(
+            "humble_port_scanner_report_{}.json",
+            std::process::id()
+        ));
+
+        report.save(&path).unwrap();
+        let loaded = ScanReport::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, report);
+    }
+
+    #[test]
+    fn should_load_reports_keyed_by_subnet() {
+        let report = collect_report();
+        let mut saved = serde_json::to_value(&report).unwrap();
+        for subnet_report in saved["subnets"]
+            .as_array_mut()
+            .unwrap()
+        {
+            let subnet_report = subnet_report
+                .as_object_mut()
+                .unwrap();
+            let target = subnet_report
+                .remove("target")
+                .unwrap();
+            subnet_report.insert(String::from("subnet"), target);
+        }
+
+        assert_eq!(serde_json::from_value::<ScanReport>(saved).unwrap(), report);
+    }
+
+    #[test]
+    fn should_render_a_human_table() {
+        let rendered = collect_report().to_string();
+
+        assert!(rendered.starts_with("Scan summary: 5 probes in 2.00s (2.5 probes/s)"));
+        assert!(rendered.contains("10.0.0.0/30"));
+        assert!(rendered.contains(
+            "  10.0.0.1           open 2 closed 1 timeout 0  22/tcp (ssh), 443/tcp (web)"
+        ));
+        assert!(!rendered.contains("  10.0.0.2 "));
+        assert!(rendered.contains("error: task stream_progress panicked: boom"));
+    }
+
+    #[test]
+    fn should_report_the_coverage_of_scans_cut_short() {
+        let config = SubnetScanConfiguration::new(
+            "10.0.0.0/30"
+                .parse::<Ipv4Net>()
+                .unwrap(),
+            20,
+            24,
+        );
+        let target = config.target.clone();
+        let cut_short_report = || {
+            let mut collector = ScanReportCollector::new([target.clone()]);
+            collector.plan(&config);
+            for (ip, port) in [
+                ([10, 0, 0, 1], 20),
+                ([10, 0, 0, 1], 21),
+                ([10, 0, 0, 2], 20),
+            ]
+            .into_iter()
+            .chain((22..24).map(|port| ([10, 0, 0, 1], port)))
+            {
+                collector.record(&target, &scan_result(ip, port, PortState::Closed));
+            }
+            collector.finish(
+                vec![TaskReport {
+                    task_name: format!("scan_{}", target),
+                    target: Some(target.clone()),
+                    outcome: TaskOutcome::Completed,
+                    restarts: 0,
+                }],
+                SystemTime::UNIX_EPOCH,
+                Duration::from_secs(1),
+            )
+        };
+
+        let report = cut_short_report();
+        let subnet_report = &report.subnets[0];
+        assert_eq!(
+            (subnet_report.planned_probes, subnet_report.not_scanned),
+            (8, 3)
+        );
+        assert_eq!(subnet_report.coverage(), Some(62.5));
+        assert_eq!(report.completed_hosts().count(), 0);
+
+        assert_eq!(
+            subnet_report.unscanned,
+            vec![UnscannedProbes {
+                hosts: std::iter::once(Ipv4Addr::new(10, 0, 0, 2)..=Ipv4Addr::new(10, 0, 0, 2))
+                    .collect(),
+                ports: std::iter::once(21..=23).collect(),
+            }]
+        );
+
+        let rendered = report.to_string();
+        assert!(rendered.contains("\n3 probes not scanned\n"));
+        assert!(rendered.contains("   62.5%  completed, 3 not scanned"));
+        assert!(rendered.contains("\n  not scanned 10.0.0.2 ports 21-23\n"));
+
+        let merged = ScanReport::merge(vec![cut_short_report(), cut_short_report()]).unwrap();
+        assert_eq!(merged.not_scanned(), 6);
+        assert_eq!(merged.subnets[0].coverage(), Some(62.5));
+
+        // reports saved before the plan was recorded have no coverage.
+        let mut unplanned = collect_report();
+        unplanned.subnets[0].planned_probes = 0;
+        unplanned.subnets[0].not_scanned = 0;
+        assert_eq!(unplanned.subnets[0].coverage(), None);
+    }
+
+    #[test]
+    fn should_list_the_hosts_and_ports_never_probed() {
+        let config = SubnetScanConfiguration::new(
+            "10.0.0.0/29"
+                .parse::<Ipv4Net>()
+                .unwrap(),
+            20,
+            24,
+        );
+        let target = config.target.clone();
+        let unscanned_of = |config: &SubnetScanConfiguration| {
+            let mut collector = ScanReportCollector::new([target.clone()]);
+            collector.plan(config);
+            for port in 20..24 {
+                collector.record(
+                    &target,
+                    &scan_result([10, 0, 0, 1], port, PortState::Closed),
+                );
+            }
+            collector.record(&target, &scan_result([10, 0, 0, 2], 20, PortState::Open));
+            collector.record(&target, &scan_result([10, 0, 0, 3], 20, PortState::TimeOut));
+            collector
+                .finish(Vec::new(), SystemTime::UNIX_EPOCH, Duration::from_secs(1))
+                .subnets
+                .remove(0)
+                .unscanned
+        };
+        let hosts = |first: u8, last: u8| {
+            std::iter::once(Ipv4Addr::new(10, 0, 0, first)..=Ipv4Addr::new(10, 0, 0, last))
+                .collect()
+        };
+
+        assert_eq!(
+            unscanned_of(&config),
+            vec![
+                UnscannedProbes {
+                    hosts: hosts(2, 3),
+                    ports: std::iter::once(21..=23).collect(),
+                },
+                UnscannedProbes {
+                    hosts: hosts(4, 6),
+                    ports: std::iter::once(20..=23).collect(),
+                },
+            ]
+        );
+        // a shard probes an unknown share of each host.
+        assert_eq!(
+            unscanned_of(&config.with_shard("1/2".parse().unwrap())),
+            Vec::new()
+        );
+    }
+}
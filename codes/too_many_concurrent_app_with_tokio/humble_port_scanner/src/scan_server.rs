@@ -0,0 +1,641 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::get,
+    Json, Router,
+};
+use futures_core::Stream;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    net::TcpListener,
+    runtime::Runtime,
+    sync::{mpsc, watch},
+    task::AbortHandle,
+};
+
+use crate::{
+    app::SubnetScannerApp,
+    arg_helpers,
+    connector::{Connector, NetworkConnector},
+    guardrails::Guardrails,
+    models::{HostAddressMode, IpPortScanResult, ScanTarget, SubnetScanConfiguration},
+    resolve_helpers::HostResolver,
+    scan_report::ScanReport,
+    services::ServiceRegistry,
+};
+
+/// A scan submitted to the server, the targets and ports take the same forms
+/// as on the command line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanJobRequest {
+    pub targets: Vec<String>,
+    pub ports: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub exclude_ports: Vec<String>,
+    #[serde(default)]
+    pub host_addresses: HostAddressMode,
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub max_restarts: u32,
+    /// Confirms a scan of more probes than the server's confirmation threshold.
+    #[serde(default)]
+    pub i_know: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Completed,
+    Cancelled,
+}
+
+impl fmt::Display for JobStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobStatus::Running => write!(f, "running"),
+            JobStatus::Completed => write!(f, "completed"),
+            JobStatus::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobSummary {
+    pub id: u64,
+    pub status: JobStatus,
+    pub request: ScanJobRequest,
+    pub submitted_at: SystemTime,
+    /// Scan results reported so far.
+    pub results: usize,
+}
+
+/// A scan result as streamed to the clients of a job.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResultEvent {
+    pub target: ScanTarget,
+    pub result: IpPortScanResult,
+}
+
+#[derive(Debug, thiserror::Error)]
+enum ApiError {
+    #[error("Unknown job {0}")]
+    UnknownJob(u64),
+    #[error("Job {id} is {status}, only running jobs can be cancelled")]
+    NotCancellable { id: u64, status: JobStatus },
+    #[error("Job {id} is {status}, its report is not available")]
+    NoReport { id: u64, status: JobStatus },
+    #[error("Invalid scan job: {0:#}")]
+    InvalidJob(anyhow::Error),
+    #[error("Scan job refused: {0:#}")]
+    RefusedJob(anyhow::Error),
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status_code = match self {
+            ApiError::UnknownJob(_) => StatusCode::NOT_FOUND,
+            ApiError::NotCancellable { .. } | ApiError::NoReport { .. } => StatusCode::CONFLICT,
+            ApiError::InvalidJob(_) => StatusCode::BAD_REQUEST,
+            ApiError::RefusedJob(_) => StatusCode::FORBIDDEN,
+        };
+
+        (
+            status_code,
+            Json(serde_json::json!({ "error": self.to_string() })),
+        )
+            .into_response()
+    }
+}
+
+struct ScanJob {
+    request: ScanJobRequest,
+    submitted_at: SystemTime,
+    status: JobStatus,
+    results: Vec<ResultEvent>,
+    report: Option<ScanReport>,
+    task: Option<AbortHandle>,
+}
+
+impl ScanJob {
+    fn summary(&self, id: u64) -> JobSummary {
+        JobSummary {
+            id,
+            status: self.status,
+            request: self.request.clone(),
+            submitted_at: self.submitted_at,
+            results: self.results.len(),
+        }
+    }
+}
+
+/// Runs the submitted scan jobs on a shared runtime and keeps their results
+/// and reports for the lifetime of the server.
+pub struct ScanServer {
+    runtime: Arc<Runtime>,
+    scan_timeout: Duration,
+    guardrails: Guardrails,
+    services: Arc<ServiceRegistry>,
+    connector: Arc<dyn Connector>,
+    jobs: Mutex<BTreeMap<u64, ScanJob>>,
+    // bumped on every change of any job, wakes up the event streams.
+    updates: watch::Sender<()>,
+}
+
+impl ScanServer {
+    pub fn new(runtime: Arc<Runtime>, scan_timeout: Duration) -> Self {
+        Self {
+            runtime,
+            scan_timeout,
+            guardrails: Guardrails::default(),
+            services: Arc::default(),
+            connector: Arc::new(NetworkConnector),
+            jobs: Mutex::new(BTreeMap::new()),
+            updates: watch::Sender::new(()),
+        }
+    }
+
+    /// Checks every submitted job against `guardrails` rather than the default ones.
+    pub fn with_guardrails(mut self, guardrails: Guardrails) -> Self {
+        self.guardrails = guardrails;
+        self
+    }
+
+    /// Resolves the service names of the jobs with `services` rather than the embedded ones.
+    pub fn with_services(mut self, services: ServiceRegistry) -> Self {
+        self.services = Arc::new(services);
+        self
+    }
+
+    /// Probes the targets of the jobs through `connector` rather than the host's network.
+    pub fn with_connector(mut self, connector: Arc<dyn Connector>) -> Self {
+        self.connector = connector;
+        self
+    }
+
+    fn jobs(&self) -> MutexGuard<'_, BTreeMap<u64, ScanJob>> {
+        self.jobs
+            .lock()
+            .expect("scan jobs lock poisoned")
+    }
+
+    fn job_summary(&self, id: u64) -> Result<JobSummary, ApiError> {
+        self.jobs()
+            .get(&id)
+            .map(|job| job.summary(id))
+            .ok_or(ApiError::UnknownJob(id))
+    }
+
+    async fn submit(self: Arc<Self>, request: ScanJobRequest) -> Result<JobSummary, ApiError> {
+        let job_request = request.clone();
+        let services = self.services.clone();
+        // resolving hostnames blocks.
+        let configs =
+            tokio::task::spawn_blocking(move || prepare_job_configurations(job_request, &services))
+                .await
+                .context("Scan job preparation panicked")
+                .and_then(|configs| configs)
+                .map_err(ApiError::InvalidJob)?;
+        self.guardrails
+            .clone()
+            .with_confirmation(request.i_know)
+            .check(&configs)
+            .map_err(ApiError::RefusedJob)?;
+
+        let (result_tx, result_rx) = mpsc::unbounded_channel();
+        let mut app = SubnetScannerApp::builder()
+            .set_configs(configs)
+            .set_scan_timeout(self.scan_timeout)
+            .set_max_restarts(request.max_restarts)
+            .set_runtime(&self.runtime)
+            .set_show_progress(false)
+            .set_result_observer(result_tx)
+            .set_connector(self.connector.clone())
+            .build()
+            .map_err(ApiError::InvalidJob)?;
+        app.start_subnet_scans();
+
+        let mut jobs = self.jobs();
+        let id = jobs
+            .last_key_value()
+            .map_or(1, |(last_id, _)| last_id + 1);
+        let task = self.runtime.spawn(
+            self.clone()
+                .run_job(id, app, result_rx),
+        );
+        let job = ScanJob {
+            request,
+            submitted_at: SystemTime::now(),
+            status: JobStatus::Running,
+            results: Vec::new(),
+            report: None,
+            task: Some(task.abort_handle()),
+        };
+        let summary = job.summary(id);
+        jobs.insert(id, job);
+        tracing::info!(job = id, "scan job submitted");
+
+        Ok(summary)
+    }
+
+    async fn run_job(
+        self: Arc<Self>,
+        id: u64,
+        app: SubnetScannerApp,
+        mut result_rx: mpsc::UnboundedReceiver<(ScanTarget, IpPortScanResult)>,
+    ) {
+        let record_results = async {
+            while let Some((target, result)) = result_rx.recv().await {
+                if let Some(job) = self.jobs().get_mut(&id) {
+                    job.results
+                        .push(ResultEvent { target, result });
+                }
+                self.updates.send_replace(());
+            }
+        };
+        let (scan_report, ()) = tokio::join!(app.run_async(), record_results);
+
+        // a job cancelled meanwhile stays cancelled.
+        if let Some(job) = self
+            .jobs()
+            .get_mut(&id)
+            .filter(|job| job.status == JobStatus::Running)
+        {
+            job.status = JobStatus::Completed;
+            job.report = Some(scan_report);
+            job.task = None;
+        }
+        tracing::info!(job = id, "scan job completed");
+        self.updates.send_replace(());
+    }
+
+    fn cancel(&self, id: u64) -> Result<JobSummary, ApiError> {
+        let mut jobs = self.jobs();
+        let job = jobs
+            .get_mut(&id)
+            .ok_or(ApiError::UnknownJob(id))?;
+        if job.status != JobStatus::Running {
+            return Err(ApiError::NotCancellable {
+                id,
+                status: job.status,
+            });
+        }
+
+        // the job task owns the scans, aborting it aborts their tasks as well.
+        if let Some(task) = job.task.take() {
+            task.abort();
+        }
+        job.status = JobStatus::Cancelled;
+        let summary = job.summary(id);
+        drop(jobs);
+        tracing::info!(job = id, "scan job cancelled");
+        self.updates.send_replace(());
+
+        Ok(summary)
+    }
+
+    fn report(&self, id: u64) -> Result<ScanReport, ApiError> {
+        let jobs = self.jobs();
+        let job = jobs
+            .get(&id)
+            .ok_or(ApiError::UnknownJob(id))?;
+
+        job.report
+            .clone()
+            .ok_or(ApiError::NoReport {
+                id,
+                status: job.status,
+            })
+    }
+
+    // every result of the job so far, then the new ones as they come, and a
+    // final `done` event carrying the job summary once the job is over.
+    fn events(self: Arc<Self>, id: u64) -> impl Stream<Item = Result<Event, axum::Error>> {
+        async_stream::stream! {
+            let mut updates = self.updates.subscribe();
+            let mut sent = 0;
+            loop {
+                let (events, finished) = match self.jobs().get(&id) {
+                    Some(job) => (
+                        job.results[sent..].to_vec(),
+                        (job.status != JobStatus::Running).then(|| job.summary(id)),
+                    ),
+                    None => break,
+                };
+
+                sent += events.len();
+                for event in events {
+                    yield Event::default()
+                        .event("result")
+                        .json_data(event);
+                }
+                if let Some(summary) = finished {
+                    yield Event::default()
+                        .event("done")
+                        .json_data(summary);
+                    break;
+                }
+                if updates.changed().await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn prepare_job_configurations(
+    request: ScanJobRequest,
+    services: &ServiceRegistry,
+) -> anyhow::Result<Vec<SubnetScanConfiguration>> {
+    let exclusions = Arc::new(arg_helpers::prepare_exclusions(
+        request.exclude,
+        None,
+        request.exclude_ports,
+        services,
+    )?);
+
+    Ok(arg_helpers::prepare_scan_configurations(
+        request.targets,
+        &[],
+        request.ports,
+        request.host_addresses,
+        services,
+        &HostResolver::System,
+    )?
+    .into_iter()
+    .map(|config| {
+        config
+            .with_exclusions(exclusions.clone())
+            .with_order(request.seed.into())
+    })
+    .collect())
+}
+
+async fn submit_job(
+    State(server): State<Arc<ScanServer>>,
+    Json(request): Json<ScanJobRequest>,
+) -> Result<(StatusCode, Json<JobSummary>), ApiError> {
+    let summary = server.submit(request).await?;
+    Ok((StatusCode::CREATED, Json(summary)))
+}
+
+async fn list_jobs(State(server): State<Arc<ScanServer>>) -> Json<Vec<JobSummary>> {
+    Json(
+        server
+            .jobs()
+            .iter()
+            .map(|(id, job)| job.summary(*id))
+            .collect(),
+    )
+}
+
+async fn get_job(
+    State(server): State<Arc<ScanServer>>,
+    Path(id): Path<u64>,
+) -> Result<Json<JobSummary>, ApiError> {
+    server.job_summary(id).map(Json)
+}
+
+async fn cancel_job(
+    State(server): State<Arc<ScanServer>>,
+    Path(id): Path<u64>,
+) -> Result<Json<JobSummary>, ApiError> {
+    server.cancel(id).map(Json)
+}
+
+async fn get_report(
+    State(server): State<Arc<ScanServer>>,
+    Path(id): Path<u64>,
+) -> Result<Json<ScanReport>, ApiError> {
+    server.report(id).map(Json)
+}
+
+async fn stream_events(
+    State(server): State<Arc<ScanServer>>,
+    Path(id): Path<u64>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, ApiError> {
+    server.job_summary(id)?;
+    Ok(Sse::new(server.events(id)).keep_alive(KeepAlive::default()))
+}
+
+/// `POST /jobs` submits a scan, `GET /jobs` lists them, `GET /jobs/{id}` tells
+/// how one is doing, `DELETE /jobs/{id}` cancels it, `GET /jobs/{id}/events`
+/// streams its results as server-sent events and `GET /jobs/{id}/report`
+/// returns its report once it completed.
+pub fn scan_router(server: Arc<ScanServer>) -> Router {
+    Router::new()
+        .route("/jobs", get(list_jobs).post(submit_job))
+        .route("/jobs/:id", get(get_job).delete(cancel_job))
+        .route("/jobs/:id/events", get(stream_events))
+        .route("/jobs/:id/report", get(get_report))
+        .with_state(server)
+}
+
+pub async fn serve_scans(listener: TcpListener, server: Arc<ScanServer>) -> anyhow::Result<()> {
+    axum::serve(listener, scan_router(server))
+        .await
+        .context("Scan server stopped unexpectedly")
+}
+
+#[cfg(test)]
+mod scan_server_tests {
+    use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use crate::{
+        connector::{Connector, NetworkConnector},
+        models::RuntimeConfiguration,
+        scan_report::ScanReport,
+        scan_server::{serve_scans, JobStatus, JobSummary, ScanServer},
+        simulated_network::{SimulatedLink, SimulatedNetwork},
+        tokio_helpers,
+    };
+
+    // a one shot HTTP/1.1 client, returns the status code and the raw body.
+    async fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr)
+            .await
+            .unwrap();
+        let request = format!(
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        );
+        stream
+            .write_all(request.as_bytes())
+            .await
+            .unwrap();
+
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .await
+            .unwrap();
+        let (head, body) = response
+            .split_once("\r\n\r\n")
+            .unwrap();
+        let status_code = head[9..12].parse().unwrap();
+
+        (status_code, body.to_string())
+    }
+
+    fn with_server(test: impl FnOnce(SocketAddr) -> futures::future::BoxFuture<'static, ()>) {
+        with_server_on(Arc::new(NetworkConnector), test)
+    }
+
+    fn with_server_on(
+        connector: Arc<dyn Connector>,
+        test: impl FnOnce(SocketAddr) -> futures::future::BoxFuture<'static, ()>,
+    ) {
+        let runtime =
+            Arc::new(tokio_helpers::setup_tokio_runtime(&RuntimeConfiguration::default()).unwrap());
+        let server = Arc::new(
+            ScanServer::new(runtime.clone(), Duration::from_millis(500)).with_connector(connector),
+        );
+
+        runtime.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0")
+                .await
+                .unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(serve_scans(listener, server));
+
+            test(addr).await;
+        });
+    }
+
+    #[test]
+    fn should_run_a_job_and_stream_its_results() {
+        with_server(|addr| {
+            Box::pin(async move {
+                let (status_code, body) = request(
+                    addr,
+                    "POST",
+                    "/jobs",
+                    r#"{"targets": ["127.0.0.0/30"], "ports": ["41000:41004"]}"#,
+                )
+                .await;
+                assert_eq!(status_code, 201);
+                let job: JobSummary = serde_json::from_str(&body).unwrap();
+                assert_eq!(job.id, 1);
+
+                let (status_code, events) = request(addr, "GET", "/jobs/1/events", "").await;
+                assert_eq!(status_code, 200);
+                assert_eq!(
+                    events
+                        .matches("event: result")
+                        .count(),
+                    8
+                );
+                assert!(events.contains("event: done"));
+                assert!(events.contains(r#""status":"completed""#));
+
+                let (status_code, body) = request(addr, "GET", "/jobs/1/report", "").await;
+                assert_eq!(status_code, 200);
+                let report: ScanReport = serde_json::from_str(&body).unwrap();
+                assert_eq!(report.total_probes(), 8);
+
+                let (_, body) = request(addr, "GET", "/jobs", "").await;
+                let jobs: Vec<JobSummary> = serde_json::from_str(&body).unwrap();
+                assert_eq!(jobs.len(), 1);
+                assert_eq!(jobs[0].status, JobStatus::Completed);
+                assert_eq!(jobs[0].results, 8);
+            })
+        });
+    }
+
+    #[test]
+    fn should_cancel_a_running_job() {
+        let network = Arc::new(SimulatedNetwork::new(SimulatedLink::refusing(
+            Duration::from_millis(1),
+        )));
+        let probed_network = network.clone();
+        with_server_on(network, |addr| {
+            Box::pin(async move {
+                request(
+                    addr,
+                    "POST",
+                    "/jobs",
+                    r#"{"targets": ["127.0.0.0/16"], "ports": ["1:60000"], "i_know": true}"#,
+                )
+                .await;
+                while probed_network.connection_attempts() == 0 {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+
+                let (status_code, body) = request(addr, "DELETE", "/jobs/1", "").await;
+                assert_eq!(status_code, 200);
+                let job: JobSummary = serde_json::from_str(&body).unwrap();
+                assert_eq!(job.status, JobStatus::Cancelled);
+
+                // the scans stopped with the job, no probe goes out anymore.
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                let attempts = probed_network.connection_attempts();
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                assert_eq!(probed_network.connection_attempts(), attempts);
+
+                let (status_code, _) = request(addr, "DELETE", "/jobs/1", "").await;
+                assert_eq!(status_code, 409);
+                let (status_code, _) = request(addr, "GET", "/jobs/1/report", "").await;
+                assert_eq!(status_code, 409);
+                let (_, events) = request(addr, "GET", "/jobs/1/events", "").await;
+                assert!(events.contains(r#""status":"cancelled""#));
+            })
+        });
+    }
+
+    #[test]
+    fn should_reject_invalid_jobs_and_unknown_ids() {
+        with_server(|addr| {
+            Box::pin(async move {
+                let (status_code, body) = request(
+                    addr,
+                    "POST",
+                    "/jobs",
+                    r#"{"targets": ["127.0.0.0/30"], "ports": ["41000-41004"]}"#,
+                )
+                .await;
+                assert_eq!(status_code, 400);
+                assert!(body.contains("Invalid scan job"));
+
+                let (status_code, body) = request(
+                    addr,
+                    "POST",
+                    "/jobs",
+                    r#"{"targets": ["8.8.8.8"], "ports": ["53:54"]}"#,
+                )
+                .await;
+                assert_eq!(status_code, 403);
+                assert_eq!(
+                    body,
+                    r#"{"error":"Scan job refused: Target 8.8.8.8 reaches public address 8.8.8.8, allowlist it to scan it"}"#
+                );
+
+                let (status_code, body) = request(addr, "GET", "/jobs/7", "").await;
+                assert_eq!(status_code, 404);
+                assert_eq!(body, r#"{"error":"Unknown job 7"}"#);
+            })
+        });
+    }
+}
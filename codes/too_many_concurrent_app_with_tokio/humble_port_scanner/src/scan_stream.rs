@@ -1,15 +1,17 @@
 use std::pin::Pin;
 
 use futures_core::Stream;
-use ipnet::Ipv4Net;
-use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::mpsc::Receiver;
 use tokio_stream::{StreamMap, StreamNotifyClose};
 
-use crate::models::IpPortScanResult;
+use crate::models::{IpPortScanResult, ScanTarget};
 
 type IpPortScanResultStreamMap = Pin<
     Box<
-        StreamMap<Ipv4Net, StreamNotifyClose<Pin<Box<dyn Stream<Item = IpPortScanResult> + Send>>>>,
+        StreamMap<
+            ScanTarget,
+            StreamNotifyClose<Pin<Box<dyn Stream<Item = IpPortScanResult> + Send>>>,
+        >,
     >,
 >;
 
@@ -17,6 +19,12 @@ pub struct ScanResultStreamer {
     stream_map: IpPortScanResultStreamMap,
 }
 
+impl Default for ScanResultStreamer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ScanResultStreamer {
     pub fn new() -> Self {
         let stream_map = StreamMap::new();
@@ -26,14 +34,14 @@ impl ScanResultStreamer {
         }
     }
 
-    pub fn add_stream_from_rx(&mut self, key: Ipv4Net, rx: UnboundedReceiver<IpPortScanResult>) {
+    pub fn add_stream_from_rx(&mut self, key: ScanTarget, rx: Receiver<IpPortScanResult>) {
         let rx_stream = StreamNotifyClose::new(ScanResultStreamer::make_stream(rx));
         self.stream_map
             .insert(key, rx_stream);
     }
 
     fn make_stream(
-        mut rx: UnboundedReceiver<IpPortScanResult>,
+        mut rx: Receiver<IpPortScanResult>,
     ) -> Pin<Box<dyn Stream<Item = IpPortScanResult> + Send>> {
         Box::pin(async_stream::stream! {
                 while let Some(scan_result) = rx.recv().await {
@@ -44,7 +52,7 @@ impl ScanResultStreamer {
 }
 
 impl Stream for ScanResultStreamer {
-    type Item = (Ipv4Net, Option<IpPortScanResult>);
+    type Item = (ScanTarget, Option<IpPortScanResult>);
 
     fn poll_next(
         mut self: Pin<&mut Self>,
@@ -0,0 +1,338 @@
+use std::{collections::HashMap, fs, path::Path, sync::Arc};
+
+use anyhow::Context;
+
+use crate::{
+    errors::AppErrors,
+    target_helpers::{self, RangeSet},
+};
+
+/// Services file of the system, added to the embedded services when present.
+pub const SYSTEM_SERVICES_PATH: &str = "/etc/services";
+
+// excerpt of the IANA service name and port number registry, the TCP services
+// commonly found on a network.
+const IANA_SERVICES: [(u16, &str); 85] = [
+    (7, "echo"),
+    (9, "discard"),
+    (13, "daytime"),
+    (19, "chargen"),
+    (20, "ftp-data"),
+    (21, "ftp"),
+    (22, "ssh"),
+    (23, "telnet"),
+    (25, "smtp"),
+    (37, "time"),
+    (49, "tacacs"),
+    (53, "domain"),
+    (70, "gopher"),
+    (79, "finger"),
+    (80, "http"),
+    (88, "kerberos"),
+    (109, "pop2"),
+    (110, "pop3"),
+    (111, "sunrpc"),
+    (113, "auth"),
+    (119, "nntp"),
+    (135, "msrpc"),
+    (139, "netbios-ssn"),
+    (143, "imap"),
+    (161, "snmp"),
+    (179, "bgp"),
+    (194, "irc"),
+    (389, "ldap"),
+    (427, "svrloc"),
+    (443, "https"),
+    (445, "microsoft-ds"),
+    (464, "kpasswd"),
+    (465, "submissions"),
+    (513, "login"),
+    (514, "shell"),
+    (515, "printer"),
+    (543, "klogin"),
+    (544, "kshell"),
+    (548, "afpovertcp"),
+    (554, "rtsp"),
+    (563, "nntps"),
+    (587, "submission"),
+    (631, "ipp"),
+    (636, "ldaps"),
+    (853, "domain-s"),
+    (873, "rsync"),
+    (989, "ftps-data"),
+    (990, "ftps"),
+    (992, "telnets"),
+    (993, "imaps"),
+    (995, "pop3s"),
+    (1080, "socks"),
+    (1194, "openvpn"),
+    (1433, "ms-sql-s"),
+    (1434, "ms-sql-m"),
+    (1521, "oracle"),
+    (1723, "pptp"),
+    (1812, "radius"),
+    (1883, "mqtt"),
+    (2049, "nfs"),
+    (2375, "docker"),
+    (2376, "docker-s"),
+    (2379, "etcd-client"),
+    (2380, "etcd-server"),
+    (3260, "iscsi-target"),
+    (3306, "mysql"),
+    (3389, "ms-wbt-server"),
+    (3478, "stun"),
+    (4369, "epmd"),
+    (5060, "sip"),
+    (5061, "sips"),
+    (5222, "xmpp-client"),
+    (5269, "xmpp-server"),
+    (5432, "postgresql"),
+    (5671, "amqps"),
+    (5672, "amqp"),
+    (5900, "vnc"),
+    (5984, "couchdb"),
+    (6379, "redis"),
+    (6697, "ircs-u"),
+    (8080, "http-alt"),
+    (8883, "secure-mqtt"),
+    (9418, "git"),
+    (11211, "memcache"),
+    (27017, "mongodb"),
+];
+
+/// Name of `port` in the embedded registry.
+pub fn service_name(port: u16) -> Option<&'static str> {
+    IANA_SERVICES
+        .binary_search_by_key(&port, |(service_port, _)| *service_port)
+        .ok()
+        .map(|index| IANA_SERVICES[index].1)
+}
+
+/// TCP service names by port, and ports by name or alias. Services added later
+/// take over the ports and names of the earlier ones.
+#[derive(Debug, Clone)]
+pub struct ServiceRegistry {
+    names: HashMap<u16, Arc<str>>,
+    ports: HashMap<String, u16>,
+}
+
+impl Default for ServiceRegistry {
+    fn default() -> Self {
+        Self::embedded()
+    }
+}
+
+impl ServiceRegistry {
+    /// The services of the embedded IANA registry excerpt.
+    pub fn embedded() -> Self {
+        let mut registry = Self {
+            names: HashMap::new(),
+            ports: HashMap::new(),
+        };
+        for (port, name) in IANA_SERVICES {
+            registry.insert(port, &[name]);
+        }
+
+        registry
+    }
+
+    /// Adds the TCP services of a file in the /etc/services format, one
+    /// `<name> <port>/<protocol> [aliases]` entry per line.
+    pub fn with_services_file(mut self, path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)
+            .context(format!("Unable to read services from {}", path.display()))?;
+        if let Some(entry) = self
+            .add_services(&content)
+            .into_iter()
+            .next()
+        {
+            return Err(AppErrors::InvalidServiceEntryError { entry })
+                .context(format!("Invalid services file {}", path.display()));
+        }
+
+        Ok(self)
+    }
+
+    /// Adds the TCP services of a file of the system such as /etc/services,
+    /// its malformed entries are skipped with a warning.
+    pub fn with_system_services_file(mut self, path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)
+            .context(format!("Unable to read services from {}", path.display()))?;
+        for entry in self.add_services(&content) {
+            tracing::warn!(path = %path.display(), entry, "skipping invalid service entry");
+        }
+
+        Ok(self)
+    }
+
+    /// Name of the service on `port`.
+    pub fn name(&self, port: u16) -> Option<Arc<str>> {
+        self.names.get(&port).cloned()
+    }
+
+    /// Port of the service called `name` or aliased to it, ignoring case.
+    pub fn port(&self, name: &str) -> Option<u16> {
+        self.ports
+            .get(&name.to_ascii_lowercase())
+            .copied()
+    }
+
+    /// Names of the services on `ports`, by port.
+    pub fn names_of(&self, ports: &RangeSet<u16>) -> HashMap<u16, Arc<str>> {
+        self.names
+            .iter()
+            .filter(|(port, _)| ports.contains(**port))
+            .map(|(port, name)| (*port, name.clone()))
+            .collect()
+    }
+
+    // adds the valid entries of `content` and returns the invalid ones.
+    fn add_services(&mut self, content: &str) -> Vec<String> {
+        let mut invalid_entries = Vec::new();
+        for entry in target_helpers::target_lines(content) {
+            let mut fields = entry.split_whitespace();
+            let name = fields.next();
+            let port_and_protocol = fields
+                .next()
+                .and_then(|port_and_protocol| port_and_protocol.split_once('/'));
+            let (Some(name), Some((port, protocol))) = (name, port_and_protocol) else {
+                invalid_entries.push(entry.to_string());
+                continue;
+            };
+            let Ok(port) = port.parse::<u16>() else {
+                invalid_entries.push(entry.to_string());
+                continue;
+            };
+
+            if protocol.eq_ignore_ascii_case("tcp") {
+                let names: Vec<&str> = std::iter::once(name)
+                    .chain(fields)
+                    .collect();
+                self.insert(port, &names);
+            }
+        }
+
+        invalid_entries
+    }
+
+    // the first name is the one the port is shown with, the others are aliases.
+    fn insert(&mut self, port: u16, names: &[&str]) {
+        self.names
+            .insert(port, Arc::from(names[0]));
+        for name in names {
+            self.ports
+                .insert(name.to_ascii_lowercase(), port);
+        }
+    }
+}
+
+#[cfg(test)]
+mod service_tests {
+    use std::sync::Arc;
+
+    use crate::services::{service_name, ServiceRegistry, IANA_SERVICES};
+
+    #[test]
+    fn should_name_well_known_ports() {
+        assert_eq!(service_name(22), Some("ssh"));
+        assert_eq!(service_name(5432), Some("postgresql"));
+        assert_eq!(service_name(41000), None);
+    }
+
+    #[test]
+    fn iana_services_should_be_sorted_by_port() {
+        assert!(IANA_SERVICES
+            .windows(2)
+            .all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn should_look_up_embedded_services_both_ways() {
+        let registry = ServiceRegistry::embedded();
+
+        assert_eq!(registry.name(443), Some(Arc::from("https")));
+        assert_eq!(registry.name(41000), None);
+        assert_eq!(registry.port("postgresql"), Some(5432));
+        assert_eq!(registry.port("SSH"), Some(22));
+        assert_eq!(registry.port("gibberish"), None);
+    }
+
+    #[test]
+    fn should_override_embedded_services_with_later_files() {
+        let system_services = "# Network services, Internet style\n\
+            ssh\t\t22/tcp\t\t\t# SSH Remote Login Protocol\n\
+            domain\t\t53/udp\n\
+            postgresql\t5432/tcp\tpostgres\n";
+        let user_services = "metrics 9898/tcp prometheus\nwww 80/tcp\n";
+        let mut registry = ServiceRegistry::embedded();
+        assert!(registry
+            .add_services(system_services)
+            .is_empty());
+        assert!(registry
+            .add_services(user_services)
+            .is_empty());
+
+        assert_eq!(registry.port("postgres"), Some(5432));
+        assert_eq!(registry.name(9898), Some(Arc::from("metrics")));
+        assert_eq!(registry.port("prometheus"), Some(9898));
+        assert_eq!(registry.name(80), Some(Arc::from("www")));
+        // the embedded name stays usable for the port.
+        assert_eq!(registry.port("http"), Some(80));
+        assert_eq!(registry.name(53), Some(Arc::from("domain")));
+    }
+
+    #[test]
+    fn should_read_services_files() {
+        let services_file = std::env::temp_dir().join(format!(
+            "humble_port_scanner_services_{}",
+            std::process::id()
+        ));
+        std::fs::write(&services_file, "grafana 3000/tcp\n").unwrap();
+        let registry = ServiceRegistry::embedded()
+            .with_services_file(&services_file)
+            .unwrap();
+        std::fs::write(&services_file, "grafana 3000/tcp\nbroken-entry\n").unwrap();
+        let error = ServiceRegistry::embedded()
+            .with_services_file(&services_file)
+            .err()
+            .unwrap();
+        std::fs::remove_file(&services_file).unwrap();
+
+        assert_eq!(registry.port("grafana"), Some(3000));
+        assert_eq!(
+            format!("{:#}", error),
+            format!(
+                "Invalid services file {}: Invalid service entry `broken-entry`, expected <name> <port>/<protocol> [aliases]",
+                services_file.display()
+            )
+        );
+        assert!(ServiceRegistry::embedded()
+            .with_services_file(&services_file)
+            .is_err());
+    }
+
+    #[test]
+    fn should_skip_malformed_entries_of_system_services_files() {
+        let services_file = std::env::temp_dir().join(format!(
+            "humble_port_scanner_system_services_{}",
+            std::process::id()
+        ));
+        std::fs::write(
+            &services_file,
+            "grafana 3000/tcp
+broken-entry
+loki 3100/tcp
+bad 99999/tcp
+",
+        )
+        .unwrap();
+        let registry = ServiceRegistry::embedded()
+            .with_system_services_file(&services_file)
+            .unwrap();
+        std::fs::remove_file(&services_file).unwrap();
+
+        assert_eq!(registry.port("grafana"), Some(3000));
+        assert_eq!(registry.port("loki"), Some(3100));
+        assert_eq!(registry.port("bad"), None);
+    }
+}
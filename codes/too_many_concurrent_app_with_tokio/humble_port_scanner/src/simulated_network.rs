@@ -0,0 +1,293 @@
+use std::{
+    collections::HashMap,
+    net::Ipv4Addr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use futures::future::BoxFuture;
+use tokio::time;
+
+use crate::{
+    connector::Connector,
+    models::{PortState, ProbeRoute, SourceBinding},
+    permutation_helpers,
+};
+
+/// What a simulated port does with a connection attempt.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SimulatedReply {
+    /// Completes the handshake, the port is open.
+    Accept,
+    /// Resets the connection, the port is closed.
+    Refuse,
+    /// Never answers, the probe times out.
+    Drop,
+}
+
+/// How a simulated port answers and how long the answer takes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SimulatedLink {
+    pub reply: SimulatedReply,
+    pub latency: Duration,
+    /// Up to this much is added to the latency, a different amount for every host and port.
+    pub jitter: Duration,
+}
+
+impl SimulatedLink {
+    pub fn accepting(latency: Duration) -> Self {
+        Self {
+            reply: SimulatedReply::Accept,
+            latency,
+            jitter: Duration::ZERO,
+        }
+    }
+
+    pub fn refusing(latency: Duration) -> Self {
+        Self {
+            reply: SimulatedReply::Refuse,
+            latency,
+            jitter: Duration::ZERO,
+        }
+    }
+
+    pub fn dropped() -> Self {
+        Self {
+            reply: SimulatedReply::Drop,
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+        }
+    }
+
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+}
+
+/// A [`Connector`] answering from rules kept in memory rather than from the
+/// network, routes and source bindings are ignored. The latencies are tokio
+/// sleeps, so under paused time a whole scan runs without waiting.
+///
+/// A port follows the most specific rule: the one of its host and port, then
+/// the one of its host, then the one of its port on every host, then the default.
+#[derive(Debug)]
+pub struct SimulatedNetwork {
+    default_link: SimulatedLink,
+    hosts: HashMap<Ipv4Addr, SimulatedLink>,
+    ports: HashMap<u16, SimulatedLink>,
+    host_ports: HashMap<(Ipv4Addr, u16), SimulatedLink>,
+    seed: u64,
+    connection_attempts: AtomicU64,
+}
+
+impl Default for SimulatedNetwork {
+    /// Every port refuses at once, like hosts with nothing listening.
+    fn default() -> Self {
+        Self::new(SimulatedLink::refusing(Duration::ZERO))
+    }
+}
+
+impl SimulatedNetwork {
+    pub fn new(default_link: SimulatedLink) -> Self {
+        Self {
+            default_link,
+            hosts: HashMap::new(),
+            ports: HashMap::new(),
+            host_ports: HashMap::new(),
+            seed: 0,
+            connection_attempts: AtomicU64::new(0),
+        }
+    }
+
+    pub fn with_host(mut self, ip: Ipv4Addr, link: SimulatedLink) -> Self {
+        self.hosts.insert(ip, link);
+        self
+    }
+
+    pub fn with_port(mut self, port: u16, link: SimulatedLink) -> Self {
+        self.ports.insert(port, link);
+        self
+    }
+
+    pub fn with_host_port(mut self, ip: Ipv4Addr, port: u16, link: SimulatedLink) -> Self {
+        self.host_ports
+            .insert((ip, port), link);
+        self
+    }
+
+    /// Picks the jitter of every host and port, the same seed gives the same delays.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn link(&self, ip: Ipv4Addr, port: u16) -> SimulatedLink {
+        self.host_ports
+            .get(&(ip, port))
+            .or_else(|| self.hosts.get(&ip))
+            .or_else(|| self.ports.get(&port))
+            .copied()
+            .unwrap_or(self.default_link)
+    }
+
+    /// Time `port` on `ip` takes to answer, its latency plus its share of the jitter.
+    pub fn delay(&self, ip: Ipv4Addr, port: u16) -> Duration {
+        let link = self.link(ip, port);
+        let jitter_nanos = link.jitter.as_nanos() as u64;
+        if jitter_nanos == 0 {
+            return link.latency;
+        }
+
+        let pair = (u64::from(u32::from(ip)) << 16) | u64::from(port);
+        let draw = permutation_helpers::mix(self.seed ^ permutation_helpers::mix(pair));
+        link.latency + Duration::from_nanos(draw % (jitter_nanos + 1))
+    }
+
+    /// Connections attempted so far, one per probe.
+    pub fn connection_attempts(&self) -> u64 {
+        self.connection_attempts
+            .load(Ordering::Relaxed)
+    }
+}
+
+impl Connector for SimulatedNetwork {
+    fn connect<'a>(
+        &'a self,
+        _route: ProbeRoute,
+        _source: &'a SourceBinding,
+        ip: Ipv4Addr,
+        port: u16,
+    ) -> BoxFuture<'a, anyhow::Result<PortState>> {
+        self.connection_attempts
+            .fetch_add(1, Ordering::Relaxed);
+        let reply = self.link(ip, port).reply;
+        let delay = self.delay(ip, port);
+
+        Box::pin(async move {
+            // an instant answer stays off the timer, which would round it up to a millisecond.
+            if !delay.is_zero() && reply != SimulatedReply::Drop {
+                time::sleep(delay).await;
+            }
+            match reply {
+                SimulatedReply::Accept => Ok(PortState::Open),
+                SimulatedReply::Refuse => Ok(PortState::Closed),
+                SimulatedReply::Drop => std::future::pending().await,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod simulated_network_tests {
+    use std::{net::Ipv4Addr, time::Duration};
+
+    use futures::FutureExt;
+    use tokio::time::Instant;
+
+    use crate::{
+        connector::Connector,
+        models::{PortState, ProbeRoute, SourceBinding},
+        simulated_network::{SimulatedLink, SimulatedNetwork, SimulatedReply},
+    };
+
+    const WEB: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+    const DOWN: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+
+    fn network() -> SimulatedNetwork {
+        SimulatedNetwork::default()
+            .with_port(22, SimulatedLink::accepting(Duration::from_millis(5)))
+            .with_port(
+                443,
+                SimulatedLink::accepting(Duration::from_millis(40))
+                    .with_jitter(Duration::from_millis(20)),
+            )
+            .with_host(DOWN, SimulatedLink::dropped())
+            .with_host_port(WEB, 80, SimulatedLink::refusing(Duration::from_millis(1)))
+    }
+
+    #[test]
+    fn should_follow_the_most_specific_rule() {
+        let network = network();
+
+        assert_eq!(network.link(WEB, 22).reply, SimulatedReply::Accept);
+        assert_eq!(network.link(WEB, 80).latency, Duration::from_millis(1));
+        assert_eq!(network.link(WEB, 8080).reply, SimulatedReply::Refuse);
+        assert_eq!(network.link(DOWN, 22).reply, SimulatedReply::Drop);
+        assert_eq!(network.link(DOWN, 80).reply, SimulatedReply::Drop);
+    }
+
+    #[test]
+    fn should_draw_the_same_jitter_for_the_same_seed() {
+        let delays = |seed| {
+            let network = network().with_seed(seed);
+            (0..64)
+                .map(|last_octet| network.delay(Ipv4Addr::new(10, 0, 1, last_octet), 443))
+                .collect::<Vec<_>>()
+        };
+
+        let delays_of_seed_1 = delays(1);
+        assert_eq!(delays(1), delays_of_seed_1);
+        assert_ne!(delays(2), delays_of_seed_1);
+        assert!(delays_of_seed_1
+            .iter()
+            .all(|delay| (Duration::from_millis(40)..=Duration::from_millis(60)).contains(delay)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_answer_after_the_latency_of_the_port() {
+        let network = network();
+        let source = SourceBinding::default();
+        let probe_start = Instant::now();
+
+        let state = network
+            .connect(ProbeRoute::Direct, &source, WEB, 22)
+            .await
+            .unwrap();
+
+        assert_eq!(state, PortState::Open);
+        assert_eq!(probe_start.elapsed(), Duration::from_millis(5));
+        assert_eq!(
+            network
+                .connect(ProbeRoute::Direct, &source, WEB, 80)
+                .await
+                .unwrap(),
+            PortState::Closed
+        );
+        assert!(tokio::time::timeout(
+            Duration::from_secs(3),
+            network.connect(ProbeRoute::Direct, &source, DOWN, 22)
+        )
+        .await
+        .is_err());
+        assert_eq!(network.connection_attempts(), 3);
+    }
+
+    #[tokio::test]
+    async fn should_answer_instant_links_on_the_first_poll() {
+        let network = SimulatedNetwork::default()
+            .with_port(22, SimulatedLink::accepting(Duration::ZERO))
+            .with_port(8080, SimulatedLink::dropped());
+        let source = SourceBinding::default();
+
+        // a zero sleep would still wait for the next tick of the timer.
+        assert_eq!(
+            network
+                .connect(ProbeRoute::Direct, &source, WEB, 22)
+                .now_or_never()
+                .map(Result::unwrap),
+            Some(PortState::Open)
+        );
+        assert_eq!(
+            network
+                .connect(ProbeRoute::Direct, &source, WEB, 80)
+                .now_or_never()
+                .map(Result::unwrap),
+            Some(PortState::Closed)
+        );
+        assert!(network
+            .connect(ProbeRoute::Direct, &source, WEB, 8080)
+            .now_or_never()
+            .is_none());
+    }
+}
@@ -0,0 +1,636 @@
+use std::{fs, marker::PhantomData, net::Ipv4Addr, ops::RangeInclusive, path::Path};
+
+use anyhow::{bail, Context};
+use ipnet::Ipv4Net;
+use serde::{Deserialize, Serialize};
+
+use crate::{errors::AppErrors, models::ScanTarget, subnet_helpers};
+
+/// Values a [`RangeSet`] can hold, mapped onto `u32` for range arithmetic.
+pub trait RangeValue: Copy {
+    /// Largest `u32` standing for a value.
+    const MAX: u32;
+
+    fn to_u32(self) -> u32;
+    fn from_u32(value: u32) -> Self;
+}
+
+impl RangeValue for Ipv4Addr {
+    const MAX: u32 = u32::MAX;
+
+    fn to_u32(self) -> u32 {
+        u32::from(self)
+    }
+
+    fn from_u32(value: u32) -> Self {
+        Ipv4Addr::from(value)
+    }
+}
+
+impl RangeValue for u16 {
+    const MAX: u32 = u16::MAX as u32;
+
+    fn to_u32(self) -> u32 {
+        self as u32
+    }
+
+    fn from_u32(value: u32) -> Self {
+        value as u16
+    }
+}
+
+/// A set of addresses or ports kept as sorted, disjoint and non-adjacent
+/// inclusive ranges, so a /8 costs as much as a single address.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "SavedRangeSet", bound(deserialize = "T: RangeValue"))]
+pub struct RangeSet<T> {
+    ranges: Vec<(u32, u32)>,
+    #[serde(skip)]
+    value_type: PhantomData<T>,
+}
+
+// the ranges as they come from a peer or a file, checked before they are used.
+#[derive(Deserialize)]
+struct SavedRangeSet {
+    ranges: Vec<(u32, u32)>,
+}
+
+impl<T: RangeValue> TryFrom<SavedRangeSet> for RangeSet<T> {
+    type Error = AppErrors;
+
+    fn try_from(saved: SavedRangeSet) -> Result<Self, Self::Error> {
+        let invalid = |reason: String| AppErrors::InvalidRangeSetError { reason };
+        for &(start, end) in &saved.ranges {
+            if start > end {
+                return Err(invalid(format!("range {}-{} is reversed", start, end)));
+            }
+            if end > T::MAX {
+                return Err(invalid(format!(
+                    "range {}-{} goes past {}",
+                    start,
+                    end,
+                    T::MAX
+                )));
+            }
+        }
+        // normalized sets keep a gap between consecutive ranges.
+        if let Some(pair) = saved
+            .ranges
+            .windows(2)
+            .find(|pair| pair[1].0 as u64 <= pair[0].1 as u64 + 1)
+        {
+            return Err(invalid(format!(
+                "ranges {}-{} and {}-{} are out of order or touching",
+                pair[0].0, pair[0].1, pair[1].0, pair[1].1
+            )));
+        }
+
+        Ok(RangeSet {
+            ranges: saved.ranges,
+            value_type: PhantomData,
+        })
+    }
+}
+
+impl<T> Default for RangeSet<T> {
+    fn default() -> Self {
+        Self {
+            ranges: Vec::new(),
+            value_type: PhantomData,
+        }
+    }
+}
+
+impl<T: RangeValue> RangeSet<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, range: RangeInclusive<T>) {
+        let (start, end) = (range.start().to_u32(), range.end().to_u32());
+        if start > end {
+            return;
+        }
+
+        self.ranges.push((start, end));
+        self.normalize();
+    }
+
+    fn normalize(&mut self) {
+        self.ranges.sort_unstable();
+
+        let mut merged: Vec<(u32, u32)> = Vec::with_capacity(self.ranges.len());
+        for (start, end) in self.ranges.drain(..) {
+            match merged.last_mut() {
+                Some(last) if start as u64 <= last.1 as u64 + 1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        self.ranges = merged;
+    }
+
+    /// Values of `self` that are not in `other`.
+    pub fn difference(&self, other: &RangeSet<T>) -> RangeSet<T> {
+        let mut remaining = Vec::with_capacity(self.ranges.len());
+
+        for &(start, end) in &self.ranges {
+            let mut next_start = start as u64;
+            for &(excluded_start, excluded_end) in &other.ranges {
+                if excluded_end < start || excluded_start > end {
+                    continue;
+                }
+                if (excluded_start as u64) > next_start {
+                    remaining.push((next_start as u32, excluded_start - 1));
+                }
+                next_start = next_start.max(excluded_end as u64 + 1);
+            }
+            if next_start <= end as u64 {
+                remaining.push((next_start as u32, end));
+            }
+        }
+
+        RangeSet {
+            ranges: remaining,
+            value_type: PhantomData,
+        }
+    }
+
+    /// Values both in `self` and in `other`.
+    pub fn intersection(&self, other: &RangeSet<T>) -> RangeSet<T> {
+        self.difference(&self.difference(other))
+    }
+
+    pub fn contains(&self, value: T) -> bool {
+        let value = value.to_u32();
+        self.ranges
+            .binary_search_by(|&(start, end)| {
+                if end < value {
+                    std::cmp::Ordering::Less
+                } else if start > value {
+                    std::cmp::Ordering::Greater
+                } else {
+                    std::cmp::Ordering::Equal
+                }
+            })
+            .is_ok()
+    }
+
+    pub fn len(&self) -> u64 {
+        self.ranges
+            .iter()
+            .map(|&(start, end)| (end - start) as u64 + 1)
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Splits the set, in order, into sets of at most `size` values each.
+    pub fn chunks(&self, size: u64) -> Vec<RangeSet<T>> {
+        self.clone()
+            .into_chunks(size)
+            .collect()
+    }
+
+    /// Same as [`RangeSet::chunks`], one chunk at a time as they are needed.
+    pub fn into_chunks(self, size: u64) -> RangeChunks<T> {
+        RangeChunks {
+            ranges: self.ranges,
+            range_index: 0,
+            next_start: 0,
+            size: size.max(1),
+            value_type: PhantomData,
+        }
+    }
+
+    pub fn ranges(&self) -> impl Iterator<Item = RangeInclusive<T>> + '_ {
+        self.ranges
+            .iter()
+            .map(|&(start, end)| T::from_u32(start)..=T::from_u32(end))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        self.ranges
+            .iter()
+            .flat_map(|&(start, end)| (start..=end).map(T::from_u32))
+    }
+}
+
+/// Chunks of at most `size` values of a [`RangeSet`], in order.
+#[derive(Debug, Clone)]
+pub struct RangeChunks<T> {
+    ranges: Vec<(u32, u32)>,
+    range_index: usize,
+    next_start: u64,
+    size: u64,
+    value_type: PhantomData<T>,
+}
+
+impl<T: RangeValue> Iterator for RangeChunks<T> {
+    type Item = RangeSet<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut chunk = Vec::new();
+        let mut chunk_len = 0;
+
+        while chunk_len < self.size {
+            let Some(&(start, end)) = self.ranges.get(self.range_index) else {
+                break;
+            };
+            let start = self.next_start.max(start as u64);
+            let taken = (end as u64 - start + 1).min(self.size - chunk_len);
+            chunk.push((start as u32, (start + taken - 1) as u32));
+            chunk_len += taken;
+            self.next_start = start + taken;
+            if self.next_start > end as u64 {
+                self.range_index += 1;
+            }
+        }
+
+        (!chunk.is_empty()).then_some(RangeSet {
+            ranges: chunk,
+            value_type: PhantomData,
+        })
+    }
+}
+
+/// Random access to the values of a [`RangeSet`] by their position in it.
+#[derive(Debug, Clone)]
+pub struct IndexedRangeSet<T> {
+    range_set: RangeSet<T>,
+    // number of values in the ranges before each range.
+    offsets: Vec<u64>,
+    len: u64,
+}
+
+impl<T: RangeValue> IndexedRangeSet<T> {
+    pub fn new(range_set: RangeSet<T>) -> Self {
+        let mut offsets = Vec::with_capacity(range_set.ranges.len());
+        let mut len = 0;
+        for &(start, end) in &range_set.ranges {
+            offsets.push(len);
+            len += (end - start) as u64 + 1;
+        }
+
+        Self {
+            range_set,
+            offsets,
+            len,
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Value at `position`, as if the set was iterated in order.
+    pub fn get(&self, position: u64) -> Option<T> {
+        if position >= self.len {
+            return None;
+        }
+
+        let range_index = self
+            .offsets
+            .partition_point(|offset| *offset <= position)
+            - 1;
+        let (start, _) = self.range_set.ranges[range_index];
+        Some(T::from_u32(
+            start + (position - self.offsets[range_index]) as u32,
+        ))
+    }
+}
+
+impl<T: RangeValue> FromIterator<RangeInclusive<T>> for RangeSet<T> {
+    fn from_iter<I: IntoIterator<Item = RangeInclusive<T>>>(ranges: I) -> Self {
+        let mut range_set = RangeSet {
+            ranges: ranges
+                .into_iter()
+                .map(|range| (range.start().to_u32(), range.end().to_u32()))
+                .filter(|(start, end)| start <= end)
+                .collect(),
+            value_type: PhantomData,
+        };
+        range_set.normalize();
+        range_set
+    }
+}
+
+/// Parses a single IP (`10.0.0.5`), a CIDR (`10.0.0.0/28`) or a dash range
+/// (`10.0.0.5-10.0.0.40`) into the addresses it covers.
+pub fn parse_ip_range(spec: &str) -> anyhow::Result<RangeInclusive<Ipv4Addr>> {
+    let spec = spec.trim();
+
+    if spec.contains('/') {
+        let subnet = spec
+            .parse::<Ipv4Net>()
+            .context(format!("Unable to parse subnet: {}", spec))?;
+        return Ok(subnet.network()..=subnet.broadcast());
+    }
+
+    if let Some((start, end)) = spec.split_once('-') {
+        let start = parse_ip(start)?;
+        let end = parse_ip(end)?;
+        if start > end {
+            bail!(
+                "Start of the IP range {} must not be after its end {}",
+                start,
+                end
+            )
+        }
+        return Ok(start..=end);
+    }
+
+    let ip = parse_ip(spec)?;
+    Ok(ip..=ip)
+}
+
+/// Parses a CIDR, a single IP or a dash range into a scan target.
+pub fn parse_scan_target(spec: &str) -> anyhow::Result<ScanTarget> {
+    let spec = spec.trim();
+
+    if spec.contains('/') {
+        return subnet_helpers::parse_subnet(spec.to_string()).map(ScanTarget::Subnet);
+    }
+
+    let addresses = parse_ip_range(spec)?;
+    Ok(ScanTarget::Range(*addresses.start(), *addresses.end()))
+}
+
+fn parse_ip(ip: &str) -> anyhow::Result<Ipv4Addr> {
+    ip.trim()
+        .parse::<Ipv4Addr>()
+        .context(format!("Unable to parse IP address: {}", ip.trim()))
+}
+
+/// Non empty lines of a target list, with `#` comments stripped.
+pub fn target_lines(content: &str) -> impl Iterator<Item = &str> {
+    content
+        .lines()
+        .map(|line| {
+            line.split_once('#')
+                .map_or(line, |(target, _comment)| target)
+                .trim()
+        })
+        .filter(|line| !line.is_empty())
+}
+
+/// Reads a target list from `path`, or from stdin when `path` is `-`.
+pub fn read_target_file(path: &Path) -> anyhow::Result<Vec<String>> {
+    let content = if path.as_os_str() == "-" {
+        std::io::read_to_string(std::io::stdin()).context("Unable to read targets from stdin")?
+    } else {
+        fs::read_to_string(path)
+            .context(format!("Unable to read targets from {}", path.display()))?
+    };
+
+    Ok(target_lines(&content)
+        .map(String::from)
+        .collect())
+}
+
+/// The targets of a target file, read once and scanned as a single target
+/// named after the file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TargetList {
+    pub name: String,
+    pub targets: Vec<String>,
+}
+
+impl TargetList {
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let targets = read_target_file(path)?;
+        if targets.is_empty() {
+            bail!("No targets found in {}", path.display())
+        }
+
+        let name = if path.as_os_str() == "-" {
+            String::from("stdin")
+        } else {
+            path.display().to_string()
+        };
+        Ok(Self { name, targets })
+    }
+}
+
+#[cfg(test)]
+mod target_tests {
+    use std::net::Ipv4Addr;
+
+    use crate::{
+        models::ScanTarget,
+        target_helpers::{
+            parse_ip_range, parse_scan_target, target_lines, IndexedRangeSet, RangeSet,
+        },
+    };
+
+    fn ip(last_octet: u8) -> Ipv4Addr {
+        Ipv4Addr::new(10, 0, 0, last_octet)
+    }
+
+    #[test]
+    fn should_merge_overlapping_and_adjacent_ranges() {
+        let range_set: RangeSet<u16> = vec![30..=40, 1..=10, 11..=12, 5..=8, 41..=41]
+            .into_iter()
+            .collect();
+
+        assert_eq!(
+            range_set
+                .ranges()
+                .collect::<Vec<_>>(),
+            vec![1..=12, 30..=41]
+        );
+        assert_eq!(range_set.len(), 24);
+        assert!(range_set.contains(35));
+        assert!(!range_set.contains(20));
+    }
+
+    #[test]
+    fn should_subtract_excluded_values() {
+        let targets: RangeSet<Ipv4Addr> = vec![ip(1)..=ip(20), ip(100)..=ip(110)]
+            .into_iter()
+            .collect();
+        let exclusions: RangeSet<Ipv4Addr> = vec![ip(0)..=ip(2), ip(5)..=ip(5), ip(18)..=ip(105)]
+            .into_iter()
+            .collect();
+
+        let effective = targets.difference(&exclusions);
+
+        assert_eq!(
+            effective
+                .ranges()
+                .collect::<Vec<_>>(),
+            vec![ip(3)..=ip(4), ip(6)..=ip(17), ip(106)..=ip(110)]
+        );
+        assert_eq!(effective.len(), 19);
+        assert_eq!(effective.iter().next(), Some(ip(3)));
+    }
+
+    #[test]
+    fn should_look_up_values_by_position() {
+        let range_set: RangeSet<u16> = vec![1..=3, 10..=11, 20..=20]
+            .into_iter()
+            .collect();
+        let indexed = IndexedRangeSet::new(range_set.clone());
+
+        assert_eq!(indexed.len(), 6);
+        assert_eq!(
+            (0..indexed.len())
+                .map(|position| indexed.get(position).unwrap())
+                .collect::<Vec<_>>(),
+            range_set
+                .iter()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(indexed.get(6), None);
+    }
+
+    #[test]
+    fn should_split_into_chunks_of_a_size() {
+        let range_set: RangeSet<u16> = vec![1..=3, 10..=11, 20..=20]
+            .into_iter()
+            .collect();
+
+        let chunks: Vec<Vec<u16>> = range_set
+            .chunks(4)
+            .iter()
+            .map(|chunk| chunk.iter().collect())
+            .collect();
+
+        assert_eq!(chunks, vec![vec![1, 2, 3, 10], vec![11, 20]]);
+        assert!(RangeSet::<u16>::new()
+            .chunks(4)
+            .is_empty());
+    }
+
+    #[test]
+    fn should_hand_out_chunks_one_at_a_time() {
+        let everything: RangeSet<Ipv4Addr> =
+            std::iter::once(Ipv4Addr::UNSPECIFIED..=Ipv4Addr::BROADCAST).collect();
+        let mut chunks = everything.into_chunks(1 << 16);
+
+        assert_eq!(
+            chunks
+                .next()
+                .unwrap()
+                .ranges()
+                .collect::<Vec<_>>(),
+            vec![Ipv4Addr::new(0, 0, 0, 0)..=Ipv4Addr::new(0, 0, 255, 255)]
+        );
+        assert_eq!(chunks.count(), (1 << 16) - 1);
+    }
+
+    #[test]
+    fn should_only_deserialize_normalized_range_sets() {
+        let ports: RangeSet<u16> = serde_json::from_str(r#"{"ranges":[[1,3],[10,11]]}"#).unwrap();
+        assert_eq!(ports.iter().collect::<Vec<_>>(), vec![1, 2, 3, 10, 11]);
+
+        for (ranges, reason) in [
+            (r#"[[5,1]]"#, "range 5-1 is reversed"),
+            (r#"[[1,70000]]"#, "range 1-70000 goes past 65535"),
+            (
+                r#"[[10,11],[1,3]]"#,
+                "ranges 10-11 and 1-3 are out of order or touching",
+            ),
+            (
+                r#"[[1,3],[4,5]]"#,
+                "ranges 1-3 and 4-5 are out of order or touching",
+            ),
+        ] {
+            let error =
+                serde_json::from_str::<RangeSet<u16>>(&format!(r#"{{"ranges":{}}}"#, ranges))
+                    .err()
+                    .unwrap();
+            assert!(
+                error
+                    .to_string()
+                    .starts_with(&format!("Invalid range set: {}", reason)),
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn should_handle_the_edges_of_the_address_space() {
+        let everything: RangeSet<Ipv4Addr> =
+            std::iter::once(Ipv4Addr::UNSPECIFIED..=Ipv4Addr::BROADCAST).collect();
+        let broadcast: RangeSet<Ipv4Addr> =
+            std::iter::once(Ipv4Addr::BROADCAST..=Ipv4Addr::BROADCAST).collect();
+
+        assert_eq!(everything.len(), 1 << 32);
+        assert_eq!(
+            everything
+                .difference(&broadcast)
+                .len(),
+            (1 << 32) - 1
+        );
+        assert!(everything
+            .difference(&everything)
+            .is_empty());
+    }
+
+    #[test]
+    fn should_parse_ips_cidrs_and_dash_ranges() {
+        assert_eq!(parse_ip_range("10.0.0.7").unwrap(), ip(7)..=ip(7));
+        assert_eq!(parse_ip_range("10.0.0.0/29").unwrap(), ip(0)..=ip(7));
+        assert_eq!(
+            parse_ip_range(" 10.0.0.5 - 10.0.0.40 ").unwrap(),
+            ip(5)..=ip(40)
+        );
+        assert_eq!(
+            parse_ip_range("10.0.0.40-10.0.0.5")
+                .err()
+                .unwrap()
+                .to_string(),
+            "Start of the IP range 10.0.0.40 must not be after its end 10.0.0.5"
+        );
+        assert_eq!(
+            parse_ip_range("garBage")
+                .err()
+                .unwrap()
+                .to_string(),
+            "Unable to parse IP address: garBage"
+        );
+    }
+
+    #[test]
+    fn should_parse_scan_targets() {
+        assert_eq!(
+            parse_scan_target("10.0.0.0/24").unwrap(),
+            ScanTarget::Subnet("10.0.0.0/24".parse().unwrap())
+        );
+        assert_eq!(
+            parse_scan_target("10.0.0.5-10.0.0.40").unwrap(),
+            ScanTarget::Range(ip(5), ip(40))
+        );
+
+        let single_ip = parse_scan_target("10.0.0.7").unwrap();
+        assert_eq!(single_ip.to_string(), "10.0.0.7");
+        assert_eq!(
+            single_ip
+                .hosts()
+                .iter()
+                .collect::<Vec<_>>(),
+            vec![ip(7)]
+        );
+        assert_eq!(
+            ScanTarget::from(String::from("targets.txt")),
+            ScanTarget::Named(String::from("targets.txt"))
+        );
+    }
+
+    #[test]
+    fn should_skip_comments_and_blank_lines() {
+        let content = "# office network\n10.0.0.0/24\n\n  10.0.1.5 # printer\n#10.0.2.0/24\n";
+
+        assert_eq!(
+            target_lines(content).collect::<Vec<_>>(),
+            vec!["10.0.0.0/24", "10.0.1.5"]
+        );
+    }
+}
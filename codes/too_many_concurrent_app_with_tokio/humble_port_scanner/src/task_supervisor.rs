@@ -0,0 +1,208 @@
+use std::{any::Any, fmt, future::Future, sync::Arc, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use tokio::{runtime::Runtime, task::JoinError};
+
+use crate::{models::ScanTarget, tokio_helpers};
+
+const RESTART_BACKOFF: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", content = "reason", rename_all = "snake_case")]
+pub enum TaskOutcome {
+    Completed,
+    Failed(String),
+    Panicked(String),
+}
+
+impl TaskOutcome {
+    pub fn is_success(&self) -> bool {
+        matches!(self, TaskOutcome::Completed)
+    }
+
+    pub fn from_join_result(join_result: Result<anyhow::Result<()>, JoinError>) -> Self {
+        match join_result {
+            Ok(Ok(())) => TaskOutcome::Completed,
+            Ok(Err(task_error)) => TaskOutcome::Failed(format!("{:#}", task_error)),
+            Err(join_error) if join_error.is_panic() => {
+                TaskOutcome::Panicked(panic_message(join_error.into_panic()))
+            }
+            Err(join_error) => TaskOutcome::Failed(join_error.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for TaskOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskOutcome::Completed => write!(f, "completed"),
+            TaskOutcome::Failed(reason) => write!(f, "failed: {}", reason),
+            TaskOutcome::Panicked(reason) => write!(f, "panicked: {}", reason),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TaskReport {
+    pub task_name: String,
+    pub target: Option<ScanTarget>,
+    pub outcome: TaskOutcome,
+    pub restarts: u32,
+}
+
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => match panic.downcast::<&'static str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => String::from("unknown panic payload"),
+        },
+    }
+}
+
+/// Runs the task built by `make_task` as a named tokio task and restarts it up
+/// to `max_restarts` times when it returns an error or panics.
+pub async fn supervise<F, Fut>(
+    task_name: String,
+    target: Option<ScanTarget>,
+    runtime: Arc<Runtime>,
+    max_restarts: u32,
+    mut make_task: F,
+) -> TaskReport
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+{
+    let mut restarts = 0;
+
+    loop {
+        let join_result =
+            tokio_helpers::run_named_task(task_name.clone(), runtime.clone(), make_task()).await;
+        let outcome = TaskOutcome::from_join_result(join_result);
+
+        if outcome.is_success() || restarts >= max_restarts {
+            if !outcome.is_success() {
+                tracing::error!(task = %task_name, %outcome, restarts, "task gave up");
+            }
+
+            return TaskReport {
+                task_name,
+                target,
+                outcome,
+                restarts,
+            };
+        }
+
+        restarts += 1;
+        tracing::warn!(task = %task_name, %outcome, restarts, "restarting task");
+        tokio::time::sleep(RESTART_BACKOFF * restarts).await;
+    }
+}
+
+#[cfg(test)]
+mod supervisor_tests {
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    };
+
+    use anyhow::anyhow;
+
+    use crate::{
+        models::RuntimeConfiguration,
+        task_supervisor::{supervise, TaskOutcome},
+        tokio_helpers::setup_tokio_runtime,
+    };
+
+    fn runtime() -> Arc<tokio::runtime::Runtime> {
+        Arc::new(setup_tokio_runtime(&RuntimeConfiguration::default()).unwrap())
+    }
+
+    #[test]
+    fn should_report_completed_tasks() {
+        let runtime = runtime();
+        let report = runtime.block_on(supervise(
+            String::from("ok_task"),
+            None,
+            runtime.clone(),
+            0,
+            || async { Ok(()) },
+        ));
+
+        assert_eq!(report.outcome, TaskOutcome::Completed);
+        assert_eq!(report.restarts, 0);
+    }
+
+    #[test]
+    fn should_report_errors_and_panics() {
+        let runtime = runtime();
+        let failed = runtime.block_on(supervise(
+            String::from("failing_task"),
+            None,
+            runtime.clone(),
+            0,
+            || async { Err(anyhow!("channel closed")) },
+        ));
+        let panicked = runtime.block_on(supervise(
+            String::from("panicking_task"),
+            None,
+            runtime.clone(),
+            0,
+            || async { panic!("probe exploded") },
+        ));
+
+        assert_eq!(
+            failed.outcome,
+            TaskOutcome::Failed(String::from("channel closed"))
+        );
+        assert_eq!(
+            panicked.outcome,
+            TaskOutcome::Panicked(String::from("probe exploded"))
+        );
+    }
+
+    #[test]
+    fn should_restart_failed_tasks_until_they_succeed() {
+        let runtime = runtime();
+        let attempts = Arc::new(AtomicU32::new(0));
+        let task_attempts = attempts.clone();
+
+        let report = runtime.block_on(supervise(
+            String::from("flaky_task"),
+            None,
+            runtime.clone(),
+            3,
+            move || {
+                let attempts = task_attempts.clone();
+                async move {
+                    if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                        panic!("transient failure");
+                    }
+                    Ok(())
+                }
+            },
+        ));
+
+        assert_eq!(report.outcome, TaskOutcome::Completed);
+        assert_eq!(report.restarts, 2);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn should_give_up_after_max_restarts() {
+        let runtime = runtime();
+        let report = runtime.block_on(supervise(
+            String::from("broken_task"),
+            None,
+            runtime.clone(),
+            2,
+            || async { Err(anyhow!("still broken")) },
+        ));
+
+        assert_eq!(
+            report.outcome,
+            TaskOutcome::Failed(String::from("still broken"))
+        );
+        assert_eq!(report.restarts, 2);
+    }
+}
@@ -1,30 +1,209 @@
-use std::{future::Future, sync::Arc};
+use std::{
+    future::Future,
+    ops::Deref,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
 
+use anyhow::{bail, Context as _};
 use tokio::{
     runtime::{self, Runtime},
-    task,
+    task::{self, JoinError, JoinHandle},
+};
+
+use crate::{
+    errors::AppErrors,
+    models::{RuntimeConfiguration, RuntimeFlavor},
 };
 
+const RUNTIME_THREAD_NAME: &str = "scan_runtime";
+
+/// A spawned task that is aborted when its handle is dropped, so dropping the
+/// future awaiting it does not leave the task running on its own.
+pub struct ScopedTask<T>(JoinHandle<T>);
+
+impl<T> From<JoinHandle<T>> for ScopedTask<T> {
+    fn from(handle: JoinHandle<T>) -> Self {
+        Self(handle)
+    }
+}
+
+impl<T> Deref for ScopedTask<T> {
+    type Target = JoinHandle<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> Future for ScopedTask<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0).poll(cx)
+    }
+}
+
+impl<T> Drop for ScopedTask<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
 // requires setting up the .cargo/config.toml
-pub async fn run_named_task<F>(task_name: String, runtime: Arc<Runtime>, fut: F)
+/// Runs `fut` as a named task of `runtime` and waits for it, dropping the
+/// returned future aborts the task.
+pub async fn run_named_task<F>(
+    task_name: String,
+    runtime: Arc<Runtime>,
+    fut: F,
+) -> Result<F::Output, JoinError>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    ScopedTask::from(spawn_named_task(&task_name, &runtime, fut)).await
+}
+
+pub fn spawn_named_task<F>(task_name: &str, runtime: &Runtime, fut: F) -> JoinHandle<F::Output>
 where
     F: Future + Send + 'static,
     F::Output: Send + 'static,
 {
-    let _ = task::Builder::new()
-        .name(&task_name)
+    task::Builder::new()
+        .name(task_name)
         .spawn_on(fut, runtime.handle())
         .unwrap()
-        .await;
 }
 
-pub fn setup_tokio_runtime() -> Runtime {
-    runtime::Builder::new_multi_thread()
-        .worker_threads(4)
-        .thread_name("scan_runtime")
+pub fn setup_tokio_runtime(config: &RuntimeConfiguration) -> anyhow::Result<Runtime> {
+    let mut builder = match config.flavor {
+        RuntimeFlavor::CurrentThread => runtime::Builder::new_current_thread(),
+        RuntimeFlavor::MultiThread => {
+            if config.worker_threads == 0 {
+                bail!(AppErrors::InvalidRuntimeConfigurationError {
+                    reason: String::from("worker threads must be greater than zero"),
+                })
+            }
+
+            let mut builder = runtime::Builder::new_multi_thread();
+            builder.worker_threads(config.worker_threads);
+            builder
+        }
+    };
+
+    if let Some(max_blocking_threads) = config.max_blocking_threads {
+        if max_blocking_threads == 0 {
+            bail!(AppErrors::InvalidRuntimeConfigurationError {
+                reason: String::from("max blocking threads must be greater than zero"),
+            })
+        }
+        builder.max_blocking_threads(max_blocking_threads);
+    }
+
+    if let Some(event_interval) = config.event_interval {
+        if event_interval == 0 {
+            bail!(AppErrors::InvalidRuntimeConfigurationError {
+                reason: String::from("event interval must be greater than zero"),
+            })
+        }
+        builder.event_interval(event_interval);
+    }
+
+    if let Some(global_queue_interval) = config.global_queue_interval {
+        if global_queue_interval == 0 {
+            bail!(AppErrors::InvalidRuntimeConfigurationError {
+                reason: String::from("global queue interval must be greater than zero"),
+            })
+        }
+        builder.global_queue_interval(global_queue_interval);
+    }
+
+    builder
+        .thread_name(RUNTIME_THREAD_NAME)
         .enable_io()
         .enable_time()
-        .enable_metrics_poll_count_histogram()
+        .enable_metrics_poll_time_histogram()
         .build()
-        .expect("Failed to build Tokio Runtime.")
+        .context("Failed to build Tokio Runtime.")
+}
+
+#[cfg(test)]
+mod runtime_setup_tests {
+    use tokio::runtime::RuntimeFlavor as TokioRuntimeFlavor;
+
+    use crate::{
+        models::{RuntimeConfiguration, RuntimeFlavor},
+        tokio_helpers::setup_tokio_runtime,
+    };
+
+    #[test]
+    fn should_build_multi_thread_runtime_with_requested_workers() {
+        let runtime = setup_tokio_runtime(&RuntimeConfiguration {
+            worker_threads: 2,
+            max_blocking_threads: Some(8),
+            event_interval: Some(31),
+            global_queue_interval: Some(61),
+            ..RuntimeConfiguration::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            runtime.handle().runtime_flavor(),
+            TokioRuntimeFlavor::MultiThread
+        );
+        assert_eq!(runtime.metrics().num_workers(), 2);
+    }
+
+    #[test]
+    fn should_build_current_thread_runtime() {
+        let runtime = setup_tokio_runtime(&RuntimeConfiguration {
+            flavor: RuntimeFlavor::CurrentThread,
+            ..RuntimeConfiguration::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            runtime.handle().runtime_flavor(),
+            TokioRuntimeFlavor::CurrentThread
+        );
+        assert_eq!(runtime.block_on(async { 40 + 2 }), 42);
+    }
+
+    #[test]
+    fn should_reject_zero_sized_thread_pools() {
+        assert_eq!(
+            "Invalid runtime configuration: worker threads must be greater than zero",
+            setup_tokio_runtime(&RuntimeConfiguration {
+                worker_threads: 0,
+                ..RuntimeConfiguration::default()
+            })
+            .err()
+            .unwrap()
+            .to_string()
+        );
+
+        assert_eq!(
+            "Invalid runtime configuration: max blocking threads must be greater than zero",
+            setup_tokio_runtime(&RuntimeConfiguration {
+                max_blocking_threads: Some(0),
+                ..RuntimeConfiguration::default()
+            })
+            .err()
+            .unwrap()
+            .to_string()
+        );
+
+        assert_eq!(
+            "Invalid runtime configuration: event interval must be greater than zero",
+            setup_tokio_runtime(&RuntimeConfiguration {
+                event_interval: Some(0),
+                ..RuntimeConfiguration::default()
+            })
+            .err()
+            .unwrap()
+            .to_string()
+        );
+    }
 }
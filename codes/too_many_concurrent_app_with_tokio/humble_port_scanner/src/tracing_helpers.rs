@@ -0,0 +1,306 @@
+use anyhow::Context;
+use clap::ValueEnum;
+use tracing_subscriber::{
+    fmt::{self, MakeWriter},
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    util::SubscriberInitExt,
+    EnvFilter, Layer,
+};
+
+#[cfg(feature = "otlp")]
+use opentelemetry::trace::TracerProvider;
+
+const DEFAULT_LOG_FILTER: &str = "warn";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    Pretty,
+    Json,
+}
+
+/// Flushes pending spans to the OTLP collector when dropped.
+pub struct TracingGuard {
+    #[cfg(feature = "otlp")]
+    tracer_provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+impl Drop for TracingGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "otlp")]
+        if let Some(tracer_provider) = self.tracer_provider.take() {
+            let _ = tracer_provider.shutdown();
+        }
+    }
+}
+
+/// Builds the env filter from `RUST_LOG`, falling back to warnings only.
+pub fn env_filter() -> EnvFilter {
+    EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_FILTER))
+}
+
+pub fn format_layer<S, W>(log_format: LogFormat, writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    let layer = fmt::layer()
+        .with_writer(writer)
+        .with_span_events(fmt::format::FmtSpan::CLOSE);
+
+    match log_format {
+        LogFormat::Pretty => layer.pretty().boxed(),
+        LogFormat::Json => layer
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .boxed(),
+    }
+}
+
+pub fn setup_tracing(
+    log_format: LogFormat,
+    otlp_endpoint: Option<String>,
+) -> anyhow::Result<TracingGuard> {
+    let registry = tracing_subscriber::registry()
+        .with(env_filter())
+        .with(format_layer(log_format, std::io::stderr));
+
+    #[cfg(feature = "otlp")]
+    {
+        let tracer_provider = otlp_endpoint
+            .map(setup_otlp_tracer_provider)
+            .transpose()?;
+        let otlp_layer = tracer_provider
+            .as_ref()
+            .map(|provider| {
+                tracing_opentelemetry::layer().with_tracer(provider.tracer("humble_port_scanner"))
+            });
+
+        registry
+            .with(otlp_layer)
+            .try_init()
+            .context("Unable to install the tracing subscriber")?;
+
+        Ok(TracingGuard { tracer_provider })
+    }
+
+    #[cfg(not(feature = "otlp"))]
+    {
+        if otlp_endpoint.is_some() {
+            anyhow::bail!(
+                "OTLP export requires humble_port_scanner to be built with the `otlp` feature"
+            )
+        }
+
+        registry
+            .try_init()
+            .context("Unable to install the tracing subscriber")?;
+
+        Ok(TracingGuard {})
+    }
+}
+
+#[cfg(feature = "otlp")]
+fn setup_otlp_tracer_provider(
+    otlp_endpoint: String,
+) -> anyhow::Result<opentelemetry_sdk::trace::SdkTracerProvider> {
+    use opentelemetry_otlp::WithExportConfig;
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(otlp_endpoint.clone())
+        .build()
+        .context(format!(
+            "Unable to setup the OTLP exporter for {}",
+            otlp_endpoint
+        ))?;
+
+    Ok(opentelemetry_sdk::trace::SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            opentelemetry_sdk::Resource::builder()
+                .with_service_name("humble_port_scanner")
+                .build(),
+        )
+        .build())
+}
+
+#[cfg(test)]
+mod tracing_setup_tests {
+    use std::{
+        collections::BTreeSet,
+        io::Write,
+        net::Ipv4Addr,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use serde_json::Value;
+    use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt};
+
+    use crate::{
+        app::SubnetScannerApp,
+        models::SubnetScanConfiguration,
+        simulated_network::{SimulatedLink, SimulatedNetwork},
+        tracing_helpers::{format_layer, LogFormat},
+    };
+
+    #[derive(Clone, Default)]
+    struct CapturedWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for CapturedWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0
+                .lock()
+                .unwrap()
+                .extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for CapturedWriter {
+        type Writer = CapturedWriter;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    fn captured_output(log_format: LogFormat) -> String {
+        let writer = CapturedWriter::default();
+        let subscriber =
+            tracing_subscriber::registry().with(format_layer(log_format, writer.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("probe", ip = "127.0.0.1", port = 22_u16);
+            let _entered = span.enter();
+            tracing::info!(state = "Open", latency_us = 120_u64, "probe finished");
+        });
+
+        let output = writer.0.lock().unwrap().clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn should_emit_probe_fields_as_json() {
+        let output = captured_output(LogFormat::Json);
+
+        assert!(output.contains("\"state\":\"Open\""));
+        assert!(output.contains("\"latency_us\":120"));
+        assert!(output.contains("\"name\":\"probe\""));
+        assert!(output.contains("\"port\":22"));
+    }
+
+    #[test]
+    fn should_emit_probe_fields_in_pretty_format() {
+        let output = captured_output(LogFormat::Pretty);
+
+        assert!(output.contains("probe finished"));
+        assert!(output.contains("latency_us"));
+    }
+
+    // the spans closed during a scan of 10.0.0.0/30 on ports 22 and 23, only
+    // port 22 of 10.0.0.1 accepts connections.
+    fn closed_scan_spans() -> Vec<Value> {
+        let runtime = Arc::new(
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .start_paused(true)
+                .build()
+                .unwrap(),
+        );
+        let network = Arc::new(
+            SimulatedNetwork::new(SimulatedLink::refusing(Duration::from_millis(1)))
+                .with_host_port(
+                    Ipv4Addr::new(10, 0, 0, 1),
+                    22,
+                    SimulatedLink::accepting(Duration::from_millis(5)),
+                ),
+        );
+        let writer = CapturedWriter::default();
+        let subscriber =
+            tracing_subscriber::registry().with(format_layer(LogFormat::Json, writer.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let mut app = SubnetScannerApp::builder()
+                .set_runtime(&runtime)
+                .set_configs(vec![SubnetScanConfiguration::new(
+                    "10.0.0.0/30"
+                        .parse::<ipnet::Ipv4Net>()
+                        .unwrap(),
+                    22,
+                    24,
+                )])
+                .set_scan_timeout(Duration::from_secs(1))
+                .set_show_progress(false)
+                .set_connector(network)
+                .build()
+                .unwrap();
+            app.start_subnet_scans();
+            app.run();
+        });
+
+        let output = String::from_utf8(writer.0.lock().unwrap().clone()).unwrap();
+        output
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .filter(|event| event["fields"]["message"] == "close")
+            .map(|event| event["span"].clone())
+            .collect()
+    }
+
+    #[test]
+    fn should_emit_scan_host_and_probe_spans_during_a_scan() {
+        let spans = closed_scan_spans();
+        let spans_named = |name: &str| {
+            spans
+                .iter()
+                .filter(|span| span["name"] == name)
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        let subnet_scans = spans_named("subnet_scan");
+        assert_eq!(subnet_scans.len(), 1);
+        assert_eq!(subnet_scans[0]["target"], "10.0.0.0/30");
+
+        assert_eq!(
+            spans_named("host_scan")
+                .iter()
+                .map(|span| span["ip"]
+                    .as_str()
+                    .unwrap()
+                    .to_string())
+                .collect::<BTreeSet<_>>(),
+            BTreeSet::from([String::from("10.0.0.1"), String::from("10.0.0.2")])
+        );
+
+        assert_eq!(
+            spans_named("probe")
+                .iter()
+                .map(|span| (
+                    span["ip"]
+                        .as_str()
+                        .unwrap()
+                        .to_string(),
+                    span["port"].as_u64().unwrap(),
+                    span["state"]
+                        .as_str()
+                        .unwrap()
+                        .to_string(),
+                ))
+                .collect::<BTreeSet<_>>(),
+            BTreeSet::from([
+                (String::from("10.0.0.1"), 22, String::from("Open")),
+                (String::from("10.0.0.1"), 23, String::from("Closed")),
+                (String::from("10.0.0.2"), 22, String::from("Closed")),
+                (String::from("10.0.0.2"), 23, String::from("Closed")),
+            ])
+        );
+    }
+}
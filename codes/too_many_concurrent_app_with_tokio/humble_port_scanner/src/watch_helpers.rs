@@ -0,0 +1,617 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    net::Ipv4Addr,
+    time::{Duration, Instant, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    change_sinks::ChangeSink,
+    models::ScanTarget,
+    scan_diff::{self, ScanDiff},
+    scan_report::{HostReport, ScanReport, SubnetReport},
+    task_supervisor::TaskOutcome,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Change {
+    HostAppeared {
+        ip: Ipv4Addr,
+    },
+    HostDisappeared {
+        ip: Ipv4Addr,
+    },
+    PortOpened {
+        ip: Ipv4Addr,
+        port: u16,
+        service: Option<String>,
+    },
+    PortClosed {
+        ip: Ipv4Addr,
+        port: u16,
+        service: Option<String>,
+    },
+}
+
+impl Change {
+    fn from_diff(scan_diff: ScanDiff) -> Vec<Change> {
+        let host_changes = scan_diff
+            .new_hosts
+            .into_iter()
+            .map(|ip| Change::HostAppeared { ip })
+            .chain(
+                scan_diff
+                    .disappeared_hosts
+                    .into_iter()
+                    .map(|ip| Change::HostDisappeared { ip }),
+            );
+        let port_changes = scan_diff
+            .opened_ports
+            .into_iter()
+            .map(|change| Change::PortOpened {
+                ip: change.ip,
+                port: change.port,
+                service: change.service,
+            })
+            .chain(
+                scan_diff
+                    .closed_ports
+                    .into_iter()
+                    .map(|change| Change::PortClosed {
+                        ip: change.ip,
+                        port: change.port,
+                        service: change.service,
+                    }),
+            );
+
+        host_changes
+            .chain(port_changes)
+            .collect()
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::HostAppeared { ip } => write!(f, "+ host {}", ip),
+            Change::HostDisappeared { ip } => write!(f, "- host {}", ip),
+            Change::PortOpened { ip, port, service } => write!(
+                f,
+                "+ {}:{}/tcp opened ({})",
+                ip,
+                port,
+                service
+                    .as_deref()
+                    .unwrap_or("unknown")
+            ),
+            Change::PortClosed { ip, port, service } => write!(
+                f,
+                "- {}:{}/tcp closed ({})",
+                ip,
+                port,
+                service
+                    .as_deref()
+                    .unwrap_or("unknown")
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangeEvent {
+    /// RFC 3339 time at which the scan noticing the change finished.
+    pub detected_at: String,
+    #[serde(flatten)]
+    pub change: Change,
+}
+
+impl fmt::Display for ChangeEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.detected_at, self.change)
+    }
+}
+
+/// Last known state of every host seen while watching.
+///
+/// Hosts of subnets whose scan failed keep their previous state, so a broken
+/// scan does not show up as hosts disappearing.
+#[derive(Debug, Default)]
+pub struct WatchState {
+    hosts: BTreeMap<Ipv4Addr, HostReport>,
+    /// Targets whose first completed scan was recorded.
+    baselines: HashSet<ScanTarget>,
+}
+
+// hosts of `subnet_report` whose every planned port was probed, none when the
+// scan failed or when the hosts it did not get to are unknown.
+fn fully_probed_hosts(subnet_report: &SubnetReport) -> Vec<&HostReport> {
+    if !subnet_report
+        .outcome
+        .as_ref()
+        .is_some_and(TaskOutcome::is_success)
+    {
+        return Vec::new();
+    }
+    if subnet_report.not_scanned > 0 && subnet_report.unscanned.is_empty() {
+        tracing::warn!(
+            target = %subnet_report.target,
+            not_scanned = subnet_report.not_scanned,
+            "scan cut short without listing its unscanned hosts, left out of the watch"
+        );
+        return Vec::new();
+    }
+
+    subnet_report
+        .hosts
+        .iter()
+        .filter(|host| {
+            !subnet_report
+                .unscanned
+                .iter()
+                .any(|unscanned| unscanned.hosts.contains(host.ip))
+        })
+        .collect()
+}
+
+impl WatchState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Folds `scan_report` into the known state and returns what changed.
+    ///
+    /// The first completed scan of each target only records its baseline and
+    /// reports nothing. Scans cut short are compared on the hosts they fully probed.
+    pub fn update(
+        &mut self,
+        scan_report: &ScanReport,
+        detected_at: SystemTime,
+    ) -> Vec<ChangeEvent> {
+        if !scan_report
+            .task_failures
+            .is_empty()
+        {
+            return Vec::new();
+        }
+
+        let mut compared_hosts = Vec::new();
+        for subnet_report in &scan_report.subnets {
+            let probed_hosts = fully_probed_hosts(subnet_report);
+            if self
+                .baselines
+                .contains(&subnet_report.target)
+            {
+                compared_hosts.extend(probed_hosts);
+                continue;
+            }
+
+            for host in probed_hosts {
+                self.hosts
+                    .insert(host.ip, host.clone());
+            }
+            if subnet_report.not_scanned == 0
+                && subnet_report
+                    .outcome
+                    .as_ref()
+                    .is_some_and(TaskOutcome::is_success)
+            {
+                self.baselines
+                    .insert(subnet_report.target.clone());
+            }
+        }
+        if compared_hosts.is_empty() {
+            return Vec::new();
+        }
+
+        let scan_diff = scan_diff::diff_hosts(self.hosts.values(), compared_hosts.iter().copied());
+        for host in compared_hosts {
+            self.hosts
+                .insert(host.ip, host.clone());
+        }
+
+        let detected_at = humantime::format_rfc3339_seconds(detected_at).to_string();
+        Change::from_diff(scan_diff)
+            .into_iter()
+            .map(|change| ChangeEvent {
+                detected_at: detected_at.clone(),
+                change,
+            })
+            .collect()
+    }
+}
+
+pub enum WatchSchedule {
+    Interval(Duration),
+    Cron(Box<cron::Schedule>),
+}
+
+impl WatchSchedule {
+    /// Time left before the next scan, given when the previous scan started.
+    pub fn delay_until_next_scan(&self, last_scan_started: Option<Instant>) -> Duration {
+        match self {
+            WatchSchedule::Interval(interval) => last_scan_started
+                .map(|started| interval.saturating_sub(started.elapsed()))
+                .unwrap_or(Duration::ZERO),
+            WatchSchedule::Cron(schedule) => schedule
+                .upcoming(chrono::Utc)
+                .next()
+                .and_then(|next_scan| {
+                    (next_scan - chrono::Utc::now())
+                        .to_std()
+                        .ok()
+                })
+                .unwrap_or(Duration::ZERO),
+        }
+    }
+}
+
+/// Runs `scan` on `schedule` and publishes the changes between consecutive scans.
+///
+/// A failed scan or sink is logged and watching goes on with the next scan,
+/// until `max_cycles` scans ran or no scan can start before `until`.
+pub fn watch<S>(
+    schedule: &WatchSchedule,
+    max_cycles: Option<u64>,
+    until: Option<SystemTime>,
+    sinks: &mut [Box<dyn ChangeSink>],
+    mut scan: S,
+) where
+    S: FnMut() -> anyhow::Result<ScanReport>,
+{
+    let mut watch_state = WatchState::new();
+    let mut last_scan_started = None;
+    let mut cycle = 0;
+    let past_until =
+        |delay: Duration| until.is_some_and(|until| SystemTime::now() + delay >= until);
+
+    while max_cycles.is_none_or(|max_cycles| cycle < max_cycles) {
+        let delay = schedule.delay_until_next_scan(last_scan_started);
+        // the next scan would start too late to issue a single probe.
+        if past_until(delay) {
+            tracing::info!(cycle, "watch deadline reached");
+            break;
+        }
+        std::thread::sleep(delay);
+        if past_until(Duration::ZERO) {
+            tracing::info!(cycle, "watch deadline reached");
+            break;
+        }
+        last_scan_started = Some(Instant::now());
+        cycle += 1;
+
+        let scan_report = match scan() {
+            Ok(scan_report) => scan_report,
+            Err(scan_error) => {
+                tracing::error!(
+                    cycle,
+                    error = format!("{:#}", scan_error),
+                    "watch scan failed"
+                );
+                continue;
+            }
+        };
+
+        let change_events = watch_state.update(&scan_report, SystemTime::now());
+        tracing::info!(cycle, changes = change_events.len(), "watch scan completed");
+        if change_events.is_empty() {
+            continue;
+        }
+
+        for sink in sinks.iter_mut() {
+            if let Err(sink_error) = sink.publish(&change_events) {
+                tracing::error!(
+                    cycle,
+                    error = format!("{:#}", sink_error),
+                    "unable to publish change events"
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod watch_tests {
+    use std::{
+        net::Ipv4Addr,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant, SystemTime},
+    };
+
+    use anyhow::anyhow;
+
+    use crate::{
+        change_sinks::ChangeSink,
+        models::ScanTarget,
+        scan_report::{
+            HostReport, OpenPort, ScanReport, StateCounts, SubnetReport, UnscannedProbes,
+        },
+        target_helpers::RangeSet,
+        task_supervisor::TaskOutcome,
+        watch_helpers::{watch, Change, ChangeEvent, WatchSchedule, WatchState},
+    };
+
+    // every watch scan probes these ports, those not open are closed.
+    const PROBED_PORTS: [u16; 3] = [22, 80, 443];
+
+    fn host(last_octet: u8, open_ports: &[u16]) -> HostReport {
+        let closed_ports: RangeSet<u16> = PROBED_PORTS
+            .iter()
+            .filter(|port| !open_ports.contains(port))
+            .map(|port| *port..=*port)
+            .collect();
+
+        HostReport {
+            ip: Ipv4Addr::new(10, 0, 0, last_octet),
+            hostname: None,
+            open_ports: open_ports
+                .iter()
+                .map(|port| OpenPort {
+                    port: *port,
+                    service: None,
+                })
+                .collect(),
+            state_counts: StateCounts {
+                open: open_ports.len() as u64,
+                closed: closed_ports.len(),
+                timeout: 0,
+            },
+            closed_ports,
+            timed_out_ports: RangeSet::new(),
+        }
+    }
+
+    fn subnet(target: &str, hosts: Vec<HostReport>, outcome: TaskOutcome) -> SubnetReport {
+        SubnetReport {
+            target: ScanTarget::Subnet(target.parse().unwrap()),
+            hosts,
+            state_counts: StateCounts::default(),
+            outcome: Some(outcome),
+            restarts: 0,
+            planned_probes: 0,
+            not_scanned: 0,
+            unscanned: Vec::new(),
+        }
+    }
+
+    fn scan_report(subnets: Vec<SubnetReport>) -> ScanReport {
+        ScanReport {
+            subnets,
+            task_failures: Vec::new(),
+            started_at: SystemTime::UNIX_EPOCH,
+            duration: Duration::from_secs(1),
+        }
+    }
+
+    fn report(hosts: Vec<HostReport>, outcome: TaskOutcome) -> ScanReport {
+        scan_report(vec![subnet("10.0.0.0/24", hosts, outcome)])
+    }
+
+    // a scan of 10.0.0.0/24 that ran out of time while probing 10.0.0.2.
+    fn cut_short_report(hosts: Vec<HostReport>, unscanned: Vec<UnscannedProbes>) -> ScanReport {
+        scan_report(vec![SubnetReport {
+            not_scanned: 2,
+            unscanned,
+            ..subnet("10.0.0.0/24", hosts, TaskOutcome::Completed)
+        }])
+    }
+
+    fn changes(change_events: Vec<ChangeEvent>) -> Vec<Change> {
+        change_events
+            .into_iter()
+            .map(|change_event| change_event.change)
+            .collect()
+    }
+
+    #[derive(Clone, Default)]
+    struct RecordingSink(Arc<Mutex<Vec<ChangeEvent>>>);
+
+    impl ChangeSink for RecordingSink {
+        fn publish(&mut self, change_events: &[ChangeEvent]) -> anyhow::Result<()> {
+            self.0
+                .lock()
+                .unwrap()
+                .extend_from_slice(change_events);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn should_only_report_changes_after_the_baseline() {
+        let mut watch_state = WatchState::new();
+        let baseline = report(vec![host(1, &[22])], TaskOutcome::Completed);
+        let next = report(vec![host(1, &[80]), host(2, &[])], TaskOutcome::Completed);
+
+        assert!(watch_state
+            .update(&baseline, SystemTime::UNIX_EPOCH)
+            .is_empty());
+
+        let change_events = watch_state.update(&next, SystemTime::UNIX_EPOCH);
+        assert_eq!(change_events[0].detected_at, "1970-01-01T00:00:00Z");
+        assert_eq!(
+            changes(change_events),
+            vec![
+                Change::HostAppeared {
+                    ip: Ipv4Addr::new(10, 0, 0, 2)
+                },
+                Change::PortOpened {
+                    ip: Ipv4Addr::new(10, 0, 0, 1),
+                    port: 80,
+                    service: None
+                },
+                Change::PortClosed {
+                    ip: Ipv4Addr::new(10, 0, 0, 1),
+                    port: 22,
+                    service: None
+                },
+            ]
+        );
+        assert!(watch_state
+            .update(&next, SystemTime::UNIX_EPOCH)
+            .is_empty());
+    }
+
+    #[test]
+    fn should_keep_the_last_known_state_of_failed_subnets() {
+        let mut watch_state = WatchState::new();
+        let baseline = report(vec![host(1, &[22])], TaskOutcome::Completed);
+        let failed = report(vec![], TaskOutcome::Failed(String::from("boom")));
+        let recovered = report(vec![host(1, &[22])], TaskOutcome::Completed);
+
+        watch_state.update(&baseline, SystemTime::UNIX_EPOCH);
+
+        assert!(watch_state
+            .update(&failed, SystemTime::UNIX_EPOCH)
+            .is_empty());
+        assert!(watch_state
+            .update(&recovered, SystemTime::UNIX_EPOCH)
+            .is_empty());
+    }
+
+    #[test]
+    fn should_take_the_baseline_of_each_target_on_its_first_completed_scan() {
+        let mut watch_state = WatchState::new();
+        let first = scan_report(vec![
+            subnet("10.0.0.0/25", vec![host(1, &[22])], TaskOutcome::Completed),
+            subnet(
+                "10.0.0.128/25",
+                vec![],
+                TaskOutcome::Failed(String::from("boom")),
+            ),
+        ]);
+        let second = scan_report(vec![
+            subnet("10.0.0.0/25", vec![host(1, &[22])], TaskOutcome::Completed),
+            subnet(
+                "10.0.0.128/25",
+                vec![host(130, &[22]), host(131, &[443])],
+                TaskOutcome::Completed,
+            ),
+        ]);
+        let third = scan_report(vec![
+            subnet("10.0.0.0/25", vec![host(1, &[22])], TaskOutcome::Completed),
+            subnet(
+                "10.0.0.128/25",
+                vec![host(130, &[22, 80]), host(131, &[443])],
+                TaskOutcome::Completed,
+            ),
+        ]);
+
+        assert!(watch_state
+            .update(&first, SystemTime::UNIX_EPOCH)
+            .is_empty());
+        assert!(watch_state
+            .update(&second, SystemTime::UNIX_EPOCH)
+            .is_empty());
+        assert_eq!(
+            changes(watch_state.update(&third, SystemTime::UNIX_EPOCH)),
+            vec![Change::PortOpened {
+                ip: Ipv4Addr::new(10, 0, 0, 130),
+                port: 80,
+                service: None
+            }]
+        );
+    }
+
+    #[test]
+    fn should_compare_the_fully_probed_hosts_of_scans_cut_short() {
+        let mut watch_state = WatchState::new();
+        let baseline = report(vec![host(1, &[22]), host(2, &[22])], TaskOutcome::Completed);
+        // 10.0.0.2 was only probed on 22, which closed, before time ran out.
+        let mut partial_host = host(2, &[]);
+        partial_host.closed_ports = std::iter::once(22..=22).collect();
+        let cut_short = cut_short_report(
+            vec![host(1, &[22, 80]), partial_host],
+            vec![UnscannedProbes {
+                hosts: std::iter::once(Ipv4Addr::new(10, 0, 0, 2)..=Ipv4Addr::new(10, 0, 0, 2))
+                    .collect(),
+                ports: std::iter::once(80..=443).collect(),
+            }],
+        );
+
+        watch_state.update(&baseline, SystemTime::UNIX_EPOCH);
+
+        assert_eq!(
+            changes(watch_state.update(&cut_short, SystemTime::UNIX_EPOCH)),
+            vec![Change::PortOpened {
+                ip: Ipv4Addr::new(10, 0, 0, 1),
+                port: 80,
+                service: None
+            }]
+        );
+        // without the unscanned hosts, nothing of the scan can be trusted.
+        assert!(watch_state
+            .update(
+                &cut_short_report(vec![host(1, &[443])], Vec::new()),
+                SystemTime::UNIX_EPOCH
+            )
+            .is_empty());
+        assert!(watch_state
+            .update(
+                &report(
+                    vec![host(1, &[22, 80]), host(2, &[22])],
+                    TaskOutcome::Completed
+                ),
+                SystemTime::UNIX_EPOCH
+            )
+            .is_empty());
+    }
+
+    #[test]
+    fn should_publish_changes_until_max_cycles() {
+        let sink = RecordingSink::default();
+        let mut sinks: Vec<Box<dyn ChangeSink>> = vec![Box::new(sink.clone())];
+        let mut scans = vec![
+            Ok(report(vec![host(1, &[22])], TaskOutcome::Completed)),
+            Err(anyhow!("runtime setup failed")),
+            Ok(report(vec![host(1, &[22, 443])], TaskOutcome::Completed)),
+        ]
+        .into_iter();
+
+        watch(
+            &WatchSchedule::Interval(Duration::from_millis(1)),
+            Some(3),
+            None,
+            &mut sinks,
+            || scans.next().unwrap(),
+        );
+
+        let published = sink.0.lock().unwrap().clone();
+        assert_eq!(
+            changes(published),
+            vec![Change::PortOpened {
+                ip: Ipv4Addr::new(10, 0, 0, 1),
+                port: 443,
+                service: None
+            }]
+        );
+        assert!(scans.next().is_none());
+    }
+
+    #[test]
+    fn should_stop_watching_once_no_scan_can_start_before_the_deadline() {
+        let mut scans = 0;
+
+        watch(
+            &WatchSchedule::Interval(Duration::from_millis(100)),
+            None,
+            Some(SystemTime::now() + Duration::from_millis(150)),
+            &mut [],
+            || {
+                scans += 1;
+                Ok(report(vec![host(1, &[22])], TaskOutcome::Completed))
+            },
+        );
+
+        assert_eq!(scans, 2);
+    }
+
+    #[test]
+    fn should_wait_out_the_rest_of_the_interval() {
+        let schedule = WatchSchedule::Interval(Duration::from_secs(60));
+
+        assert_eq!(schedule.delay_until_next_scan(None), Duration::ZERO);
+        assert!(schedule.delay_until_next_scan(Some(Instant::now())) > Duration::from_secs(59));
+    }
+}
@@ -0,0 +1,86 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::io::{AsyncBufRead, AsyncWrite, AsyncWriteExt, Lines};
+
+use crate::models::{IpPortScanResult, SubnetScanConfiguration};
+
+/// Bumped on every incompatible change of the messages below.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// A share of a scan handed to a worker: some hosts of one target, with every port.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkChunk {
+    pub id: u64,
+    pub config: SubnetScanConfiguration,
+    /// Results of the chunk the coordinator already received from a lost worker.
+    pub delivered: u64,
+    pub scan_timeout: Duration,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WorkerMessage {
+    Hello {
+        version: u32,
+        worker_name: String,
+    },
+    Result {
+        chunk_id: u64,
+        result: IpPortScanResult,
+    },
+    ChunkDone {
+        chunk_id: u64,
+    },
+    /// The scan of the chunk failed or panicked, it is not handed out again.
+    ChunkFailed {
+        chunk_id: u64,
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CoordinatorMessage {
+    Assign {
+        chunk: Box<WorkChunk>,
+    },
+    /// Every chunk is done, the worker may leave.
+    Done,
+    Rejected {
+        reason: String,
+    },
+}
+
+/// Writes `message` as a single line of JSON.
+pub async fn send_message<W, M>(writer: &mut W, message: &M) -> anyhow::Result<()>
+where
+    W: AsyncWrite + Unpin,
+    M: Serialize,
+{
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    writer
+        .write_all(&line)
+        .await
+        .context("Unable to send message")
+}
+
+/// Reads the next message, `None` once the peer closed the connection.
+pub async fn read_message<R, M>(lines: &mut Lines<R>) -> anyhow::Result<Option<M>>
+where
+    R: AsyncBufRead + Unpin,
+    M: DeserializeOwned,
+{
+    match lines
+        .next_line()
+        .await
+        .context("Unable to read message")?
+    {
+        Some(line) => Ok(Some(
+            serde_json::from_str(&line).context(format!("Invalid message: {}", line))?,
+        )),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,122 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::{bail, Context};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWrite, BufReader},
+    net::TcpStream,
+    sync::mpsc,
+};
+
+use crate::{
+    app::SubnetScannerApp,
+    connector::Connector,
+    metrics_helpers::ScanMetrics,
+    port_helpers,
+    work_protocol::{self, CoordinatorMessage, WorkChunk, WorkerMessage, PROTOCOL_VERSION},
+};
+
+const RESULT_CHANNEL_CAPACITY: usize = 256;
+
+/// Scans the chunks handed out by the coordinator at `coordinator` with
+/// `connector`, one at a time, until it says every chunk is done. Returns the
+/// number of chunks scanned.
+pub async fn run_worker(
+    coordinator: SocketAddr,
+    worker_name: String,
+    connector: Arc<dyn Connector>,
+) -> anyhow::Result<u64> {
+    let stream = TcpStream::connect(coordinator)
+        .await
+        .context(format!(
+            "Unable to connect to the coordinator at {}",
+            coordinator
+        ))?;
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    work_protocol::send_message(
+        &mut writer,
+        &WorkerMessage::Hello {
+            version: PROTOCOL_VERSION,
+            worker_name: worker_name.clone(),
+        },
+    )
+    .await?;
+
+    let metrics = Arc::new(ScanMetrics::new());
+    let mut chunks_scanned = 0;
+    loop {
+        match work_protocol::read_message(&mut lines).await? {
+            Some(CoordinatorMessage::Assign { chunk }) => {
+                tracing::info!(worker = %worker_name, chunk = chunk.id, "scanning chunk");
+                scan_chunk(*chunk, &mut writer, connector.clone(), metrics.clone()).await?;
+                chunks_scanned += 1;
+            }
+            Some(CoordinatorMessage::Done) => return Ok(chunks_scanned),
+            Some(CoordinatorMessage::Rejected { reason }) => {
+                bail!("Coordinator rejected the worker: {}", reason)
+            }
+            None => bail!("Coordinator closed the connection before the scan was done"),
+        }
+    }
+}
+
+// streams every result as soon as it is probed, so the coordinator knows how
+// far the chunk got should this worker die.
+async fn scan_chunk<W>(
+    chunk: WorkChunk,
+    writer: &mut W,
+    connector: Arc<dyn Connector>,
+    metrics: Arc<ScanMetrics>,
+) -> anyhow::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    // the coordinator cannot tell whether the source exists on this host.
+    if let Err(source_error) = port_helpers::validate_source(&chunk.config.source) {
+        let reason = format!("{:#}", source_error);
+        tracing::warn!(chunk = chunk.id, %reason, "chunk scan failed");
+        let failed = WorkerMessage::ChunkFailed {
+            chunk_id: chunk.id,
+            reason,
+        };
+        return work_protocol::send_message(writer, &failed).await;
+    }
+
+    let (tx, mut rx) = mpsc::channel(RESULT_CHANNEL_CAPACITY);
+    let scan = tokio::spawn(SubnetScannerApp::scan_configuration(
+        chunk.config,
+        chunk.scan_timeout,
+        chunk.delivered,
+        connector,
+        tx,
+        metrics,
+    ));
+
+    while let Some(result) = rx.recv().await {
+        work_protocol::send_message(
+            writer,
+            &WorkerMessage::Result {
+                chunk_id: chunk.id,
+                result,
+            },
+        )
+        .await?;
+    }
+    let done = match scan.await {
+        Ok(Ok(())) => WorkerMessage::ChunkDone { chunk_id: chunk.id },
+        Ok(Err(scan_error)) => WorkerMessage::ChunkFailed {
+            chunk_id: chunk.id,
+            reason: format!("{:#}", scan_error),
+        },
+        Err(join_error) => WorkerMessage::ChunkFailed {
+            chunk_id: chunk.id,
+            reason: format!("chunk scan panicked: {}", join_error),
+        },
+    };
+    if let WorkerMessage::ChunkFailed { reason, .. } = &done {
+        tracing::warn!(chunk = chunk.id, %reason, "chunk scan failed");
+    }
+
+    work_protocol::send_message(writer, &done).await
+}